@@ -1,8 +1,1509 @@
 
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::net::IpAddr;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+use log::{Level, LevelFilter};
+use serde::{de, Deserializer};
 use serde_derive::Deserialize;
+use serde_json::error::Category;
+
+use crate::error::IWError;
+
+// Schema tag of the original FP2 record with ten fields
+pub const DEFAULT_SCHEMA_VERSION: &str = "fp2-10field";
+
+// Campbell loggers count the seconds since this date
+pub const DEFAULT_EPOCH_BASE: &str = "1990-01-01 00:00:00";
+
+// Written by --init. JSON has no comments, so they are kept in "_comment" keys, which are ignored when reading.
+pub const DEFAULT_CONFIG: &str = r#"{
+    "_comment": "Configuration of iridium_weatherstation, all keys except ports and alive_message_intervall are optional",
+
+    "_comment_ports": "TCP ports the Iridium gateway connects to, the port decides the station name",
+    "ports": [2100, 2101, 2102, 2103],
+
+    "_comment_alive_message_intervall": "Seconds between two alive messages in the log file",
+    "alive_message_intervall": 3600,
+
+    "_comment_log_level": "One of: error, warn, info, debug, trace",
+    "log_level": "info",
+
+    "_comment_bind_address": "Address of the listeners, 0.0.0.0 accepts connections on all interfaces",
+    "bind_address": "0.0.0.0",
+
+    "_comment_read_timeout_secs": "A connection that sends nothing for this many seconds is closed",
+    "read_timeout_secs": 60,
+
+    "_comment_stations": "Per port options, ports without an entry use the defaults",
+    "stations": {
+        "2100": {
+            "_comment": "Nahuelbuta, replace with the settings of your station",
+            "enabled": true,
+            "timezone": null,
+            "sbs_header_length": 48,
+            "checksum": "none"
+        }
+    },
+
+    "_comment_database": "MySQL database, nothing is stored without hostname and db_name",
+    "hostname": null,
+    "db_name": null,
+    "username": null,
+    "password": null,
+
+    "_comment_battery": "A logger status below these voltages is logged as low battery",
+    "solar_battery_min": 11.5,
+    "lithium_battery_min": 3.0,
+
+    "_comment_test_mode": "Process the data but write nothing to disk or the database",
+    "test_mode": false
+}
+"#;
+
+// Fails if the file already exists, so an existing configuration is never overwritten
+pub fn write_default_config(path: &str) -> Result<(), IWError> {
+    let mut file = File::options().create_new(true).write(true).open(path)?;
+    file.write_all(DEFAULT_CONFIG.as_bytes())?;
+
+    Ok(())
+}
+
+pub fn load_config<P: AsRef<Path>>(path: P) -> Result<IWConfiguration, IWError> {
+    let path = path.as_ref();
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Err(IWError::ConfigNotFound(path.display().to_string())),
+        Err(e) => return Err(IWError::IO(e)),
+    };
+
+    if path.extension().is_some_and(|extension| extension == "toml") {
+        parse_config_toml(&text)
+    } else {
+        parse_config(&text)
+    }
+}
+
+pub fn parse_config(text: &str) -> Result<IWConfiguration, IWError> {
+    serde_json::from_str(text).map_err(config_error)
+}
+
+pub fn parse_config_toml(text: &str) -> Result<IWConfiguration, IWError> {
+    // Parse first, so syntax errors can be told apart from missing or wrong fields
+    let table: toml::Table = text.parse().map_err(|e| toml_syntax_error(e, text))?;
+
+    toml::Value::Table(table).try_into().map_err(|e: toml::de::Error| {
+        let message = e.message().to_string();
+
+        if message.starts_with("missing field") {
+            let field = message.split('`').nth(1).unwrap_or_default();
+            IWError::ConfigMissingField(field.to_string())
+        } else {
+            IWError::InvalidConfig(message)
+        }
+    })
+}
+
+fn toml_syntax_error(e: toml::de::Error, text: &str) -> IWError {
+    // TOML reports a byte range, line and column start at 1 like in serde_json
+    let start = e.span().map_or(0, |span| span.start);
+    let before = &text[..start];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+
+    IWError::ConfigSyntax { line, column, message: e.message().trim().to_string() }
+}
+
+fn config_error(e: serde_json::Error) -> IWError {
+    let message = e.to_string();
+
+    match e.classify() {
+        Category::Syntax | Category::Eof => {
+            // The position is reported separately, remove it from the message
+            let message = message.rsplit_once(" at line ").map_or(message.as_str(), |(message, _)| message).to_string();
+            IWError::ConfigSyntax { line: e.line(), column: e.column(), message }
+        }
+        Category::Data if message.starts_with("missing field") => {
+            let field = message.split('`').nth(1).unwrap_or_default();
+            IWError::ConfigMissingField(field.to_string())
+        }
+        _ => IWError::InvalidConfig(message),
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IWPrecipMode {
+    #[default]
+    Interval,
+    Cumulative,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IWNanPolicy {
+    #[default]
+    Keep,
+    Null,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IWFileDedup {
+    #[default]
+    Off,
+    First,
+    Last,
+}
+
+// What happens to new data when the database buffer is full
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IWSpoolOverflow {
+    #[default]
+    DropOldest,
+    Stop,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IWSchema {
+    Status1,
+    Status2,
+    Status3,
+    Weather,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IWEndian {
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IWMetricsBackend {
+    #[default]
+    Prometheus,
+    Statsd,
+}
+
+// How often the dated files (binary dump, JSON) start a new file
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IWFileGranularity {
+    #[default]
+    Day,
+    Hour,
+}
+
+impl IWFileGranularity {
+    pub fn date_format(self) -> &'static str {
+        match self {
+            IWFileGranularity::Day => "%Y_%m_%d",
+            IWFileGranularity::Hour => "%Y_%m_%d_%H",
+        }
+    }
+}
+
+// Kept out of the debug output, the configuration is logged at startup
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct IWPassword(pub String);
+
+impl fmt::Debug for IWPassword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"***\"")
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IWTableLayout {
+    #[default]
+    Wide,
+    Long,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IWSinkConfig {
+    Csv {
+        #[serde(default = "default_enabled")]
+        enabled: bool,
+    },
+    Binary {
+        #[serde(default = "default_enabled")]
+        enabled: bool,
+        #[serde(default = "default_binary_dir")]
+        dir: String,
+    },
+    WebSocket {
+        #[serde(default = "default_enabled")]
+        enabled: bool,
+    },
+    Json {
+        #[serde(default = "default_enabled")]
+        enabled: bool,
+        #[serde(default = "default_json_dir")]
+        dir: String,
+    },
+    Mysql {
+        #[serde(default = "default_enabled")]
+        enabled: bool,
+        hostname: String,
+        db_name: String,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<IWPassword>,
+    },
+    Mqtt {
+        #[serde(default = "default_enabled")]
+        enabled: bool,
+        host: String,
+        #[serde(default = "default_mqtt_port")]
+        port: u16,
+        #[serde(default = "default_mqtt_topic_prefix")]
+        topic_prefix: String,
+    },
+}
+
+impl IWSinkConfig {
+    pub fn enabled(&self) -> bool {
+        match self {
+            IWSinkConfig::Csv { enabled } => *enabled,
+            IWSinkConfig::Binary { enabled, .. } => *enabled,
+            IWSinkConfig::WebSocket { enabled } => *enabled,
+            IWSinkConfig::Json { enabled, .. } => *enabled,
+            IWSinkConfig::Mysql { enabled, .. } => *enabled,
+            IWSinkConfig::Mqtt { enabled, .. } => *enabled,
+        }
+    }
+}
+
+// Trailing two bytes of the frame, not every firmware appends them
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IWChecksum {
+    #[default]
+    None,
+    // PakBus signature nullifier
+    Signature,
+    // CRC-16/CCITT-FALSE, big endian
+    Crc16,
+}
+
+impl IWChecksum {
+    // Number of checksum bytes after the data
+    pub fn byte_len(self) -> usize {
+        match self {
+            IWChecksum::None => 0,
+            IWChecksum::Signature | IWChecksum::Crc16 => 2,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IWIdentityEncoding {
+    #[default]
+    Ascii,
+    Hex,
+    Integer,
+}
 
-#[derive(Deserialize, Debug)]
+// Where the station identity is in the packet, the offset counts from the start of the SBS header
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct IWIdentityField {
+    pub offset: usize,
+    pub length: usize,
+    #[serde(default)]
+    pub encoding: IWIdentityEncoding,
+}
+
+// A station that is polled by the server instead of connecting in
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct IWPollTarget {
+    pub address: String,
+    pub station: String,
+    // Selects the output folder and the station configuration, like the port of a listener
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default = "default_poll_interval_secs")]
+    pub interval_secs: u64,
+    // Hex string sent to the logger after connecting
+    #[serde(default)]
+    pub request: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct IWStationConfiguration {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    // Name of a profile in the pipelines section, all other options of the station are taken from there
+    #[serde(default)]
+    pub pipeline: Option<String>,
+    #[serde(default)]
+    pub precip_mode: IWPrecipMode,
+    #[serde(default)]
+    pub payload_station_id: bool,
+    #[serde(default, deserialize_with = "u16_keys")]
+    pub id_to_station: HashMap<u16, String>,
+    #[serde(default)]
+    pub max_skew_reject_secs: Option<i64>,
+    #[serde(default)]
+    pub streaming_connection: bool,
+    // A streaming connection that sends nothing for this many seconds is closed, zero disables the timeout
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+    #[serde(default)]
+    pub ieee4_fields: Vec<String>,
+    #[serde(default)]
+    pub ieee4_endian: IWEndian,
+    #[serde(default = "default_min_records")]
+    pub min_records: usize,
+    #[serde(default = "default_schema_version")]
+    pub schema_version: String,
+    #[serde(default)]
+    pub schema: Option<IWSchema>,
+    #[serde(default)]
+    pub schema_autodetect: bool,
+    #[serde(default = "default_normalize_360_to_0")]
+    pub normalize_360_to_0: bool,
+    #[serde(default)]
+    pub require_monotonic: bool,
+    #[serde(default)]
+    pub monotonic_tolerance_secs: i64,
+    #[serde(default)]
+    pub precip_mm_per_tip: Option<f64>,
+    #[serde(default)]
+    pub store_raw: bool,
+    #[serde(default)]
+    pub checksum: IWChecksum,
+    // Other Iridium gateways wrap the payload with a header of a different size
+    #[serde(default = "default_sbs_header_length")]
+    pub sbs_header_length: usize,
+    // IANA name of the logger's time zone, the time stamps are converted to UTC
+    #[serde(default)]
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub file_granularity: IWFileGranularity,
+    // Overrides expected_interval_secs of the main configuration
+    #[serde(default)]
+    pub expected_interval_secs: Option<i64>,
+    // Records further apart than the expected interval times this factor are logged as a gap
+    #[serde(default = "default_gap_tolerance")]
+    pub gap_tolerance: f64,
+}
+
+impl Default for IWStationConfiguration {
+    fn default() -> Self {
+        IWStationConfiguration {
+            enabled: default_enabled(),
+            pipeline: None,
+            precip_mode: IWPrecipMode::default(),
+            payload_station_id: false,
+            id_to_station: HashMap::new(),
+            max_skew_reject_secs: None,
+            streaming_connection: false,
+            idle_timeout_secs: default_idle_timeout_secs(),
+            ieee4_fields: Vec::new(),
+            ieee4_endian: IWEndian::default(),
+            min_records: default_min_records(),
+            schema_version: default_schema_version(),
+            schema: None,
+            schema_autodetect: false,
+            normalize_360_to_0: default_normalize_360_to_0(),
+            require_monotonic: false,
+            monotonic_tolerance_secs: 0,
+            precip_mm_per_tip: None,
+            store_raw: false,
+            checksum: IWChecksum::default(),
+            sbs_header_length: default_sbs_header_length(),
+            timezone: None,
+            file_granularity: IWFileGranularity::default(),
+            expected_interval_secs: None,
+            gap_tolerance: default_gap_tolerance(),
+        }
+    }
+}
+
+impl IWStationConfiguration {
+    pub fn max_gap_secs(&self, expected_interval_secs: i64) -> i64 {
+        (self.expected_interval_secs.unwrap_or(expected_interval_secs) as f64 * self.gap_tolerance) as i64
+    }
+
+    pub fn tz(&self) -> Result<Option<Tz>, IWError> {
+        match self.timezone {
+            Some(ref name) => name.parse().map(Some)
+                .map_err(|_| IWError::InvalidConfig(format!("unknown time zone '{}'", name))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct IWConfiguration {
     pub ports: Vec<u16>,
     pub alive_message_intervall: u64,
+    #[serde(default, deserialize_with = "u16_keys")]
+    pub stations: HashMap<u16, IWStationConfiguration>,
+    #[serde(default)]
+    pub pipelines: HashMap<String, IWStationConfiguration>,
+    #[serde(default)]
+    pub poll_targets: Vec<IWPollTarget>,
+    #[serde(default)]
+    pub identity_field: Option<IWIdentityField>,
+    #[serde(default)]
+    pub identity_to_station: HashMap<String, String>,
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    // Verbosity of the routine lines logged for every connection, warnings and errors are always logged
+    #[serde(default = "default_log_level")]
+    pub accept_log_level: String,
+    #[serde(default)]
+    pub max_log_size_mb: Option<u64>,
+    #[serde(default = "default_max_log_files")]
+    pub max_log_files: usize,
+    #[serde(default = "default_accept_backlog")]
+    pub accept_backlog: i32,
+    #[serde(default = "default_bind_address")]
+    pub bind_address: String,
+    #[serde(default)]
+    pub bind_ipv6: bool,
+    #[serde(default)]
+    pub listener_threads: Option<usize>,
+    #[serde(default)]
+    pub startup_delay_secs: u64,
+    #[serde(default = "default_read_timeout_secs")]
+    pub read_timeout_secs: u64,
+    // Time the running connections get to finish on shutdown before they are closed
+    #[serde(default = "default_shutdown_drain_secs")]
+    pub shutdown_drain_secs: u64,
+    // Caps the memory a single connection can use, null disables the limit
+    #[serde(default = "default_max_packet_bytes", alias = "max_payload_bytes")]
+    pub max_packet_bytes: Option<usize>,
+    #[serde(default)]
+    pub strict_port_check: bool,
+    #[serde(default)]
+    pub connection_rate_limit: Option<f64>,
+    #[serde(default = "default_connection_burst")]
+    pub connection_burst: u32,
+    #[serde(default)]
+    pub health_port: Option<u16>,
+    #[serde(default)]
+    pub metrics_backend: IWMetricsBackend,
+    #[serde(default)]
+    pub statsd_addr: Option<String>,
+    // Prometheus metrics with the ingest counters are served on this port
+    #[serde(default)]
+    pub metrics_port: Option<u16>,
+    // Decoded records are published to this broker, nothing is sent without a host
+    // Older form of the mqtt sink, used when the sinks list has none
+    #[serde(default)]
+    pub mqtt_host: Option<String>,
+    #[serde(default = "default_mqtt_port")]
+    pub mqtt_port: u16,
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub mqtt_topic_prefix: String,
+    #[serde(default = "default_statsd_interval_secs")]
+    pub statsd_interval_secs: u64,
+    #[serde(default)]
+    pub metrics_label_ttl_secs: Option<u64>,
+    #[serde(default)]
+    pub websocket_enabled: bool,
+    // Used without a sinks list, the files go into the default JSON directory
+    #[serde(default = "default_enabled")]
+    pub json_enabled: bool,
+    #[serde(default)]
+    pub sinks: Vec<IWSinkConfig>,
+    #[serde(default = "default_binary_dir")]
+    pub binary_dir: String,
+    #[serde(default = "default_csv_delimiter")]
+    pub csv_delimiter: char,
+    #[serde(default = "default_decimal_separator")]
+    pub decimal_separator: char,
+    // Decimal places of every float in the CSV files, three keep all FP2 values exact
+    #[serde(default = "default_csv_decimals")]
+    pub csv_decimals: usize,
+    #[serde(default)]
+    pub nan_policy: IWNanPolicy,
+    #[serde(default)]
+    pub csv_null_token: String,
+    #[serde(default)]
+    pub file_dedup: IWFileDedup,
+    #[serde(default)]
+    pub annotate_schema_version: bool,
+    // Extra CSV column with the MOMSN from the SBS header
+    #[serde(default)]
+    pub annotate_momsn: bool,
+    #[serde(default = "default_epoch_base_str")]
+    pub epoch_base: String,
+    #[serde(default)]
+    pub test_mode: bool,
+    #[serde(default)]
+    pub safe_mode: bool,
+    #[serde(default = "default_failed_dir")]
+    pub failed_dir: String,
+    // Each sink retries its own write, so a sink that already has the data is not written twice.
+    // Parsing is not retried, it fails the same way every time.
+    #[serde(default = "default_io_retry_attempts")]
+    pub io_retry_attempts: u32,
+    #[serde(default = "default_io_retry_delay_ms")]
+    pub io_retry_delay_ms: u64,
+    #[serde(default)]
+    pub wal_dir: Option<String>,
+    #[serde(default)]
+    pub wal_fsync: bool,
+    // Older form of the mysql sink, used when the sinks list has none
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub db_name: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<IWPassword>,
+    #[serde(default)]
+    pub table_layout: IWTableLayout,
+    #[serde(default)]
+    pub verify_after_store: bool,
+    #[serde(default)]
+    pub skip_duplicates: bool,
+    // Records per INSERT statement, a large backlog in one statement exceeds max_allowed_packet
+    #[serde(default = "default_db_batch_size")]
+    pub db_batch_size: usize,
+    // Reconnect attempts after the database connection was lost, the delay doubles after every attempt
+    #[serde(default = "default_db_retry_attempts")]
+    pub db_retry_attempts: u32,
+    #[serde(default = "default_db_retry_delay_ms")]
+    pub db_retry_delay_ms: u64,
+    #[serde(default = "default_db_retry_max_delay_ms")]
+    pub db_retry_max_delay_ms: u64,
+    // Data that could not be stored after all attempts is kept here, one file per packet,
+    // and written to the database after the next successful insert
+    #[serde(default = "default_db_buffer_dir")]
+    pub db_buffer_dir: String,
+    // Limits of the database buffer, the data that is shed is still in the CSV files
+    #[serde(default)]
+    pub max_spool_files: Option<usize>,
+    #[serde(default)]
+    pub max_spool_bytes: Option<u64>,
+    #[serde(default)]
+    pub spool_overflow: IWSpoolOverflow,
+    // Last cumulative precipitation total per station. Without this file the first
+    // cumulative record after a restart has no reference and is stored as NaN.
+    #[serde(default = "default_precip_state_file")]
+    pub precip_state_file: String,
+    #[serde(default = "default_solar_battery_min")]
+    pub solar_battery_min: f64,
+    #[serde(default = "default_lithium_battery_min")]
+    pub lithium_battery_min: f64,
+    #[serde(default = "default_solar_battery_range")]
+    pub solar_battery_range: (f64, f64),
+    #[serde(default = "default_lithium_battery_range")]
+    pub lithium_battery_range: (f64, f64),
+    // Logger status fields where exactly zero means a disconnected sensor or a decoding error
+    #[serde(default)]
+    pub reject_zero: Vec<String>,
+    #[serde(default)]
+    pub weather_ranges: HashMap<String, (f64, f64)>,
+    #[serde(default = "default_max_skew_warn_secs")]
+    pub max_skew_warn_secs: i64,
+    #[serde(default = "default_expected_interval_secs")]
+    pub expected_interval_secs: i64,
+    #[serde(default = "default_last_seen_warn_secs")]
+    pub last_seen_warn_secs: i64,
+    #[serde(default = "default_last_seen_critical_secs")]
+    pub last_seen_critical_secs: i64,
+    // A field with this many identical values in a row is reported as a stuck sensor, zero disables the check
+    #[serde(default = "default_stuck_sensor_records")]
+    pub stuck_sensor_records: u32,
+    // Rain, wind and radiation are often zero for hours, so they are not checked by default
+    #[serde(default = "default_stuck_sensor_fields")]
+    pub stuck_sensor_fields: Vec<String>,
+}
+
+// Map keys are always strings in TOML, so the numeric keys are parsed here for both formats
+fn u16_keys<'de, D, V>(deserializer: D) -> Result<HashMap<u16, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: serde::Deserialize<'de>,
+{
+    let map: HashMap<String, V> = serde::Deserialize::deserialize(deserializer)?;
+
+    map.into_iter().map(|(key, value)| {
+        key.parse().map(|key| (key, value)).map_err(|_| de::Error::custom(format!("invalid key '{}', expected u16", key)))
+    }).collect()
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_poll_interval_secs() -> u64 {
+    3600
+}
+
+fn default_gap_tolerance() -> f64 {
+    1.0
+}
+
+fn default_min_records() -> usize {
+    1
+}
+
+fn default_sbs_header_length() -> usize {
+    48
+}
+
+fn default_schema_version() -> String {
+    DEFAULT_SCHEMA_VERSION.to_string()
+}
+
+fn default_normalize_360_to_0() -> bool {
+    true
+}
+
+fn default_epoch_base_str() -> String {
+    DEFAULT_EPOCH_BASE.to_string()
+}
+
+pub fn default_epoch_base() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(1990, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+}
+
+fn parse_level_filter(name: &str) -> Option<LevelFilter> {
+    match name.to_lowercase().as_str() {
+        "error" => Some(LevelFilter::Error),
+        "warn" => Some(LevelFilter::Warn),
+        "info" => Some(LevelFilter::Info),
+        "debug" => Some(LevelFilter::Debug),
+        "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_max_log_files() -> usize {
+    5
+}
+
+fn default_accept_backlog() -> i32 {
+    128
+}
+
+fn default_idle_timeout_secs() -> u64 {
+    3600
+}
+
+fn default_read_timeout_secs() -> u64 {
+    60
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "iridium".to_string()
+}
+
+fn default_shutdown_drain_secs() -> u64 {
+    30
+}
+
+fn default_max_packet_bytes() -> Option<usize> {
+    Some(4 * 1024 * 1024)
+}
+
+fn default_bind_address() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_binary_dir() -> String {
+    "old/binary".to_string()
+}
+
+fn default_csv_delimiter() -> char {
+    ','
+}
+
+fn default_decimal_separator() -> char {
+    '.'
+}
+
+fn default_csv_decimals() -> usize {
+    3
+}
+
+fn default_connection_burst() -> u32 {
+    10
+}
+
+fn default_statsd_interval_secs() -> u64 {
+    10
+}
+
+fn default_json_dir() -> String {
+    "json".to_string()
+}
+
+fn default_failed_dir() -> String {
+    "old/failed".to_string()
+}
+
+fn default_io_retry_attempts() -> u32 {
+    3
+}
+
+fn default_io_retry_delay_ms() -> u64 {
+    500
+}
+
+fn default_db_batch_size() -> usize {
+    500
+}
+
+fn default_db_retry_attempts() -> u32 {
+    3
+}
+
+fn default_db_retry_delay_ms() -> u64 {
+    1000
+}
+
+fn default_db_retry_max_delay_ms() -> u64 {
+    30000
+}
+
+fn default_db_buffer_dir() -> String {
+    "old/db_buffer".to_string()
+}
+
+fn default_precip_state_file() -> String {
+    "old/precipitation_totals.json".to_string()
+}
+
+fn default_solar_battery_min() -> f64 {
+    11.5
+}
+
+fn default_lithium_battery_min() -> f64 {
+    3.0
+}
+
+fn default_solar_battery_range() -> (f64, f64) {
+    (9.0, 16.0)
+}
+
+fn default_lithium_battery_range() -> (f64, f64) {
+    (2.0, 4.0)
+}
+
+fn default_max_skew_warn_secs() -> i64 {
+    3600
+}
+
+fn default_expected_interval_secs() -> i64 {
+    3600
+}
+
+fn default_last_seen_warn_secs() -> i64 {
+    // Two missed hourly transmissions
+    2 * 3600
+}
+
+fn default_last_seen_critical_secs() -> i64 {
+    24 * 3600
+}
+
+fn default_stuck_sensor_records() -> u32 {
+    // Six hours of hourly records
+    6
+}
+
+fn default_stuck_sensor_fields() -> Vec<String> {
+    ["air_temperature", "air_relative_humidity", "soil_temperature", "air_pressure"].iter().map(|field| field.to_string()).collect()
+}
+
+impl Default for IWConfiguration {
+    fn default() -> Self {
+        IWConfiguration {
+            ports: Vec::new(),
+            alive_message_intervall: 3600,
+            stations: HashMap::new(),
+            pipelines: HashMap::new(),
+            poll_targets: Vec::new(),
+            identity_field: None,
+            identity_to_station: HashMap::new(),
+            log_level: default_log_level(),
+            accept_log_level: default_log_level(),
+            max_log_size_mb: None,
+            max_log_files: default_max_log_files(),
+            accept_backlog: default_accept_backlog(),
+            bind_address: default_bind_address(),
+            bind_ipv6: false,
+            listener_threads: None,
+            startup_delay_secs: 0,
+            read_timeout_secs: default_read_timeout_secs(),
+            shutdown_drain_secs: default_shutdown_drain_secs(),
+            max_packet_bytes: default_max_packet_bytes(),
+            strict_port_check: false,
+            connection_rate_limit: None,
+            connection_burst: default_connection_burst(),
+            health_port: None,
+            metrics_backend: IWMetricsBackend::default(),
+            metrics_port: None,
+            statsd_addr: None,
+            mqtt_host: None,
+            mqtt_port: default_mqtt_port(),
+            mqtt_topic_prefix: default_mqtt_topic_prefix(),
+            statsd_interval_secs: default_statsd_interval_secs(),
+            metrics_label_ttl_secs: None,
+            websocket_enabled: false,
+            json_enabled: default_enabled(),
+            sinks: Vec::new(),
+            binary_dir: default_binary_dir(),
+            csv_delimiter: default_csv_delimiter(),
+            decimal_separator: default_decimal_separator(),
+            csv_decimals: default_csv_decimals(),
+            nan_policy: IWNanPolicy::default(),
+            csv_null_token: String::new(),
+            file_dedup: IWFileDedup::default(),
+            annotate_schema_version: false,
+            annotate_momsn: false,
+            epoch_base: default_epoch_base_str(),
+            test_mode: false,
+            safe_mode: false,
+            failed_dir: default_failed_dir(),
+            io_retry_attempts: default_io_retry_attempts(),
+            io_retry_delay_ms: default_io_retry_delay_ms(),
+            wal_dir: None,
+            wal_fsync: false,
+            hostname: None,
+            db_name: None,
+            username: None,
+            password: None,
+            table_layout: IWTableLayout::default(),
+            verify_after_store: false,
+            skip_duplicates: false,
+            db_batch_size: default_db_batch_size(),
+            db_retry_attempts: default_db_retry_attempts(),
+            db_retry_delay_ms: default_db_retry_delay_ms(),
+            db_retry_max_delay_ms: default_db_retry_max_delay_ms(),
+            db_buffer_dir: default_db_buffer_dir(),
+            max_spool_files: None,
+            max_spool_bytes: None,
+            spool_overflow: IWSpoolOverflow::default(),
+            precip_state_file: default_precip_state_file(),
+            solar_battery_min: default_solar_battery_min(),
+            lithium_battery_min: default_lithium_battery_min(),
+            solar_battery_range: default_solar_battery_range(),
+            lithium_battery_range: default_lithium_battery_range(),
+            reject_zero: Vec::new(),
+            weather_ranges: HashMap::new(),
+            max_skew_warn_secs: default_max_skew_warn_secs(),
+            expected_interval_secs: default_expected_interval_secs(),
+            last_seen_warn_secs: default_last_seen_warn_secs(),
+            last_seen_critical_secs: default_last_seen_critical_secs(),
+            stuck_sensor_records: default_stuck_sensor_records(),
+            stuck_sensor_fields: default_stuck_sensor_fields(),
+        }
+    }
+}
+
+impl IWConfiguration {
+    // Safe mode decodes and logs everything like test mode, but nothing is written to disk or the database
+    pub fn writes_disabled(&self) -> bool {
+        self.test_mode || self.safe_mode
+    }
+
+    pub fn station(&self, port: u16) -> IWStationConfiguration {
+        let station = self.stations.get(&port).cloned().unwrap_or_default();
+
+        match station.pipeline.as_ref().and_then(|name| self.pipelines.get(name)) {
+            Some(pipeline) => IWStationConfiguration {
+                enabled: station.enabled,
+                pipeline: station.pipeline.clone(),
+                ..pipeline.clone()
+            },
+            None => station,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), IWError> {
+        if self.decimal_separator == self.csv_delimiter {
+            return Err(IWError::InvalidConfig(format!("decimal separator '{}' is the same as the CSV delimiter", self.decimal_separator)))
+        }
+
+        self.epoch_base()?;
+        self.bind_ip()?;
+
+        if self.metrics_backend == IWMetricsBackend::Statsd && self.statsd_addr.is_none() {
+            return Err(IWError::InvalidConfig("metrics backend 'statsd' needs a statsd_addr".to_string()))
+        }
+
+        if self.db_batch_size == 0 {
+            return Err(IWError::InvalidConfig("db_batch_size must be at least 1".to_string()))
+        }
+
+        if self.metrics_backend == IWMetricsBackend::Statsd && self.metrics_port.is_some() {
+            return Err(IWError::InvalidConfig("metrics_port needs the metrics backend 'prometheus'".to_string()))
+        }
+
+        for (port, station) in self.stations.iter() {
+            if let Some(name) = &station.pipeline {
+                if !self.pipelines.contains_key(name) {
+                    return Err(IWError::InvalidConfig(format!("unknown pipeline '{}' for port '{}'", name, port)))
+                }
+            }
+        }
+
+        for station in self.stations.values().chain(self.pipelines.values()) {
+            station.tz()?;
+        }
+
+        if let Some(field) = &self.identity_field {
+            if field.length == 0 || (field.encoding == IWIdentityEncoding::Integer && field.length > 8) {
+                return Err(IWError::InvalidConfig(format!("invalid identity field length '{}'", field.length)))
+            }
+        }
+
+        Ok(())
+    }
+
+    // Plausible range of a weather field, the configured ranges override the physical limits
+    pub fn weather_range(&self, field: &str) -> Option<(f64, f64)> {
+        if let Some(range) = self.weather_ranges.get(field) {
+            return Some(*range)
+        }
+
+        match field {
+            "air_relative_humidity" => Some((0.0, 100.0)),
+            "wind_direction" => Some((0.0, 360.0)),
+            "solar_radiation" | "wind_speed" | "wind_max" | "precipitation" => Some((0.0, f64::MAX)),
+            _ => None,
+        }
+    }
+
+    // None for unknown level names
+    pub fn log_level_filter(&self) -> Option<LevelFilter> {
+        parse_level_filter(&self.log_level)
+    }
+
+    // Level of the routine connection lines, errors are always logged as errors
+    pub fn accept_level(&self) -> Option<Level> {
+        parse_level_filter(&self.accept_log_level)?.to_level()
+    }
+
+    pub fn bind_ip(&self) -> Result<IpAddr, IWError> {
+        self.bind_address.parse()
+            .map_err(|e| IWError::InvalidConfig(format!("bind address '{}': {}", self.bind_address, e)))
+    }
+
+    pub fn epoch_base(&self) -> Result<NaiveDateTime, IWError> {
+        NaiveDateTime::parse_from_str(&self.epoch_base, "%Y-%m-%d %H:%M:%S")
+            .map_err(|e| IWError::InvalidConfig(format!("epoch base '{}': {}", self.epoch_base, e)))
+    }
+
+    // Without a sinks section the old flat flags are used
+    pub fn output_sinks(&self) -> Vec<IWSinkConfig> {
+        let mut sinks = if self.sinks.is_empty() {
+            vec![
+                IWSinkConfig::Binary { enabled: true, dir: self.binary_dir.clone() },
+                IWSinkConfig::Csv { enabled: true },
+                IWSinkConfig::WebSocket { enabled: self.websocket_enabled },
+                IWSinkConfig::Json { enabled: self.json_enabled, dir: default_json_dir() },
+            ]
+        } else {
+            self.sinks.clone()
+        };
+
+        // The flat database and MQTT settings of older configurations, only used without such a sink in the list
+        if let (Some(hostname), Some(db_name)) = (&self.hostname, &self.db_name) {
+            if !sinks.iter().any(|sink| matches!(sink, IWSinkConfig::Mysql { .. })) {
+                sinks.push(IWSinkConfig::Mysql {
+                    enabled: true,
+                    hostname: hostname.clone(),
+                    db_name: db_name.clone(),
+                    username: self.username.clone(),
+                    password: self.password.clone(),
+                });
+            }
+        }
+
+        if let Some(ref host) = self.mqtt_host {
+            if !sinks.iter().any(|sink| matches!(sink, IWSinkConfig::Mqtt { .. })) {
+                sinks.push(IWSinkConfig::Mqtt {
+                    enabled: true,
+                    host: host.clone(),
+                    port: self.mqtt_port,
+                    topic_prefix: self.mqtt_topic_prefix.clone(),
+                });
+            }
+        }
+
+        sinks.into_iter().filter(|sink| sink.enabled()).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use log::{Level, LevelFilter};
+
+    use super::{IWConfiguration, IWPassword, IWSinkConfig, IWSpoolOverflow, IWMetricsBackend, DEFAULT_CONFIG, write_default_config, load_config, parse_config, parse_config_toml, default_epoch_base};
+
+    use crate::error::IWError;
+
+    #[test]
+    fn test_default_config() {
+        let config: IWConfiguration = serde_json::from_str(DEFAULT_CONFIG).unwrap();
+
+        assert!(config.validate().is_ok());
+        assert_eq!(config.ports, vec![2100, 2101, 2102, 2103]);
+        assert_eq!(config.alive_message_intervall, 3600);
+        assert!(config.station(2100).enabled);
+        assert_eq!(config.solar_battery_min, IWConfiguration::default().solar_battery_min);
+        assert!(config.output_sinks().contains(&IWSinkConfig::Json { enabled: true, dir: "json".to_string() }));
+
+        let path = std::env::temp_dir().join(format!("iw_test_default_config_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        write_default_config(path).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), DEFAULT_CONFIG);
+
+        // An existing file is not overwritten
+        let result = write_default_config(path);
+        match result {
+            Err(IWError::IO(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError::IO, got: '{:?}'", result);
+            }
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_config() {
+        let config = parse_config(r#"{"ports": [2100], "alive_message_intervall": 60}"#).unwrap();
+        assert_eq!(config.ports, vec![2100]);
+
+        let result = load_config("does_not_exist/iridium_weatherstation_config.json");
+        match result {
+            Err(IWError::ConfigNotFound(ref path)) => {
+                assert_eq!(path, "does_not_exist/iridium_weatherstation_config.json");
+            }
+            _ => {
+                panic!("Expected IWError::ConfigNotFound, got: '{:?}'", result);
+            }
+        }
+
+        // Missing comma after the ports
+        let result = parse_config("{\n    \"ports\": [2100]\n    \"alive_message_intervall\": 60\n}");
+        match result {
+            Err(IWError::ConfigSyntax { line: 3, .. }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError::ConfigSyntax, got: '{:?}'", result);
+            }
+        }
+
+        // Truncated file
+        let result = parse_config(r#"{"ports": [2100], "#);
+        match result {
+            Err(IWError::ConfigSyntax { line: 1, .. }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError::ConfigSyntax, got: '{:?}'", result);
+            }
+        }
+
+        let result = parse_config(r#"{"ports": [2100]}"#);
+        match result {
+            Err(IWError::ConfigMissingField(ref field)) => {
+                assert_eq!(field, "alive_message_intervall");
+            }
+            _ => {
+                panic!("Expected IWError::ConfigMissingField, got: '{:?}'", result);
+            }
+        }
+
+        let result = parse_config(r#"{"ports": "2100", "alive_message_intervall": 60}"#);
+        match result {
+            Err(IWError::InvalidConfig(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError::InvalidConfig, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_toml_config() {
+        let json = parse_config(r#"{
+            "ports": [2100, 2101],
+            "alive_message_intervall": 600,
+            "log_level": "debug",
+            "max_packet_bytes": 65536,
+            "solar_battery_range": [10.0, 15.0],
+            "stations": {
+                "2100": {"timezone": "America/Santiago", "checksum": "crc16", "id_to_station": {"7": "Nahuelbuta"}}
+            },
+            "sinks": [
+                {"type": "csv"},
+                {"type": "binary", "dir": "/tmp/binary"}
+            ]
+        }"#).unwrap();
+
+        let toml = parse_config_toml(r#"
+            ports = [2100, 2101]
+            alive_message_intervall = 600
+            log_level = "debug"
+            max_packet_bytes = 65536
+            solar_battery_range = [10.0, 15.0]
+
+            [stations.2100]
+            timezone = "America/Santiago"
+            checksum = "crc16"
+            id_to_station = { 7 = "Nahuelbuta" }
+
+            [[sinks]]
+            type = "csv"
+
+            [[sinks]]
+            type = "binary"
+            dir = "/tmp/binary"
+        "#).unwrap();
+
+        assert_eq!(format!("{:?}", json), format!("{:?}", toml));
+
+        let result = parse_config_toml("ports = [2100]\nalive_message_intervall = = 600\n");
+        match result {
+            Err(IWError::ConfigSyntax { line: 2, .. }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError::ConfigSyntax, got: '{:?}'", result);
+            }
+        }
+
+        let result = parse_config_toml("ports = [2100]\n");
+        match result {
+            Err(IWError::ConfigMissingField(ref field)) => {
+                assert_eq!(field, "alive_message_intervall");
+            }
+            _ => {
+                panic!("Expected IWError::ConfigMissingField, got: '{:?}'", result);
+            }
+        }
+
+        let result = parse_config_toml("ports = \"2100\"\nalive_message_intervall = 600\n");
+        match result {
+            Err(IWError::InvalidConfig(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError::InvalidConfig, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sinks1() {
+        let config: IWConfiguration = serde_json::from_str(r#"{
+            "ports": [2100],
+            "alive_message_intervall": 3600,
+            "sinks": [
+                {"type": "csv"},
+                {"type": "binary", "dir": "/tmp/binary"},
+                {"type": "websocket", "enabled": false}
+            ]
+        }"#).unwrap();
+
+        assert_eq!(config.output_sinks(), vec![
+            IWSinkConfig::Csv { enabled: true },
+            IWSinkConfig::Binary { enabled: true, dir: "/tmp/binary".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_sinks2() {
+        let config: IWConfiguration = serde_json::from_str(r#"{
+            "ports": [2100],
+            "alive_message_intervall": 3600,
+            "websocket_enabled": true
+        }"#).unwrap();
+
+        assert_eq!(config.output_sinks(), vec![
+            IWSinkConfig::Binary { enabled: true, dir: "old/binary".to_string() },
+            IWSinkConfig::Csv { enabled: true },
+            IWSinkConfig::WebSocket { enabled: true },
+            IWSinkConfig::Json { enabled: true, dir: "json".to_string() },
+        ]);
+
+        let config: IWConfiguration = serde_json::from_str(r#"{
+            "ports": [2100],
+            "alive_message_intervall": 3600,
+            "json_enabled": false
+        }"#).unwrap();
+
+        assert_eq!(config.output_sinks(), vec![
+            IWSinkConfig::Binary { enabled: true, dir: "old/binary".to_string() },
+            IWSinkConfig::Csv { enabled: true },
+        ]);
+    }
+
+    #[test]
+    fn test_spool_overflow() {
+        assert_eq!(IWConfiguration::default().spool_overflow, IWSpoolOverflow::DropOldest);
+
+        let config = parse_config(r#"{"ports": [2100], "alive_message_intervall": 3600, "max_spool_files": 1000, "spool_overflow": "stop"}"#).unwrap();
+
+        assert_eq!(config.max_spool_files, Some(1000));
+        assert_eq!(config.spool_overflow, IWSpoolOverflow::Stop);
+    }
+
+    #[test]
+    fn test_sinks_mysql_mqtt() {
+        let config: IWConfiguration = serde_json::from_str(r#"{
+            "ports": [2100],
+            "alive_message_intervall": 3600,
+            "sinks": [
+                {"type": "mysql", "hostname": "localhost", "db_name": "weatherstation", "username": "iridium", "password": "s3cr3t"},
+                {"type": "mqtt", "host": "broker.local"}
+            ]
+        }"#).unwrap();
+
+        assert_eq!(config.output_sinks(), vec![
+            IWSinkConfig::Mysql {
+                enabled: true,
+                hostname: "localhost".to_string(),
+                db_name: "weatherstation".to_string(),
+                username: Some("iridium".to_string()),
+                password: Some(IWPassword("s3cr3t".to_string())),
+            },
+            IWSinkConfig::Mqtt { enabled: true, host: "broker.local".to_string(), port: 1883, topic_prefix: "iridium".to_string() },
+        ]);
+
+        // The flat fields still work
+        let config: IWConfiguration = serde_json::from_str(r#"{
+            "ports": [2100],
+            "alive_message_intervall": 3600,
+            "sinks": [{"type": "csv"}],
+            "hostname": "localhost",
+            "db_name": "weatherstation",
+            "mqtt_host": "broker.local",
+            "mqtt_port": 8883
+        }"#).unwrap();
+
+        assert_eq!(config.output_sinks(), vec![
+            IWSinkConfig::Csv { enabled: true },
+            IWSinkConfig::Mysql { enabled: true, hostname: "localhost".to_string(), db_name: "weatherstation".to_string(), username: None, password: None },
+            IWSinkConfig::Mqtt { enabled: true, host: "broker.local".to_string(), port: 8883, topic_prefix: "iridium".to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_sinks3() {
+        let config: IWConfiguration = serde_json::from_str(r#"{
+            "ports": [2100],
+            "alive_message_intervall": 3600,
+            "binary_dir": "/data/binary"
+        }"#).unwrap();
+
+        assert_eq!(config.output_sinks()[0], IWSinkConfig::Binary { enabled: true, dir: "/data/binary".to_string() });
+    }
+
+    #[test]
+    fn test_validate1() {
+        assert!(IWConfiguration::default().validate().is_ok());
+
+        let config = IWConfiguration {
+            decimal_separator: ',',
+            ..Default::default()
+        };
+        let result = config.validate();
+
+        match result {
+            Err(IWError::InvalidConfig(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate2() {
+        let config = IWConfiguration {
+            metrics_backend: IWMetricsBackend::Statsd,
+            ..Default::default()
+        };
+        let result = config.validate();
+
+        match result {
+            Err(IWError::InvalidConfig(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+
+        let config = IWConfiguration {
+            metrics_backend: IWMetricsBackend::Statsd,
+            statsd_addr: Some("127.0.0.1:8125".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let config = IWConfiguration {
+            db_batch_size: 0,
+            ..Default::default()
+        };
+        let result = config.validate();
+
+        match result {
+            Err(IWError::InvalidConfig(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bind_ip1() {
+        assert_eq!(IWConfiguration::default().bind_ip().unwrap(), IpAddr::from([0, 0, 0, 0]));
+
+        let config = IWConfiguration {
+            bind_address: "10.8.0.1".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.bind_ip().unwrap(), IpAddr::from([10, 8, 0, 1]));
+
+        let config = IWConfiguration {
+            bind_address: "vpn0".to_string(),
+            ..Default::default()
+        };
+        let result = config.validate();
+
+        match result {
+            Err(IWError::InvalidConfig(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_log_level1() {
+        assert_eq!(IWConfiguration::default().log_level_filter(), Some(LevelFilter::Info));
+
+        let config = IWConfiguration {
+            log_level: "Debug".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.log_level_filter(), Some(LevelFilter::Debug));
+
+        let config = IWConfiguration {
+            log_level: "verbose".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.log_level_filter(), None);
+    }
+
+    #[test]
+    fn test_accept_level() {
+        assert_eq!(IWConfiguration::default().accept_level(), Some(Level::Info));
+
+        // A successful connection does not produce any info lines any more
+        let config = IWConfiguration {
+            accept_log_level: "warn".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.accept_level(), Some(Level::Warn));
+
+        let config = IWConfiguration {
+            accept_log_level: "debug".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.accept_level(), Some(Level::Debug));
+
+        let config = IWConfiguration {
+            accept_log_level: "trace".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.accept_level(), Some(Level::Trace));
+
+        let config = IWConfiguration {
+            accept_log_level: "verbose".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.accept_level(), None);
+    }
+
+    #[test]
+    fn test_password_debug() {
+        let config = parse_config(r#"{"ports": [2100], "alive_message_intervall": 3600, "username": "weather", "password": "s3cr3t"}"#).unwrap();
+
+        assert_eq!(config.password, Some(IWPassword("s3cr3t".to_string())));
+
+        let text = format!("{:?}", config);
+        assert!(!text.contains("s3cr3t"));
+        assert!(text.contains("password: Some(\"***\")"));
+    }
+
+    #[test]
+    fn test_epoch_base1() {
+        assert_eq!(IWConfiguration::default().epoch_base().unwrap(), default_epoch_base());
+
+        let config = IWConfiguration {
+            epoch_base: "1970-01-01 00:00:00".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.epoch_base().unwrap().and_utc().timestamp(), 0);
+
+        let config = IWConfiguration {
+            epoch_base: "1990-01-01".to_string(),
+            ..Default::default()
+        };
+        let result = config.validate();
+
+        match result {
+            Err(IWError::InvalidConfig(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
 }
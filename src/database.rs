@@ -0,0 +1,637 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::Duration;
+
+use log::{debug, info, warn, error};
+use mysql::{Pool, PooledConn, Opts, OptsBuilder, Row, TxOpts, Value, DriverError, params};
+use mysql::prelude::Queryable;
+
+use crate::config::{IWConfiguration, IWSinkConfig, IWTableLayout};
+use crate::error::IWError;
+use crate::process_data::{IWStationData, IWLoggerStatus, IWWeatherData, WEATHER_FIELDS};
+
+
+const BATTERY_DATA_TABLE: &str = "CREATE TABLE IF NOT EXISTS battery_data (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    timestamp DATETIME NOT NULL,
+    station VARCHAR(64) NOT NULL,
+    battery_voltage DOUBLE,
+    li_battery_voltage DOUBLE,
+    wind_diag DOUBLE,
+    cf_card INT UNSIGNED
+)";
+
+const MULTIPLE_DATA_TABLE: &str = "CREATE TABLE IF NOT EXISTS multiple_data (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    timestamp DATETIME NOT NULL,
+    station VARCHAR(64) NOT NULL,
+    air_temperature DOUBLE,
+    air_relative_humidity DOUBLE,
+    solar_radiation DOUBLE,
+    soil_water_content DOUBLE,
+    soil_temperature DOUBLE,
+    wind_speed DOUBLE,
+    wind_max DOUBLE,
+    wind_direction DOUBLE,
+    precipitation DOUBLE,
+    air_pressure DOUBLE
+)";
+
+const LONG_DATA_TABLE: &str = "CREATE TABLE IF NOT EXISTS long_data (
+    id INT NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    station VARCHAR(64) NOT NULL,
+    timestamp DATETIME NOT NULL,
+    variable VARCHAR(64) NOT NULL,
+    value DOUBLE NOT NULL,
+    INDEX (station, variable, timestamp)
+)";
+
+pub struct IWDatabase {
+    // Replaced by a new pool when the connection to the server is lost
+    pool: Mutex<Pool>,
+    opts: Opts,
+    table_layout: IWTableLayout,
+    verify_after_store: bool,
+    skip_duplicates: bool,
+    batch_size: usize,
+    retry_attempts: u32,
+    retry_delay_ms: u64,
+    retry_max_delay_ms: u64,
+}
+
+// All values are stored as DOUBLE, so anything beyond rounding noise means the column lost data
+const VERIFY_TOLERANCE: f64 = 1e-9;
+
+impl std::fmt::Debug for IWDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("IWDatabase").finish_non_exhaustive()
+    }
+}
+
+// The logger status always goes into battery_data, only the weather data has two layouts
+pub fn table_schema(table_layout: IWTableLayout) -> [&'static str; 2] {
+    match table_layout {
+        IWTableLayout::Wide => [BATTERY_DATA_TABLE, MULTIPLE_DATA_TABLE],
+        IWTableLayout::Long => [BATTERY_DATA_TABLE, LONG_DATA_TABLE],
+    }
+}
+
+// One (variable, value) pair per field, sentinel values (NAN, +/-INF) are left out
+fn long_rows(data: &IWWeatherData) -> Vec<(&'static str, f64)> {
+    WEATHER_FIELDS.iter().copied()
+        .zip(data.field_values())
+        .filter(|(_, value)| value.is_finite())
+        .collect()
+}
+
+const BATTERY_FIELDS: [&str; 4] = ["battery_voltage", "li_battery_voltage", "wind_diag", "cf_card"];
+
+fn battery_values(status: &IWLoggerStatus) -> [f64; 4] {
+    [status.solar_battery, status.lithium_battery, status.wind_diag, status.cf_card as f64]
+}
+
+// Compares the written values with the ones read back, returns one message per mismatch
+fn verify_values(expected: &[(&str, f64)], actual: &[(String, Option<f64>)]) -> Vec<String> {
+    expected.iter()
+        .filter(|(_, value)| value.is_finite())
+        .filter_map(|(field, value)| {
+            match actual.iter().find(|(name, _)| name == field) {
+                Some((_, Some(stored))) if (stored - value).abs() <= VERIFY_TOLERANCE * value.abs().max(1.0) => None,
+                Some((_, Some(stored))) => Some(format!("{}: expected {}, stored {}", field, value, stored)),
+                _ => Some(format!("{}: expected {}, not stored", field, value)),
+            }
+        })
+        .collect()
+}
+
+// Server gone away (2006), lost connection (2013), server shutdown (1053) and connection killed (1927)
+const CONNECTION_ERROR_CODES: [u16; 4] = [2006, 2013, 1053, 1927];
+
+// Errors that a new connection can fix, e.g. after a restart of the MySQL server
+fn is_connection_error(e: &mysql::Error) -> bool {
+    match e {
+        mysql::Error::IoError(_) | mysql::Error::CodecError(_) => true,
+        mysql::Error::DriverError(DriverError::ConnectTimeout | DriverError::CouldNotConnect(_) | DriverError::Timeout) => true,
+        mysql::Error::MySqlError(e) => CONNECTION_ERROR_CODES.contains(&e.code),
+        _ => false,
+    }
+}
+
+// Doubles with every attempt, starting at 1
+fn reconnect_delay_ms(attempt: u32, delay_ms: u64, max_delay_ms: u64) -> u64 {
+    delay_ms.saturating_mul(1u64.checked_shl(attempt - 1).unwrap_or(u64::MAX)).min(max_delay_ms)
+}
+
+// Without a MySQL sink only the files are written
+pub fn db_opts(config: &IWConfiguration) -> Option<OptsBuilder> {
+    config.output_sinks().into_iter().find_map(|sink| match sink {
+        IWSinkConfig::Mysql { hostname, db_name, username, password, .. } => {
+            Some(OptsBuilder::new()
+                .ip_or_hostname(Some(hostname))
+                .db_name(Some(db_name))
+                .user(username)
+                .pass(password.map(|password| password.0)))
+        }
+        _ => None,
+    })
+}
+
+impl IWDatabase {
+    pub fn new(config: &IWConfiguration) -> Result<Option<Self>, IWError> {
+        match db_opts(config) {
+            Some(opts) => {
+                let opts = Opts::from(opts);
+                let pool = Pool::new(opts.clone())?;
+                debug!("Connected to database: '{:?}'", opts.get_db_name());
+                Ok(Some(IWDatabase {
+                    pool: Mutex::new(pool),
+                    opts,
+                    table_layout: config.table_layout,
+                    verify_after_store: config.verify_after_store,
+                    skip_duplicates: config.skip_duplicates,
+                    batch_size: config.db_batch_size,
+                    retry_attempts: config.db_retry_attempts,
+                    retry_delay_ms: config.db_retry_delay_ms,
+                    retry_max_delay_ms: config.db_retry_max_delay_ms,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn pool(&self) -> Pool {
+        self.pool.lock().unwrap().clone()
+    }
+
+    fn reconnect(&self) {
+        match Pool::new(self.opts.clone()) {
+            Ok(pool) => {
+                *self.pool.lock().unwrap() = pool;
+                info!("Reconnected to database");
+            }
+            Err(e) => {
+                warn!("Could not reconnect to database: '{}'", e);
+            }
+        }
+    }
+
+    pub fn init_db(&self) -> Result<(), IWError> {
+        let mut connection = self.pool().get_conn()?;
+
+        for statement in table_schema(self.table_layout) {
+            connection.query_drop(statement)?;
+        }
+
+        Ok(())
+    }
+
+    // Retries with a new pool when the connection was lost, returns the number of duplicate records that were skipped
+    pub fn store(&self, station_name: &str, data: &IWStationData) -> Result<usize, IWError> {
+        let mut attempt = 0;
+
+        loop {
+            match self.store_once(station_name, data) {
+                Err(IWError::Database(ref e)) if is_connection_error(e) && attempt < self.retry_attempts => {
+                    attempt += 1;
+                    let delay_ms = reconnect_delay_ms(attempt, self.retry_delay_ms, self.retry_max_delay_ms);
+                    warn!("Database connection lost: '{}', reconnect attempt '{}' of '{}' in '{}' ms", e, attempt, self.retry_attempts, delay_ms);
+
+                    sleep(Duration::from_millis(delay_ms));
+                    self.reconnect();
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn store_once(&self, station_name: &str, data: &IWStationData) -> Result<usize, IWError> {
+        let mut connection = self.pool().get_conn()?;
+        let mut skipped = 0;
+        let new_data;
+
+        let data = if self.skip_duplicates {
+            let stored = self.stored_timestamps(&mut connection, station_name, data)?;
+            let (remaining, duplicates) = remove_duplicates(data, &stored);
+            skipped = duplicates;
+
+            match remaining {
+                Some(remaining) => {
+                    new_data = remaining;
+                    &new_data
+                }
+                None => return Ok(skipped),
+            }
+        } else {
+            data
+        };
+
+        match data {
+            IWStationData::SingleData(status) => {
+                connection.exec_drop(
+                    "INSERT INTO battery_data (timestamp, station, battery_voltage, li_battery_voltage, wind_diag, cf_card)
+                     VALUES (:timestamp, :station, :battery_voltage, :li_battery_voltage, :wind_diag, :cf_card)",
+                    params! {
+                        "timestamp" => &status.timestamp,
+                        "station" => station_name,
+                        "battery_voltage" => sql_value(status.solar_battery),
+                        "li_battery_voltage" => sql_value(status.lithium_battery),
+                        "wind_diag" => sql_value(status.wind_diag),
+                        "cf_card" => status.cf_card,
+                    })?;
+            }
+            IWStationData::MultipleData(data) if self.table_layout == IWTableLayout::Long => {
+                // All or nothing, so a retry after a lost connection does not insert the rows twice
+                let mut transaction = connection.start_transaction(TxOpts::default())?;
+                transaction.exec_batch(
+                    "INSERT INTO long_data (station, timestamp, variable, value) VALUES (:station, :timestamp, :variable, :value)",
+                    data.iter().flat_map(|entry| long_rows(entry).into_iter().map(move |(variable, value)| params! {
+                        "station" => station_name,
+                        "timestamp" => &entry.timestamp,
+                        "variable" => variable,
+                        "value" => value,
+                    })))?;
+                transaction.commit()?;
+            }
+            IWStationData::MultipleData(data) => {
+                let affected_rows = insert_multiple_data(&mut connection, station_name, data, self.batch_size)?;
+                debug!("Rows written to multiple_data: '{}'", affected_rows);
+            }
+        }
+
+        if self.verify_after_store {
+            for discrepancy in self.verify(&mut connection, station_name, data)? {
+                error!("Database verification failed for station '{}': {}", station_name, discrepancy);
+            }
+        }
+
+        Ok(skipped)
+    }
+
+    // Time stamps of this station already in the table, within the time range of the new data
+    fn stored_timestamps(&self, connection: &mut PooledConn, station_name: &str, data: &IWStationData) -> Result<HashSet<String>, IWError> {
+        let timestamps = data_timestamps(data);
+
+        let (first, last) = match (timestamps.iter().min(), timestamps.iter().max()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(HashSet::new()),
+        };
+
+        let table = match data {
+            IWStationData::SingleData(_) => "battery_data",
+            IWStationData::MultipleData(_) if self.table_layout == IWTableLayout::Long => "long_data",
+            IWStationData::MultipleData(_) => "multiple_data",
+        };
+
+        let stored: Vec<String> = connection.exec(
+            format!("SELECT DISTINCT DATE_FORMAT(timestamp, '%Y-%m-%d %H:%i:%s') FROM {} WHERE station = ? AND timestamp BETWEEN ? AND ?", table),
+            (station_name, first, last))?;
+
+        Ok(stored.into_iter().collect())
+    }
+
+    // Reads the just inserted rows back by (station, timestamp)
+    fn verify(&self, connection: &mut PooledConn, station_name: &str, data: &IWStationData) -> Result<Vec<String>, IWError> {
+        let mut result = Vec::new();
+
+        match data {
+            IWStationData::SingleData(status) => {
+                let row: Option<Row> = connection.exec_first(
+                    format!("SELECT {} FROM battery_data WHERE station = :station AND timestamp = :timestamp ORDER BY id DESC LIMIT 1",
+                        BATTERY_FIELDS.join(", ")),
+                    params! { "station" => station_name, "timestamp" => &status.timestamp })?;
+                let actual = row_values(row, &BATTERY_FIELDS);
+                let expected: Vec<_> = BATTERY_FIELDS.iter().copied().zip(battery_values(status)).collect();
+
+                result.extend(verify_values(&expected, &actual).into_iter()
+                    .map(|discrepancy| format!("{} {}", status.timestamp, discrepancy)));
+            }
+            IWStationData::MultipleData(data) => {
+                for entry in data {
+                    let params = params! { "station" => station_name, "timestamp" => &entry.timestamp };
+
+                    let actual = if self.table_layout == IWTableLayout::Long {
+                        let rows: Vec<(String, f64)> = connection.exec(
+                            "SELECT variable, value FROM long_data WHERE station = :station AND timestamp = :timestamp", params)?;
+                        rows.into_iter().map(|(variable, value)| (variable, Some(value))).collect()
+                    } else {
+                        let row: Option<Row> = connection.exec_first(
+                            format!("SELECT {} FROM multiple_data WHERE station = :station AND timestamp = :timestamp ORDER BY id DESC LIMIT 1",
+                                WEATHER_FIELDS.join(", ")), params)?;
+                        row_values(row, &WEATHER_FIELDS)
+                    };
+
+                    result.extend(verify_values(&long_rows(entry), &actual).into_iter()
+                        .map(|discrepancy| format!("{} {}", entry.timestamp, discrepancy)));
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+fn data_timestamps(data: &IWStationData) -> Vec<&str> {
+    match data {
+        IWStationData::SingleData(status) => vec![status.timestamp.as_str()],
+        IWStationData::MultipleData(data) => data.iter().map(|entry| entry.timestamp.as_str()).collect(),
+    }
+}
+
+// Drops records with a time stamp that is already stored or appears earlier in the same batch,
+// None when nothing is left to insert
+fn remove_duplicates(data: &IWStationData, stored: &HashSet<String>) -> (Option<IWStationData>, usize) {
+    match data {
+        IWStationData::SingleData(status) if stored.contains(&status.timestamp) => (None, 1),
+        IWStationData::SingleData(_) => (Some(data.clone()), 0),
+        IWStationData::MultipleData(data) => {
+            let mut seen = stored.clone();
+            let remaining: Vec<IWWeatherData> = data.iter()
+                .filter(|entry| seen.insert(entry.timestamp.clone()))
+                .cloned()
+                .collect();
+            let skipped = data.len() - remaining.len();
+
+            if remaining.is_empty() {
+                (None, skipped)
+            } else {
+                (Some(IWStationData::MultipleData(remaining)), skipped)
+            }
+        }
+    }
+}
+
+// MySQL rejects NAN and +/-INF in a DOUBLE column, so the sentinel values are stored as NULL
+fn sql_value(value: f64) -> Value {
+    if value.is_finite() {
+        Value::from(value)
+    } else {
+        Value::NULL
+    }
+}
+
+fn multiple_data_row(station_name: &str, entry: &IWWeatherData) -> Vec<Value> {
+    let mut row = vec![entry.timestamp.as_str().into(), station_name.into()];
+    row.extend(entry.field_values().iter().map(|value| sql_value(*value)));
+    row
+}
+
+// One multi-row INSERT per batch, so a day's backlog is not written record by record
+fn multiple_data_insert(rows: usize) -> String {
+    let placeholders = format!("(?, ?, {})", vec!["?"; WEATHER_FIELDS.len()].join(", "));
+
+    format!("INSERT INTO multiple_data (timestamp, station, {}) VALUES {}",
+        WEATHER_FIELDS.join(", "), vec![placeholders; rows].join(", "))
+}
+
+// Statement and parameters of every batch, at most batch_size rows each
+fn multiple_data_batches(station_name: &str, data: &[IWWeatherData], batch_size: usize) -> Vec<(String, Vec<Value>)> {
+    data.chunks(batch_size.max(1)).map(|batch| {
+        let values = batch.iter().flat_map(|entry| multiple_data_row(station_name, entry)).collect();
+        (multiple_data_insert(batch.len()), values)
+    }).collect()
+}
+
+// The transaction is rolled back when it is dropped without a commit, so either all rows are written or none
+fn insert_multiple_data(connection: &mut PooledConn, station_name: &str, data: &[IWWeatherData], batch_size: usize) -> Result<u64, IWError> {
+    if data.is_empty() {
+        return Ok(0)
+    }
+
+    let mut transaction = connection.start_transaction(TxOpts::default())?;
+    let mut affected_rows = 0;
+
+    for (statement, values) in multiple_data_batches(station_name, data, batch_size) {
+        transaction.exec_drop(statement, values)?;
+        affected_rows += transaction.affected_rows();
+    }
+
+    transaction.commit()?;
+
+    Ok(affected_rows)
+}
+
+// A missing row gives an empty list, so every expected value is reported as not stored
+fn row_values(row: Option<Row>, fields: &[&str]) -> Vec<(String, Option<f64>)> {
+    match row {
+        Some(row) => fields.iter().enumerate()
+            .map(|(i, field)| (field.to_string(), row.get::<Option<f64>, usize>(i).flatten()))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{db_opts, is_connection_error, long_rows, multiple_data_batches, multiple_data_insert, multiple_data_row, reconnect_delay_ms, remove_duplicates, table_schema, verify_values};
+
+    use crate::config::{IWConfiguration, IWStationConfiguration, IWTableLayout, default_epoch_base};
+    use std::collections::HashSet;
+
+    use mysql::{DriverError, MySqlError, Value};
+
+    use crate::process_data::{IWStationData, IWLoggerStatus, IWWeatherData, parse_binary_data};
+
+    fn weather_data(timestamp: &str) -> IWWeatherData {
+        IWWeatherData {
+            timestamp: timestamp.to_string(),
+            air_temperature: 16.57,
+            air_relative_humidity: 76.58,
+            solar_radiation: 820.0,
+            soil_water_content: 0.048,
+            soil_temperature: 20.6,
+            wind_speed: 6.046,
+            wind_max: 8.25,
+            wind_direction: 342.5,
+            precipitation: 0.0,
+            air_pressure: 978.0,
+            precipitation_raw: None,
+        }
+    }
+
+    #[test]
+    fn test_is_connection_error() {
+        let mysql_error = |code| mysql::Error::MySqlError(MySqlError { state: "HY000".to_string(), message: String::new(), code });
+
+        assert!(is_connection_error(&mysql::Error::IoError(std::io::Error::from(std::io::ErrorKind::BrokenPipe))));
+        assert!(is_connection_error(&mysql::Error::DriverError(DriverError::CouldNotConnect(None))));
+        assert!(is_connection_error(&mysql_error(2006)));
+        assert!(is_connection_error(&mysql_error(2013)));
+
+        // Duplicate key and syntax errors do not go away with a new connection
+        assert!(!is_connection_error(&mysql_error(1062)));
+        assert!(!is_connection_error(&mysql_error(1064)));
+        assert!(!is_connection_error(&mysql::Error::DriverError(DriverError::MixedParams)));
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        assert_eq!(reconnect_delay_ms(1, 1000, 30000), 1000);
+        assert_eq!(reconnect_delay_ms(2, 1000, 30000), 2000);
+        assert_eq!(reconnect_delay_ms(5, 1000, 30000), 16000);
+        assert_eq!(reconnect_delay_ms(6, 1000, 30000), 30000);
+        assert_eq!(reconnect_delay_ms(100, 1000, 30000), 30000);
+    }
+
+    #[test]
+    fn test_db_opts() {
+        assert!(db_opts(&IWConfiguration::default()).is_none());
+
+        let config = IWConfiguration {
+            hostname: Some("localhost".to_string()),
+            ..Default::default()
+        };
+        assert!(db_opts(&config).is_none());
+
+        let config = IWConfiguration {
+            hostname: Some("localhost".to_string()),
+            db_name: Some("weatherstation".to_string()),
+            username: Some("iridium".to_string()),
+            ..Default::default()
+        };
+        let opts = mysql::Opts::from(db_opts(&config).unwrap());
+        assert_eq!(opts.get_ip_or_hostname(), "localhost");
+        assert_eq!(opts.get_db_name(), Some("weatherstation"));
+        assert_eq!(opts.get_user(), Some("iridium"));
+        assert_eq!(opts.get_pass(), None);
+    }
+
+    #[test]
+    fn test_long_rows() {
+        let mut data = weather_data("2022-04-03 13:00:00");
+
+        let rows = long_rows(&data);
+        assert_eq!(rows.len(), 10);
+        assert_eq!(rows[0], ("air_temperature", 16.57));
+        assert_eq!(rows[9], ("air_pressure", 978.0));
+
+        data.solar_radiation = f64::NAN;
+        data.wind_max = f64::INFINITY;
+
+        let rows = long_rows(&data);
+        assert_eq!(rows.len(), 8);
+        assert!(!rows.iter().any(|(variable, _)| *variable == "solar_radiation" || *variable == "wind_max"));
+    }
+
+    #[test]
+    fn test_table_schema() {
+        assert!(table_schema(IWTableLayout::Wide)[1].contains("multiple_data"));
+        assert!(table_schema(IWTableLayout::Long)[1].contains("long_data"));
+    }
+
+    #[test]
+    fn test_verify_values() {
+        let data = IWWeatherData {
+            solar_radiation: f64::NAN,
+            ..weather_data("2022-04-03 13:00:00")
+        };
+        let expected = long_rows(&data);
+
+        let actual: Vec<_> = expected.iter().map(|(field, value)| (field.to_string(), Some(*value))).collect();
+        assert!(verify_values(&expected, &actual).is_empty());
+
+        // Simulate a column that only keeps one decimal and a value that was not stored at all
+        let actual: Vec<_> = expected.iter()
+            .filter(|(field, _)| *field != "air_pressure")
+            .map(|(field, value)| (field.to_string(), Some((value * 10.0).trunc() / 10.0)))
+            .collect();
+        let discrepancies = verify_values(&expected, &actual);
+        assert_eq!(discrepancies.len(), 6);
+        assert_eq!(discrepancies[0], "air_temperature: expected 16.57, stored 16.5");
+        assert_eq!(discrepancies[5], "air_pressure: expected 978, not stored");
+    }
+
+    #[test]
+    fn test_multiple_data_insert() {
+        let statement = multiple_data_insert(3);
+
+        assert!(statement.starts_with("INSERT INTO multiple_data (timestamp, station, air_temperature, "));
+        assert_eq!(statement.matches("VALUES").count(), 1);
+        assert_eq!(statement.matches('(').count(), 4);
+        assert_eq!(statement.matches('?').count(), 3 * 12);
+    }
+
+    #[test]
+    fn test_multiple_data_batches() {
+        let data: Vec<IWWeatherData> = (13..18).map(|hour| weather_data(&format!("2022-04-03 {}:00:00", hour))).collect();
+        let batches = multiple_data_batches("Nahuelbuta", &data, 2);
+
+        let rows: Vec<usize> = batches.iter().map(|(statement, _)| statement.matches("(?, ?,").count()).collect();
+        assert_eq!(rows, vec![2, 2, 1]);
+
+        for (statement, values) in batches.iter() {
+            assert_eq!(statement.matches('?').count(), values.len());
+        }
+
+        // The last batch holds the last record
+        assert_eq!(batches[2].1[0], Value::from("2022-04-03 17:00:00"));
+
+        assert_eq!(multiple_data_batches("Nahuelbuta", &data, 500).len(), 1);
+    }
+
+    #[test]
+    fn test_remove_duplicates() {
+        let data = IWStationData::MultipleData(vec![
+            weather_data("2022-04-03 13:00:00"),
+            weather_data("2022-04-03 14:00:00"),
+            weather_data("2022-04-03 14:00:00"),
+            weather_data("2022-04-03 15:00:00"),
+        ]);
+
+        let stored: HashSet<String> = ["2022-04-03 13:00:00".to_string()].into_iter().collect();
+
+        match remove_duplicates(&data, &stored) {
+            (Some(IWStationData::MultipleData(remaining)), 2) => {
+                assert_eq!(remaining.len(), 2);
+                assert_eq!(remaining[0].timestamp, "2022-04-03 14:00:00");
+                assert_eq!(remaining[1].timestamp, "2022-04-03 15:00:00");
+            }
+            result => {
+                panic!("Expected two remaining records, got: '{:?}'", result);
+            }
+        }
+
+        assert_eq!(remove_duplicates(&data, &HashSet::new()).1, 1);
+
+        let status = IWStationData::SingleData(IWLoggerStatus {
+            timestamp: "2022-04-03 13:00:00".to_string(),
+            solar_battery: 12.47,
+            lithium_battery: 3.369,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        });
+        assert_eq!(remove_duplicates(&status, &stored), (None, 1));
+        assert_eq!(remove_duplicates(&status, &HashSet::new()), (Some(status.clone()), 0));
+    }
+
+    #[test]
+    fn test_multiple_data_row() {
+        // Air temperature is F2_NAN (159, 254), wind max is F2_POS_INFINITY (31, 255)
+        let data = parse_binary_data(&[2, 0, 28, 208, 252, 170, 60, 0, 0, 0, 0, 159, 254, 93, 234, 3, 52, 96, 48, 72, 12, 119, 158, 31, 255, 42, 25, 96, 0, 3, 210],
+            &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+
+        let entry = match data {
+            IWStationData::MultipleData(ref data) => &data[0],
+            _ => panic!("Expected MultipleData, got: '{:?}'", data),
+        };
+        assert_eq!(entry.special_fields(), vec!["air_temperature", "wind_max"]);
+
+        let row = multiple_data_row("Nahuelbuta", entry);
+        assert_eq!(row.len(), 12);
+        assert_eq!(row[0], Value::from("2022-04-03 13:00:00"));
+        assert_eq!(row[1], Value::from("Nahuelbuta"));
+        assert_eq!(row[2], Value::NULL);
+        assert_eq!(row[3], Value::from(76.58));
+        assert_eq!(row[8], Value::NULL);
+        assert_eq!(row[9], Value::from(258.5));
+    }
+}
@@ -1,7 +1,7 @@
 
-
 use std::io;
 use std::fmt;
+use std::net::IpAddr;
 
 
 #[derive(Debug)]
@@ -9,20 +9,87 @@ pub enum IWError {
     DataTooShort(usize),
     DataLengthMismatch(usize),
     InvalidDataHeader,
+    OddDataLength(usize),
+    TooFewRecords { got: usize, min: usize },
+    SchemaMismatch(usize),
+    AlignmentError { expected: usize, actual: usize },
+    OutOfRange { field: String, value: f64 },
+    NonMonotonicTimestamps { previous: String, current: String },
+    InvalidConfig(String),
+    ConfigNotFound(String),
+    ConfigSyntax { line: usize, column: usize, message: String },
+    ConfigMissingField(String),
+    InvalidHex(String),
+    PortsInUse(Vec<u16>),
+    Timeout(usize),
+    PacketTooLarge { declared: Option<usize>, max: usize },
+    ChecksumMismatch { received: u16, calculated: u16 },
+    RateLimited(IpAddr),
     IO(io::Error),
+    Json(serde_json::Error),
+    Database(mysql::Error),
 }
 
 impl std::error::Error for IWError {
 
 }
 
+impl IWError {
+    // Variant name, used as a metrics label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IWError::DataTooShort(_) => "DataTooShort",
+            IWError::DataLengthMismatch(_) => "DataLengthMismatch",
+            IWError::InvalidDataHeader => "InvalidDataHeader",
+            IWError::OddDataLength(_) => "OddDataLength",
+            IWError::TooFewRecords { .. } => "TooFewRecords",
+            IWError::SchemaMismatch(_) => "SchemaMismatch",
+            IWError::AlignmentError { .. } => "AlignmentError",
+            IWError::OutOfRange { .. } => "OutOfRange",
+            IWError::NonMonotonicTimestamps { .. } => "NonMonotonicTimestamps",
+            IWError::InvalidConfig(_) => "InvalidConfig",
+            IWError::ConfigNotFound(_) => "ConfigNotFound",
+            IWError::ConfigSyntax { .. } => "ConfigSyntax",
+            IWError::ConfigMissingField(_) => "ConfigMissingField",
+            IWError::InvalidHex(_) => "InvalidHex",
+            IWError::PortsInUse(_) => "PortsInUse",
+            IWError::Timeout(_) => "Timeout",
+            IWError::PacketTooLarge { .. } => "PacketTooLarge",
+            IWError::ChecksumMismatch { .. } => "ChecksumMismatch",
+            IWError::RateLimited(_) => "RateLimited",
+            IWError::IO(_) => "IO",
+            IWError::Json(_) => "Json",
+            IWError::Database(_) => "Database",
+        }
+    }
+}
+
 impl fmt::Display for IWError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
       match self {
             IWError::DataTooShort(s) => write!(f, "Data too short:  '{}'", s),
             IWError::DataLengthMismatch(s) => write!(f, "Data length does not match:  '{}'", s),
             IWError::InvalidDataHeader => write!(f, "Invalid data header"),
+            IWError::OddDataLength(s) => write!(f, "Odd data length:  '{}'", s),
+            IWError::TooFewRecords { got, min } => write!(f, "Too few records: '{}', expected at least: '{}'", got, min),
+            IWError::SchemaMismatch(s) => write!(f, "Data length does not match the schema:  '{}'", s),
+            IWError::AlignmentError { expected, actual } => write!(f, "Record misaligned, expected: '{}' bytes, actual: '{}' bytes", expected, actual),
+            IWError::OutOfRange { field, value } => write!(f, "Value out of range: '{}' = '{}'", field, value),
+            IWError::NonMonotonicTimestamps { previous, current } => write!(f, "Time stamp goes backwards: '{}' after '{}'", current, previous),
+            IWError::InvalidConfig(s) => write!(f, "Invalid configuration: '{}'", s),
+            IWError::ConfigNotFound(s) => write!(f, "Configuration file not found: '{}'", s),
+            IWError::ConfigSyntax { line, column, message } => write!(f, "Invalid configuration syntax at line '{}', column '{}': '{}'", line, column, message),
+            IWError::ConfigMissingField(s) => write!(f, "Missing configuration field: '{}'", s),
+            IWError::InvalidHex(s) => write!(f, "Invalid hex string: '{}'", s),
+            IWError::PortsInUse(s) => write!(f, "Ports already in use: '{:?}'", s),
+            IWError::Timeout(s) => write!(f, "Read timeout, bytes received: '{}'", s),
+            IWError::PacketTooLarge { declared: Some(declared), max } => write!(f, "Packet too large, declared length: '{}', maximum: '{}'", declared, max),
+            IWError::PacketTooLarge { declared: None, max } => write!(f, "Packet too large, maximum: '{}'", max),
+            IWError::ChecksumMismatch { received, calculated } => write!(f, "Checksum mismatch, received: '{:04x}', calculated: '{:04x}'", received, calculated),
+            IWError::RateLimited(s) => write!(f, "Rate limit exceeded for: '{}'", s),
             IWError::IO(e) => write!(f, "IO error: '{}'", e),
+            IWError::Json(e) => write!(f, "JSON error: '{}'", e),
+            IWError::Database(e) => write!(f, "Database error: '{}'", e),
         }
     }
 }
@@ -32,3 +99,15 @@ impl From<io::Error> for IWError {
      IWError::IO(e)
     }
 }
+
+impl From<serde_json::Error> for IWError {
+    fn from(e: serde_json::Error) -> Self {
+     IWError::Json(e)
+    }
+}
+
+impl From<mysql::Error> for IWError {
+    fn from(e: mysql::Error) -> Self {
+     IWError::Database(e)
+    }
+}
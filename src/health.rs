@@ -0,0 +1,288 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use chrono::NaiveDateTime;
+use serde_derive::Serialize;
+
+use crate::config::IWConfiguration;
+use crate::process_data::{IWLoggerStatus, IWWeatherData, WEATHER_FIELDS, timestamp_to_datetime};
+
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IWHealthStatus {
+    Ok,
+    Warn,
+    Critical,
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct IWStationHealth {
+    pub last_seen: Option<NaiveDateTime>,
+    pub solar_battery: Option<f64>,
+    pub lithium_battery: Option<f64>,
+    pub clock_skew_secs: Option<i64>,
+    pub gap_count: u64,
+    pub last_record: Option<NaiveDateTime>,
+    // Values of the newest record and how often each of them came in a row, same order as WEATHER_FIELDS
+    pub last_values: Option<[f64; 10]>,
+    pub repeat_counts: [u32; 10],
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct IWStationHealthSummary {
+    pub station: String,
+    pub status: IWHealthStatus,
+    pub last_seen_age_secs: Option<i64>,
+    pub solar_battery: Option<f64>,
+    pub lithium_battery: Option<f64>,
+    pub clock_skew_secs: Option<i64>,
+    pub gap_count: u64,
+    pub stuck_sensors: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct IWServiceHealth {
+    pub status: IWHealthStatus,
+    pub ports: Vec<u16>,
+    // Time stamp of the newest weather record per station
+    pub last_record: BTreeMap<String, String>,
+}
+
+#[derive(Default, Debug)]
+pub struct IWHealthCache {
+    stations: Mutex<BTreeMap<String, IWStationHealth>>,
+}
+
+impl IWHealthCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update_status(&self, station: &str, data: &IWLoggerStatus, received_at: NaiveDateTime) {
+        let mut stations = self.stations.lock().unwrap();
+        let entry = stations.entry(station.to_string()).or_default();
+
+        entry.last_seen = Some(received_at);
+        entry.solar_battery = Some(data.solar_battery);
+        entry.lithium_battery = Some(data.lithium_battery);
+
+        if let Some(timestamp) = timestamp_to_datetime(&data.timestamp) {
+            entry.clock_skew_secs = Some((received_at - timestamp).num_seconds());
+        }
+    }
+
+    pub fn update_weather(&self, station: &str, data: &[IWWeatherData], received_at: NaiveDateTime, expected_interval_secs: i64) {
+        let mut stations = self.stations.lock().unwrap();
+        let entry = stations.entry(station.to_string()).or_default();
+
+        entry.last_seen = Some(received_at);
+
+        for record in data {
+            if let Some(timestamp) = timestamp_to_datetime(&record.timestamp) {
+                if let Some(last_record) = entry.last_record {
+                    if (timestamp - last_record).num_seconds() > expected_interval_secs {
+                        entry.gap_count += 1;
+                    }
+                }
+
+                // A batch sent again must not count as repeated values
+                if entry.last_record.is_none_or(|last_record| timestamp > last_record) {
+                    let values = record.field_values();
+
+                    for (i, value) in values.iter().enumerate() {
+                        let repeated = entry.last_values.is_some_and(|last_values| last_values[i] == *value);
+                        entry.repeat_counts[i] = if repeated { entry.repeat_counts[i] + 1 } else { 1 };
+                    }
+
+                    entry.last_values = Some(values);
+                    entry.last_record = Some(timestamp);
+                }
+
+                entry.clock_skew_secs = Some((received_at - timestamp).num_seconds());
+            }
+        }
+    }
+
+    pub fn service_health(&self, ports: &[u16]) -> IWServiceHealth {
+        let stations = self.stations.lock().unwrap();
+
+        IWServiceHealth {
+            status: IWHealthStatus::Ok,
+            ports: ports.to_vec(),
+            last_record: stations.iter().filter_map(|(station, health)| {
+                health.last_record.map(|last_record| (station.clone(), last_record.format("%Y-%m-%d %H:%M:%S").to_string()))
+            }).collect(),
+        }
+    }
+
+    pub fn summary(&self, config: &IWConfiguration, now: NaiveDateTime) -> Vec<IWStationHealthSummary> {
+        let stations = self.stations.lock().unwrap();
+
+        stations.iter().map(|(station, health)| {
+            IWStationHealthSummary {
+                station: station.clone(),
+                status: station_status(health, config, now),
+                last_seen_age_secs: health.last_seen.map(|last_seen| (now - last_seen).num_seconds()),
+                solar_battery: health.solar_battery,
+                lithium_battery: health.lithium_battery,
+                clock_skew_secs: health.clock_skew_secs,
+                gap_count: health.gap_count,
+                stuck_sensors: stuck_sensors(health, config),
+            }
+        }).collect()
+    }
+}
+
+pub fn stuck_sensors(health: &IWStationHealth, config: &IWConfiguration) -> Vec<String> {
+    if config.stuck_sensor_records == 0 {
+        return Vec::new()
+    }
+
+    WEATHER_FIELDS.iter().zip(health.repeat_counts.iter())
+        .filter(|(name, count)| **count >= config.stuck_sensor_records && config.stuck_sensor_fields.iter().any(|field| field == *name))
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+pub fn station_status(health: &IWStationHealth, config: &IWConfiguration, now: NaiveDateTime) -> IWHealthStatus {
+    let last_seen_age = match health.last_seen {
+        Some(last_seen) => (now - last_seen).num_seconds(),
+        None => return IWHealthStatus::Warn,
+    };
+
+    if last_seen_age > config.last_seen_critical_secs {
+        return IWHealthStatus::Critical
+    }
+
+    let low_solar = health.solar_battery.is_some_and(|voltage| voltage < config.solar_battery_min);
+    let low_lithium = health.lithium_battery.is_some_and(|voltage| voltage < config.lithium_battery_min);
+    let skewed = health.clock_skew_secs.is_some_and(|skew| skew.abs() > config.max_skew_warn_secs);
+
+    let stuck = !stuck_sensors(health, config).is_empty();
+
+    if last_seen_age > config.last_seen_warn_secs || low_solar || low_lithium || skewed || health.gap_count > 0 || stuck {
+        IWHealthStatus::Warn
+    } else {
+        IWHealthStatus::Ok
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::{IWHealthCache, IWHealthStatus};
+
+    use crate::config::IWConfiguration;
+    use crate::process_data::{IWLoggerStatus, IWWeatherData};
+
+    fn datetime(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_health_ok() {
+        let cache = IWHealthCache::new();
+        let config = IWConfiguration::default();
+        let status = IWLoggerStatus {
+            timestamp: "2022-04-05 00:00:00".to_string(),
+            solar_battery: 12.47,
+            lithium_battery: 3.369,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        };
+
+        cache.update_status("Nahuelbuta", &status, datetime("2022-04-05 00:01:00"));
+        let summary = cache.summary(&config, datetime("2022-04-05 00:30:00"));
+
+        assert_eq!(summary.len(), 1);
+        assert_eq!(summary[0].station, "Nahuelbuta");
+        assert_eq!(summary[0].status, IWHealthStatus::Ok);
+        assert_eq!(summary[0].last_seen_age_secs, Some(1740));
+        assert_eq!(summary[0].clock_skew_secs, Some(60));
+    }
+
+    #[test]
+    fn test_health_low_battery() {
+        let cache = IWHealthCache::new();
+        let config = IWConfiguration::default();
+        let status = IWLoggerStatus {
+            timestamp: "2022-04-05 00:00:00".to_string(),
+            solar_battery: 10.2,
+            lithium_battery: 3.369,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        };
+
+        cache.update_status("Nahuelbuta", &status, datetime("2022-04-05 00:01:00"));
+        let summary = cache.summary(&config, datetime("2022-04-05 00:30:00"));
+
+        assert_eq!(summary[0].status, IWHealthStatus::Warn);
+    }
+
+    #[test]
+    fn test_health_stuck_sensor() {
+        let cache = IWHealthCache::new();
+        let config = IWConfiguration::default();
+
+        // The humidity changes, the temperature does not
+        let data: Vec<IWWeatherData> = (0..6).map(|hour| IWWeatherData {
+            timestamp: format!("2022-04-05 0{}:00:00", hour),
+            air_temperature: 16.57,
+            air_relative_humidity: 70.0 + hour as f64,
+            solar_radiation: 0.0,
+            soil_water_content: 0.048,
+            soil_temperature: 20.6 + hour as f64 * 0.1,
+            wind_speed: 0.0,
+            wind_max: 0.0,
+            wind_direction: 0.0,
+            precipitation: 0.0,
+            air_pressure: 978.0 + hour as f64,
+            precipitation_raw: None,
+        }).collect();
+
+        cache.update_weather("Nahuelbuta", &data[..5], datetime("2022-04-05 04:01:00"), 3600);
+        let summary = cache.summary(&config, datetime("2022-04-05 04:30:00"));
+        assert!(summary[0].stuck_sensors.is_empty());
+        assert_eq!(summary[0].status, IWHealthStatus::Ok);
+
+        // The same batch again does not count
+        cache.update_weather("Nahuelbuta", &data[..5], datetime("2022-04-05 04:01:00"), 3600);
+        assert!(cache.summary(&config, datetime("2022-04-05 04:30:00"))[0].stuck_sensors.is_empty());
+
+        cache.update_weather("Nahuelbuta", &data[5..], datetime("2022-04-05 05:01:00"), 3600);
+        let summary = cache.summary(&config, datetime("2022-04-05 05:30:00"));
+        assert_eq!(summary[0].stuck_sensors, vec!["air_temperature"]);
+        assert_eq!(summary[0].status, IWHealthStatus::Warn);
+    }
+
+    #[test]
+    fn test_health_critical() {
+        let cache = IWHealthCache::new();
+        let config = IWConfiguration::default();
+        let status = IWLoggerStatus {
+            timestamp: "2022-04-05 00:00:00".to_string(),
+            solar_battery: 12.47,
+            lithium_battery: 3.369,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        };
+
+        cache.update_status("Nahuelbuta", &status, datetime("2022-04-05 00:01:00"));
+        let summary = cache.summary(&config, datetime("2022-04-07 00:00:00"));
+
+        assert_eq!(summary[0].status, IWHealthStatus::Critical);
+    }
+}
@@ -0,0 +1,353 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::net::{TcpListener, TcpStream, IpAddr};
+use std::io::{BufRead, BufReader, Write};
+use std::thread::spawn;
+use std::time::Duration;
+use std::sync::Arc;
+
+use log::{debug, error};
+use chrono::Utc;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use tungstenite::handshake::derive_accept_key;
+
+use crate::config::IWMetricsBackend;
+use crate::error::IWError;
+use crate::process_data::IWServerState;
+use crate::websocket::serve_websocket;
+
+
+fn start_websocket(mut stream: TcpStream, key: &str, state: &IWServerState) -> Result<(), IWError> {
+    stream.set_read_timeout(None)?;
+
+    // Subscribe before the handshake is finished, so the client can not miss any data
+    let receiver = state.websocket.subscribe();
+
+    write!(stream, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes()))?;
+    stream.flush()?;
+
+    debug!("WebSocket client connected");
+
+    spawn(move || serve_websocket(stream, receiver));
+
+    Ok(())
+}
+
+// "gzip;q=0" means the client does not want gzip
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|encoding| {
+        let mut parts = encoding.split(';');
+        let name = parts.next().unwrap_or("").trim();
+
+        let quality = parts.filter_map(|parameter| parameter.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+            .map_or(Some(1.0), |(_, value)| value.trim().parse::<f64>().ok());
+
+        name.eq_ignore_ascii_case("gzip") && quality.is_some_and(|quality| quality > 0.0)
+    })
+}
+
+fn handle_http_request(mut stream: TcpStream, state: &IWServerState) -> Result<(), IWError> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut accept_gzip = false;
+    let mut websocket_key = None;
+
+    // Only the Accept-Encoding and WebSocket headers are of interest, skip the rest
+    loop {
+        let mut line = String::new();
+        let len = reader.read_line(&mut line)?;
+        if len == 0 || line.trim().is_empty() {
+            break
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim();
+
+            if name.eq_ignore_ascii_case("accept-encoding") {
+                accept_gzip = accepts_gzip(value);
+            } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+                websocket_key = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+    debug!("HTTP request: '{} {}'", method, path);
+
+    if let ("GET", "/ws", Some(key)) = (method, path, &websocket_key) {
+        if state.websocket_enabled() {
+            return start_websocket(stream, key, state)
+        }
+    }
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/health") => {
+            ("200 OK", "application/json", serde_json::to_string(&state.health.service_health(&state.bound_ports))?)
+        }
+        ("GET", "/health/stations") => {
+            let summary = state.health.summary(&state.config, Utc::now().naive_utc());
+            ("200 OK", "application/json", serde_json::to_string(&summary)?)
+        }
+        ("GET", "/metrics") if state.config.metrics_backend == IWMetricsBackend::Prometheus => {
+            ("200 OK", "text/plain; version=0.0.4", state.metrics.render())
+        }
+        _ => {
+            ("404 Not Found", "text/plain", "Not found".to_string())
+        }
+    };
+
+    let (content_encoding, body) = if accept_gzip {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body.as_bytes())?;
+        ("Content-Encoding: gzip\r\n", encoder.finish()?)
+    } else {
+        ("", body.into_bytes())
+    };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+        status, content_type, content_encoding, body.len())?;
+    stream.write_all(&body)?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+pub fn start_http_server(ip: IpAddr, port: u16, state: Arc<IWServerState>) {
+    let listener = match TcpListener::bind((ip, port)) {
+        Ok(listener) => {
+            debug!("Create HTTP listener for port: '{}'", port);
+            listener
+        }
+        Err(e) => {
+            error!("An error occurred while binding the HTTP port: '{}'", e);
+            return
+        }
+    };
+
+    spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_http_request(stream, &state) {
+                        error!("An error occurred while processing the HTTP request: '{}'", e);
+                    }
+                }
+                Err(e) => {
+                    error!("An error occurred while accepting the HTTP connection: '{}'", e);
+                }
+            }
+        }
+    });
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::io::{Read, Write};
+    use std::thread::spawn;
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use flate2::read::GzDecoder;
+    use tungstenite::Message;
+
+    use super::{accepts_gzip, handle_http_request};
+
+    use crate::config::{IWConfiguration, IWStationConfiguration, default_epoch_base};
+    use crate::error::IWError;
+    use crate::process_data::{IWServerState, IWLoggerStatus, IWStationData, parse_binary_data};
+
+    fn http_request(state: Arc<IWServerState>, request: &str) -> Vec<u8> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_http_request(stream, &state).unwrap();
+        });
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        server.join().unwrap();
+
+        response
+    }
+
+    fn split_response(response: &[u8]) -> (String, Vec<u8>) {
+        let header_end = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+        let header = String::from_utf8(response[..header_end].to_vec()).unwrap();
+        (header, response[header_end + 4..].to_vec())
+    }
+
+    fn test_state() -> Arc<IWServerState> {
+        let state = IWServerState::new(IWConfiguration::default()).unwrap();
+        let now = Utc::now().naive_utc();
+        let status = IWLoggerStatus {
+            timestamp: now.format("%Y-%m-%d %H:%M:%S").to_string(),
+            solar_battery: 12.47,
+            lithium_battery: 3.369,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        };
+        state.health.update_status("Nahuelbuta", &status, now);
+        Arc::new(state)
+    }
+
+    #[test]
+    fn test_health_stations_identity() {
+        let response = http_request(test_state(), "GET /health/stations HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let (header, body) = split_response(&response);
+
+        assert!(header.starts_with("HTTP/1.1 200 OK"));
+        assert!(!header.contains("Content-Encoding"));
+        assert!(String::from_utf8(body).unwrap().contains("\"station\":\"Nahuelbuta\""));
+    }
+
+    #[test]
+    fn test_health_stations_gzip() {
+        let response = http_request(test_state(), "GET /health/stations HTTP/1.1\r\nAccept-Encoding: deflate, gzip\r\n\r\n");
+        let (header, body) = split_response(&response);
+
+        assert!(header.contains("Content-Encoding: gzip"));
+
+        let mut decoded = String::new();
+        GzDecoder::new(body.as_slice()).read_to_string(&mut decoded).unwrap();
+        let summary: serde_json::Value = serde_json::from_str(&decoded).unwrap();
+
+        assert_eq!(summary[0]["station"], "Nahuelbuta");
+        assert_eq!(summary[0]["status"], "ok");
+        assert_eq!(summary[0]["solar_battery"], 12.47);
+    }
+
+    #[test]
+    fn test_metrics_gzip() {
+        let state = test_state();
+        state.metrics.inc_parse_error("Nahuelbuta", &IWError::InvalidDataHeader);
+
+        let response = http_request(state.clone(), "GET /metrics HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+        let (header, body) = split_response(&response);
+
+        assert!(header.starts_with("HTTP/1.1 200 OK"));
+        assert!(header.contains("Content-Encoding: gzip"));
+
+        let mut decoded = String::new();
+        GzDecoder::new(body.as_slice()).read_to_string(&mut decoded).unwrap();
+        assert!(decoded.contains("iw_parse_errors_total{station=\"Nahuelbuta\",kind=\"InvalidDataHeader\"} 1"));
+
+        // Refused with a quality of zero
+        let response = http_request(state, "GET /metrics HTTP/1.1\r\nAccept-Encoding: gzip;q=0, identity\r\n\r\n");
+        let (header, body) = split_response(&response);
+
+        assert!(!header.contains("Content-Encoding"));
+        assert!(String::from_utf8(body).unwrap().contains("iw_parse_errors_total{station=\"Nahuelbuta\",kind=\"InvalidDataHeader\"} 1"));
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(accepts_gzip("gzip"));
+        assert!(accepts_gzip(" deflate, GZIP "));
+        assert!(accepts_gzip("gzip;q=0.5"));
+        assert!(accepts_gzip("gzip; q=1.0, identity"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip("gzip; q=0.000"));
+        assert!(!accepts_gzip("x-gzip-like"));
+        assert!(!accepts_gzip("deflate, br"));
+        assert!(!accepts_gzip(""));
+    }
+
+    #[test]
+    fn test_health() {
+        let mut state = IWServerState::new(IWConfiguration::default()).unwrap();
+        state.bound_ports = vec![2100, 2101];
+
+        let data = parse_binary_data(&[2, 0, 28, 208, 252, 170, 60, 0, 0, 0, 0, 70, 121, 93, 234, 3, 52, 96, 48, 72, 12, 119, 158, 67, 59, 42, 25, 96, 0, 3, 210], &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+
+        match data {
+            IWStationData::MultipleData(ref data) => state.health.update_weather("Nahuelbuta", data, Utc::now().naive_utc(), 3600),
+            _ => panic!("Expected MultipleData, got: '{:?}'", data),
+        }
+
+        let response = http_request(Arc::new(state), "GET /health HTTP/1.1\r\n\r\n");
+        let (header, body) = split_response(&response);
+
+        assert!(header.starts_with("HTTP/1.1 200 OK"));
+
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["ports"], serde_json::json!([2100, 2101]));
+        assert_eq!(health["last_record"]["Nahuelbuta"], "2022-04-03 13:00:00");
+    }
+
+    #[test]
+    fn test_not_found() {
+        let response = http_request(test_state(), "GET /unknown HTTP/1.1\r\n\r\n");
+        let (header, _) = split_response(&response);
+
+        assert!(header.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    #[test]
+    fn test_websocket() {
+        let config = IWConfiguration {
+            websocket_enabled: true,
+            ..Default::default()
+        };
+        let state = Arc::new(IWServerState::new(config).unwrap());
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server_state = state.clone();
+        let server = spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_http_request(stream, &server_state).unwrap();
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (mut websocket, _) = tungstenite::client(format!("ws://127.0.0.1:{}/ws", port), stream).unwrap();
+        server.join().unwrap();
+
+        let data = parse_binary_data(&[2, 0, 28, 208, 252, 170, 60, 0, 0, 0, 0, 70, 121, 93, 234, 3, 52, 96, 48, 72, 12, 119, 158, 67, 59, 42, 25, 96, 0, 3, 210], &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+
+        match data {
+            IWStationData::MultipleData(ref data) => state.websocket.broadcast_weather("Nahuelbuta", data, None),
+            _ => panic!("Expected MultipleData, got: '{:?}'", data),
+        }
+
+        match websocket.read().unwrap() {
+            Message::Text(message) => {
+                let message: serde_json::Value = serde_json::from_str(&message).unwrap();
+                assert_eq!(message["station"], "Nahuelbuta");
+                assert_eq!(message["data"]["timestamp"], "2022-04-03 13:00:00");
+                assert_eq!(message["data"]["air_temperature"], 16.57);
+            }
+            message => {
+                panic!("Expected text message, got: '{:?}'", message);
+            }
+        }
+
+        // After the client is gone the next broadcast drops it
+        drop(websocket);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        state.websocket.broadcast_weather("Nahuelbuta", &[], None);
+    }
+}
@@ -0,0 +1,51 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+//! Decoding of the Campbell FP2 data sent by the iridium weather stations.
+//!
+//! ```
+//! use iridium_weatherstation::{parse_binary_data, IWStationData};
+//! use iridium_weatherstation::config::{IWStationConfiguration, default_epoch_base};
+//!
+//! // Frame header followed by one weather record, without the SBS header
+//! let bytes = [2, 0, 28, 208, 252, 170, 60, 0, 0, 0, 0, 70, 121, 93, 234, 3, 52, 96, 48,
+//!     72, 12, 119, 158, 67, 59, 42, 25, 96, 0, 3, 210];
+//!
+//! match parse_binary_data(&bytes, &IWStationConfiguration::default(), default_epoch_base()).unwrap() {
+//!     IWStationData::MultipleData(data) => {
+//!         assert_eq!(data[0].timestamp, "2022-04-03 13:00:00");
+//!         assert_eq!(data[0].air_temperature, 16.57);
+//!     }
+//!     data => panic!("Expected MultipleData, got: '{:?}'", data),
+//! }
+//! ```
+
+pub mod config;
+pub mod error;
+
+mod database;
+mod health;
+mod http_server;
+mod log_rotation;
+mod metrics;
+mod mqtt;
+mod process_data;
+mod rate_limit;
+mod statsd;
+mod wal;
+mod websocket;
+
+pub use crate::process_data::{IWLoggerStatus, IWCfCardStatus, IWWeatherData, IWStationData, WEATHER_FIELDS,
+    u16_to_f64, f64_to_u16, f64_to_u16_with_loss, parse_binary_data, decode_hex, trace_decode};
+
+// Only used by the binary, the server writes files and database rows and is not part of the library API
+#[doc(hidden)]
+pub mod server {
+    pub use crate::database::IWDatabase;
+    pub use crate::log_rotation::IWRotatingFile;
+    pub use crate::process_data::start_server;
+}
@@ -0,0 +1,114 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+
+// Log file that is renamed to <name>.1, <name>.2, ... when it gets too large
+#[derive(Debug)]
+pub struct IWRotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    max_files: usize,
+}
+
+impl IWRotatingFile {
+    pub fn new<P: AsRef<Path>>(path: P, max_size: Option<u64>, max_files: usize) -> io::Result<Self> {
+        let file = File::options().append(true).create(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(IWRotatingFile {
+            path: path.as_ref().to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // The oldest copy is overwritten by the next one
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = File::options().append(true).create(true).open(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+impl Write for IWRotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(max_size) = self.max_size {
+            if self.size > 0 && self.size + buf.len() as u64 > max_size {
+                self.rotate()?;
+            }
+        }
+
+        let len = self.file.write(buf)?;
+        self.size += len as u64;
+
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::IWRotatingFile;
+
+    #[test]
+    fn test_rotate() {
+        let dir = std::env::temp_dir().join(format!("iw_test_log_rotation_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("test.log");
+        let mut file = IWRotatingFile::new(&path, Some(20), 2).unwrap();
+
+        for i in 0..4 {
+            writeln!(file, "line {} of the log", i).unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "line 3 of the log\n");
+        assert_eq!(std::fs::read_to_string(dir.join("test.log.1")).unwrap(), "line 2 of the log\n");
+        assert_eq!(std::fs::read_to_string(dir.join("test.log.2")).unwrap(), "line 1 of the log\n");
+        assert!(!dir.join("test.log.3").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
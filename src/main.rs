@@ -5,24 +5,111 @@
 // A simple data processing tool written in Rust for one of the campbell iridium weather stations
 //
 
-mod config;
-mod error;
-mod process_data;
-
-
-use std::fs::File;
+use std::path::Path;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use log::{info, debug};
+use log::{info, debug, warn, error};
 use simplelog::{WriteLogger, LevelFilter, ConfigBuilder};
 use chrono::Local;
+use clap::{Command, Arg};
 
-use crate::config::IWConfiguration;
-use crate::process_data::start_server;
+use iridium_weatherstation::{decode_hex, trace_decode};
+use iridium_weatherstation::config::{write_default_config, load_config};
+use iridium_weatherstation::error::IWError;
+use iridium_weatherstation::server::{IWDatabase, IWRotatingFile, start_server};
 
 
+const CONFIG_FILE_NAME: &str = "iridium_weatherstation_config.json";
+const CONFIG_FILE_NAME_TOML: &str = "iridium_weatherstation_config.toml";
+
 fn main() {
+    let matches = Command::new("iridium_weatherstation")
+        .version(env!("CARGO_PKG_VERSION"))
+        .arg(Arg::new("decode-hex")
+            .long("decode-hex")
+            .value_name("HEXSTRING")
+            .takes_value(true)
+            .help("Decode a single packet given as hex string and print the records"))
+        .arg(Arg::new("trace-decode")
+            .long("trace-decode")
+            .value_name("HEXSTRING")
+            .takes_value(true)
+            .help("Print every intermediate step of decoding a single packet given as hex string"))
+        .arg(Arg::new("init")
+            .long("init")
+            .help("Write a default configuration file and exit"))
+        .arg(Arg::new("init-db")
+            .long("init-db")
+            .help("Create the database tables for the configured table layout and exit"))
+        .get_matches();
+
+    if let Some(hex) = matches.value_of("trace-decode") {
+        match trace_decode(hex) {
+            Ok(trace) => {
+                for line in trace {
+                    println!("{}", line);
+                }
+                return
+            }
+            Err(e) => {
+                eprintln!("Could not decode hex string: '{}'", e);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    if let Some(hex) = matches.value_of("decode-hex") {
+        match decode_hex(hex) {
+            Ok(data) => {
+                println!("{}", serde_json::to_string_pretty(&data).unwrap());
+                return
+            }
+            Err(e) => {
+                eprintln!("Could not decode hex string: '{}'", e);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    if matches.is_present("init") {
+        match write_default_config(CONFIG_FILE_NAME) {
+            Ok(_) => {
+                println!("Default configuration written to '{}'", CONFIG_FILE_NAME);
+                return
+            }
+            Err(e) => {
+                eprintln!("Could not write '{}': '{}'", CONFIG_FILE_NAME, e);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    // The log level comes from the configuration, so it has to be read before the logger exists
+    // The TOML file is used when both exist
+    let config_file_name = if Path::new(CONFIG_FILE_NAME_TOML).exists() {
+        CONFIG_FILE_NAME_TOML
+    } else {
+        CONFIG_FILE_NAME
+    };
+
+    let config = match load_config(config_file_name) {
+        Ok(config) => config,
+        Err(e @ IWError::ConfigNotFound(_)) => {
+            eprintln!("{}, run 'iridium_weatherstation --init' to create a default one", e);
+            std::process::exit(1)
+        }
+        Err(e) => {
+            eprintln!("Could not load '{}': {}", config_file_name, e);
+            std::process::exit(1)
+        }
+    };
+
+    if let Err(e) = config.validate() {
+        eprintln!("Invalid configuration in '{}': {}", config_file_name, e);
+        std::process::exit(1)
+    }
+
     let dt = Local::now();
     let log_file_name = dt.format("iridium_weatherstation_%Y_%m_%d.log").to_string();
     let log_config = ConfigBuilder::new()
@@ -31,24 +118,69 @@ fn main() {
         .build();
 
     let _ = WriteLogger::init(
-        LevelFilter::Debug,
+        config.log_level_filter().unwrap_or(LevelFilter::Info),
         log_config,
-        File::options().append(true).create(true).open(log_file_name).unwrap()
+        IWRotatingFile::new(log_file_name, config.max_log_size_mb.map(|size| size * 1024 * 1024), config.max_log_files).unwrap()
     );
 
     info!("Data processor started.");
+    info!("Configuration was read successfully.");
+
+    if config.log_level_filter().is_none() {
+        warn!("Unknown log level '{}', using 'info'", config.log_level);
+    }
 
-    let config_file = File::open("iridium_weatherstation_config.json").unwrap();
-    let config: IWConfiguration = serde_json::from_reader(config_file).unwrap();
+    if config.accept_level().is_none() {
+        warn!("Unknown accept log level '{}', using 'info'", config.accept_log_level);
+    }
 
-    info!("Configuration was read successfully.");
+    if matches.is_present("init-db") {
+        match IWDatabase::new(&config).and_then(|database| database.map(|database| database.init_db()).transpose()) {
+            Ok(Some(_)) => {
+                println!("Database tables created");
+                return
+            }
+            Ok(None) => {
+                eprintln!("No database configured, add a mysql sink or set 'hostname' and 'db_name'");
+                std::process::exit(1)
+            }
+            Err(e) => {
+                eprintln!("Could not create the database tables: '{}'", e);
+                std::process::exit(1)
+            }
+        }
+    }
 
     debug!("Settings: {:?}", config);
 
-    start_server(&config);
+    let state = match start_server(&config) {
+        Ok(state) => state,
+        Err(e) => {
+            error!("Could not start the server: '{}'", e);
+            eprintln!("Could not start the server: {}", e);
+            std::process::exit(1)
+        }
+    };
+
+    let handler_state = state.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_state.request_shutdown()) {
+        error!("Could not install the signal handler: '{}'", e);
+    }
+
+    let mut last_alive = Instant::now();
+    info!("Alive message");
+
+    while !state.is_shutdown() {
+        sleep(Duration::from_secs(1));
 
-    loop {
-        info!("Alive message");
-        sleep(Duration::from_secs(config.alive_message_intervall));
+        if last_alive.elapsed() >= Duration::from_secs(config.alive_message_intervall) {
+            info!("Alive message");
+            last_alive = Instant::now();
+        }
     }
+
+    info!("Shutdown requested, draining connections");
+    let (drained, closed) = state.wait_for_connections(Duration::from_secs(config.shutdown_drain_secs));
+    info!("Connections drained: '{}', closed after the drain timeout: '{}'", drained, closed);
+    info!("Shutdown complete");
 }
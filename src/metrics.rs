@@ -0,0 +1,243 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::error::IWError;
+
+
+const INGEST_COUNTERS: [&str; 3] = ["iw_bytes_received_total", "iw_frames_parsed_total", "iw_db_insert_failures_total"];
+
+#[derive(Clone, Default, Debug)]
+struct IWIngestCounters {
+    bytes_received: u64,
+    frames_parsed: u64,
+    db_insert_failures: u64,
+    last_record: Option<NaiveDateTime>,
+}
+
+impl IWIngestCounters {
+    // Same order as INGEST_COUNTERS
+    fn values(&self) -> [u64; 3] {
+        [self.bytes_received, self.frames_parsed, self.db_insert_failures]
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct IWMetrics {
+    // (station, error kind) -> count
+    parse_errors: Mutex<BTreeMap<(String, &'static str), u64>>,
+    // Only collected when a metrics port or StatsD is configured
+    ingest_enabled: bool,
+    ingest: Mutex<BTreeMap<String, IWIngestCounters>>,
+    // Last time data came in for a station
+    last_seen: Mutex<HashMap<String, Instant>>,
+    // Labels of stations without data for this long are removed, e.g. after a rename
+    label_ttl: Option<Duration>,
+}
+
+impl IWMetrics {
+    pub fn new(label_ttl: Option<Duration>, ingest_enabled: bool) -> Self {
+        IWMetrics {
+            label_ttl,
+            ingest_enabled,
+            ..Default::default()
+        }
+    }
+
+    pub fn touch(&self, station: &str, now: Instant) {
+        self.last_seen.lock().unwrap().insert(station.to_string(), now);
+    }
+
+    pub fn inc_parse_error(&self, station: &str, error: &IWError) {
+        self.touch(station, Instant::now());
+        let mut parse_errors = self.parse_errors.lock().unwrap();
+        *parse_errors.entry((station.to_string(), error.kind())).or_default() += 1;
+    }
+
+    fn update_ingest<F: FnOnce(&mut IWIngestCounters)>(&self, station: &str, update: F) {
+        if !self.ingest_enabled {
+            return
+        }
+
+        self.touch(station, Instant::now());
+        update(self.ingest.lock().unwrap().entry(station.to_string()).or_default());
+    }
+
+    pub fn add_bytes_received(&self, station: &str, bytes: usize) {
+        self.update_ingest(station, |counters| counters.bytes_received += bytes as u64);
+    }
+
+    pub fn inc_frames_parsed(&self, station: &str) {
+        self.update_ingest(station, |counters| counters.frames_parsed += 1);
+    }
+
+    pub fn inc_db_insert_failure(&self, station: &str) {
+        self.update_ingest(station, |counters| counters.db_insert_failures += 1);
+    }
+
+    pub fn set_last_record(&self, station: &str, timestamp: NaiveDateTime) {
+        self.update_ingest(station, |counters| {
+            if counters.last_record.is_none_or(|last_record| timestamp > last_record) {
+                counters.last_record = Some(timestamp);
+            }
+        });
+    }
+
+    pub fn expire_stale_labels(&self, now: Instant) {
+        let label_ttl = match self.label_ttl {
+            Some(label_ttl) => label_ttl,
+            None => return,
+        };
+
+        let mut last_seen = self.last_seen.lock().unwrap();
+        last_seen.retain(|_, seen| now.saturating_duration_since(*seen) <= label_ttl);
+
+        self.parse_errors.lock().unwrap().retain(|(station, _), _| last_seen.contains_key(station));
+        self.ingest.lock().unwrap().retain(|station, _| last_seen.contains_key(station));
+    }
+
+    #[cfg(test)]
+    pub fn parse_errors(&self, station: &str, kind: &str) -> u64 {
+        let parse_errors = self.parse_errors.lock().unwrap();
+        parse_errors.iter()
+            .find(|((s, k), _)| s == station && *k == kind)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    // Current value of every counter: (station, error kind) -> count
+    pub fn parse_error_counts(&self) -> BTreeMap<(String, &'static str), u64> {
+        self.expire_stale_labels(Instant::now());
+        self.parse_errors.lock().unwrap().clone()
+    }
+
+    // Current value of every ingest counter: (station, counter name) -> count
+    pub fn ingest_counts(&self) -> BTreeMap<(String, &'static str), u64> {
+        self.expire_stale_labels(Instant::now());
+        let ingest = self.ingest.lock().unwrap();
+
+        ingest.iter().flat_map(|(station, counters)| {
+            INGEST_COUNTERS.iter().zip(counters.values()).map(move |(name, count)| ((station.clone(), *name), count))
+        }).collect()
+    }
+
+    // Seconds since the newest record of every station
+    pub fn last_record_ages(&self, now: NaiveDateTime) -> BTreeMap<String, i64> {
+        self.expire_stale_labels(Instant::now());
+        let ingest = self.ingest.lock().unwrap();
+
+        ingest.iter().filter_map(|(station, counters)| {
+            counters.last_record.map(|last_record| (station.clone(), (now - last_record).num_seconds()))
+        }).collect()
+    }
+
+    // Prometheus text format
+    pub fn render(&self) -> String {
+        self.render_at(Utc::now().naive_utc())
+    }
+
+    // The age of the last record is relative to now
+    fn render_at(&self, now: NaiveDateTime) -> String {
+        self.expire_stale_labels(Instant::now());
+        let mut result = String::new();
+
+        result.push_str("# TYPE iw_parse_errors_total counter\n");
+
+        for ((station, kind), count) in self.parse_errors.lock().unwrap().iter() {
+            let _ = writeln!(result, "iw_parse_errors_total{{station=\"{}\",kind=\"{}\"}} {}", station, kind, count);
+        }
+
+        if !self.ingest_enabled {
+            return result
+        }
+
+        let ingest = self.ingest.lock().unwrap();
+
+        for (i, name) in INGEST_COUNTERS.iter().enumerate() {
+            let _ = writeln!(result, "# TYPE {} counter", name);
+
+            for (station, station_counters) in ingest.iter() {
+                let _ = writeln!(result, "{}{{station=\"{}\"}} {}", name, station, station_counters.values()[i]);
+            }
+        }
+
+        result.push_str("# TYPE iw_last_record_age_seconds gauge\n");
+
+        for (station, station_counters) in ingest.iter() {
+            if let Some(last_record) = station_counters.last_record {
+                let _ = writeln!(result, "iw_last_record_age_seconds{{station=\"{}\"}} {}", station, (now - last_record).num_seconds());
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use chrono::NaiveDateTime;
+
+    use super::IWMetrics;
+
+    use crate::error::IWError;
+
+    #[test]
+    fn test_expire_stale_labels() {
+        let metrics = IWMetrics::new(Some(Duration::from_secs(60)), false);
+        let now = Instant::now();
+
+        metrics.inc_parse_error("Nahuelbuta", &IWError::InvalidDataHeader);
+        assert!(metrics.render().contains("station=\"Nahuelbuta\""));
+
+        // The station was renamed, only the new name gets data from now on
+        metrics.touch("Nahuelbuta Norte", now + Duration::from_secs(120));
+        metrics.expire_stale_labels(now + Duration::from_secs(120));
+
+        assert_eq!(metrics.parse_errors("Nahuelbuta", "InvalidDataHeader"), 0);
+        assert!(!metrics.render().contains("station=\"Nahuelbuta\""));
+
+        // Without a TTL nothing expires
+        let metrics = IWMetrics::new(None, false);
+        metrics.inc_parse_error("Nahuelbuta", &IWError::InvalidDataHeader);
+        metrics.expire_stale_labels(now + Duration::from_secs(3600 * 24 * 365));
+
+        assert_eq!(metrics.parse_errors("Nahuelbuta", "InvalidDataHeader"), 1);
+    }
+
+    #[test]
+    fn test_ingest_metrics() {
+        let datetime = |s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+
+        let metrics = IWMetrics::new(None, true);
+        metrics.add_bytes_received("Nahuelbuta", 79);
+        metrics.add_bytes_received("Nahuelbuta", 79);
+        metrics.inc_frames_parsed("Nahuelbuta");
+        metrics.inc_db_insert_failure("Nahuelbuta");
+        metrics.set_last_record("Nahuelbuta", datetime("2022-04-03 13:00:00"));
+        metrics.set_last_record("Nahuelbuta", datetime("2022-04-03 12:00:00"));
+
+        let text = metrics.render_at(datetime("2022-04-03 13:30:00"));
+
+        assert!(text.contains("# TYPE iw_bytes_received_total counter\niw_bytes_received_total{station=\"Nahuelbuta\"} 158\n"));
+        assert!(text.contains("iw_frames_parsed_total{station=\"Nahuelbuta\"} 1\n"));
+        assert!(text.contains("iw_db_insert_failures_total{station=\"Nahuelbuta\"} 1\n"));
+        assert!(text.contains("# TYPE iw_last_record_age_seconds gauge\niw_last_record_age_seconds{station=\"Nahuelbuta\"} 1800\n"));
+
+        // Nothing is collected without a metrics port
+        let metrics = IWMetrics::new(None, false);
+        metrics.add_bytes_received("Nahuelbuta", 79);
+
+        assert!(!metrics.render().contains("iw_bytes_received_total"));
+    }
+}
@@ -0,0 +1,136 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::thread::{spawn, sleep};
+use std::time::Duration;
+
+use log::{debug, warn};
+use rumqttc::{Client, MqttOptions, QoS};
+
+use crate::config::{IWConfiguration, IWSinkConfig};
+use crate::error::IWError;
+use crate::process_data::IWStationData;
+
+
+// Wait time before the next connection attempt after the broker went away
+const MQTT_RECONNECT_DELAY_SECS: u64 = 5;
+// Messages queued while the broker is not reachable, newer ones are dropped after that
+const MQTT_QUEUE_CAPACITY: usize = 1000;
+
+pub struct IWMqttPublisher {
+    client: Client,
+    topic_prefix: String,
+}
+
+impl std::fmt::Debug for IWMqttPublisher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("IWMqttPublisher").field("topic_prefix", &self.topic_prefix).finish_non_exhaustive()
+    }
+}
+
+// One (topic, JSON payload) pair per record
+pub fn mqtt_messages(topic_prefix: &str, station_name: &str, data: &IWStationData) -> Result<Vec<(String, String)>, IWError> {
+    match data {
+        IWStationData::SingleData(status) => {
+            Ok(vec![(format!("{}/{}/status", topic_prefix, station_name), serde_json::to_string(status)?)])
+        }
+        IWStationData::MultipleData(data) => {
+            data.iter()
+                .map(|entry| Ok((format!("{}/{}/weather", topic_prefix, station_name), serde_json::to_string(entry)?)))
+                .collect()
+        }
+    }
+}
+
+impl IWMqttPublisher {
+    // None without an MQTT sink
+    pub fn new(config: &IWConfiguration) -> Option<Self> {
+        let (host, port, topic_prefix) = config.output_sinks().into_iter().find_map(|sink| match sink {
+            IWSinkConfig::Mqtt { host, port, topic_prefix, .. } => Some((host, port, topic_prefix)),
+            _ => None,
+        })?;
+
+        let mut options = MqttOptions::new(format!("iridium_weatherstation_{}", std::process::id()), &host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+
+        let (client, mut connection) = Client::new(options, MQTT_QUEUE_CAPACITY);
+
+        // The event loop reconnects on the next iteration after an error
+        spawn(move || {
+            for event in connection.iter() {
+                if let Err(e) = event {
+                    warn!("MQTT connection error: '{}'", e);
+                    sleep(Duration::from_secs(MQTT_RECONNECT_DELAY_SECS));
+                }
+            }
+        });
+
+        debug!("MQTT publisher for broker: '{}:{}'", host, port);
+
+        Some(IWMqttPublisher { client, topic_prefix })
+    }
+
+    // Never blocks, messages that do not fit into the queue are dropped
+    pub fn publish(&self, station_name: &str, data: &IWStationData) -> Result<(), IWError> {
+        for (topic, payload) in mqtt_messages(&self.topic_prefix, station_name, data)? {
+            if let Err(e) = self.client.try_publish(topic, QoS::AtLeastOnce, false, payload) {
+                warn!("MQTT message dropped: '{}'", e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::mqtt_messages;
+
+    use crate::process_data::{IWStationData, IWLoggerStatus, IWWeatherData};
+
+    #[test]
+    fn test_mqtt_messages() {
+        let entry = IWWeatherData {
+            timestamp: "2022-04-03 13:00:00".to_string(),
+            air_temperature: 16.57,
+            air_relative_humidity: 76.58,
+            solar_radiation: 820.0,
+            soil_water_content: 0.048,
+            soil_temperature: 20.6,
+            wind_speed: 6.046,
+            wind_max: 8.25,
+            wind_direction: 342.5,
+            precipitation: 0.0,
+            air_pressure: 978.0,
+            precipitation_raw: None,
+        };
+        let data = IWStationData::MultipleData(vec![entry.clone(), IWWeatherData { timestamp: "2022-04-03 14:00:00".to_string(), ..entry }]);
+
+        let messages = mqtt_messages("iridium", "Nahuelbuta", &data).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].0, "iridium/Nahuelbuta/weather");
+
+        let payload: serde_json::Value = serde_json::from_str(&messages[1].1).unwrap();
+        assert_eq!(payload["timestamp"], "2022-04-03 14:00:00");
+        assert_eq!(payload["air_temperature"], 16.57);
+
+        let data = IWStationData::SingleData(IWLoggerStatus {
+            timestamp: "2022-04-05 00:00:00".to_string(),
+            solar_battery: 12.47,
+            lithium_battery: 3.369,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        });
+
+        let messages = mqtt_messages("iridium", "Nahuelbuta", &data).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, "iridium/Nahuelbuta/status");
+        assert!(messages[0].1.contains("\"solar_battery\":12.47"));
+    }
+}
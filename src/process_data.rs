@@ -5,30 +5,71 @@
 // A simple data processing tool written in Rust for one of the campbell iridium weather stations
 //
 
-use std::net::{TcpListener, TcpStream, SocketAddr};
+use std::net::{TcpListener, TcpStream, SocketAddr, IpAddr, Shutdown};
 use std::io::{Read, Write, Cursor};
-use std::fs::File;
-use std::f64::{INFINITY, NEG_INFINITY, NAN};
-use std::thread::spawn;
+use std::fs::{self, File};
+use std::thread::{spawn, sleep};
+use std::time::{Duration as StdDuration, Instant};
 use std::path::Path;
-
-use log::{info, debug, error};
-use chrono::{Local, NaiveDateTime, Duration};
-use byteorder::{LittleEndian, BigEndian, ReadBytesExt};
-
-use crate::config::IWConfiguration;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::sync_channel;
+use std::sync::atomic::{AtomicBool, AtomicUsize, AtomicU64, Ordering};
+use std::collections::{HashMap, HashSet};
+
+use log::{info, debug, warn, error, log, Level};
+use chrono::{Local, DateTime, NaiveDateTime, Duration, TimeZone};
+use chrono_tz::Tz;
+use byteorder::{LittleEndian, BigEndian, ReadBytesExt, ByteOrder};
+use serde_derive::{Serialize, Deserialize};
+use socket2::{Socket, Domain, Type, Protocol};
+
+use crate::config::{IWConfiguration, IWStationConfiguration, IWPollTarget, IWFileGranularity, IWChecksum, IWIdentityField, IWIdentityEncoding, IWPrecipMode, IWSinkConfig, IWMetricsBackend, IWEndian, IWFileDedup, IWNanPolicy, IWSchema, IWSpoolOverflow, DEFAULT_SCHEMA_VERSION, default_epoch_base};
+use crate::database::IWDatabase;
 use crate::error::IWError;
+use crate::health::IWHealthCache;
+use crate::http_server::start_http_server;
+use crate::metrics::IWMetrics;
+use crate::mqtt::IWMqttPublisher;
+use crate::rate_limit::IWRateLimiter;
+use crate::statsd::start_statsd_emitter;
+use crate::wal::{IWWal, IWWalEntry, WAL_SINK_CSV, WAL_SINK_DATABASE, WAL_SINK_JSON, WAL_SINK_MQTT};
+use crate::websocket::IWWebSocketClients;
 
 
 const HEADER_LENGTH1: usize = 48;
 const HEADER_LENGTH2: usize = 3;
+const STATION_ID_LENGTH: usize = 2;
+const LISTENER_POLL_INTERVAL_MS: u64 = 20;
 const ULONG_LEN: usize = 4;
 const FP2_LEN: usize = 2;
+const IEEE4_LEN: usize = 4;
 
 const LOGGER_STATUS1_LENGTH: usize = (2 * ULONG_LEN) + (3 * FP2_LEN);
 const LOGGER_STATUS2_LENGTH: usize = (3 * ULONG_LEN) + (3 * FP2_LEN);
+// Newer loggers append the battery temperature
+const LOGGER_STATUS3_LENGTH: usize = (3 * ULONG_LEN) + (4 * FP2_LEN);
 const WEATHER_DATA_LENGTH: usize =  (2 * ULONG_LEN) + (10 * FP2_LEN);
 
+// Field order in a weather record
+pub const WEATHER_FIELDS: [&str; 10] = ["air_temperature", "air_relative_humidity", "solar_radiation", "soil_water_content",
+    "soil_temperature", "wind_speed", "wind_max", "wind_direction", "precipitation", "air_pressure"];
+
+const F2_POS_INFINITY: u16 = 0b00011111_11111111; // 31, 255
+const F2_NEG_INFINITY: u16 = 0b10011111_11111111; // 159, 255
+const F2_NAN: u16 = 0b10011111_11111110; // 159, 254
+const F2_MAX_MANTISSA: f64 = 7999.0;
+
+
+fn port_to_folder(port: u16) -> &'static str {
+    match port {
+        2100 => "2100_Na",
+        2101 => "2101_SG",
+        2102 => "2102_PdA",
+        2103 => "2103_LC",
+        2104 => "2104_Tue",
+        _ => "unknown",
+    }
+}
 
 // TODO: Read mapping from configuration file
 fn port_to_station(port: u16) -> String{
@@ -44,16 +85,84 @@ fn port_to_station(port: u16) -> String{
     }
 }
 
-#[derive(Clone, PartialEq, Debug)]
+// The logger writes the CF card field as signed integer, negative values are error codes.
+// The raw value is kept as u32, so the CSV and database columns do not change.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IWCfCardStatus {
+    Ok(i32),
+    CardFault,
+    Error(i32),
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct IWLoggerStatus {
     pub timestamp: String,
     pub solar_battery: f64,
     pub lithium_battery: f64,
     pub wind_diag: f64,
     pub cf_card: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery_temperature: Option<f64>,
+}
+
+impl IWLoggerStatus {
+    pub fn cf_card_status(&self) -> IWCfCardStatus {
+        match self.cf_card as i32 {
+            -129 => IWCfCardStatus::CardFault,
+            value if value < 0 => IWCfCardStatus::Error(value),
+            value => IWCfCardStatus::Ok(value),
+        }
+    }
+
+    // Implausible voltages usually mean that the status was decoded from misaligned bytes
+    pub fn validate(&self, config: &IWConfiguration) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let (solar_min, solar_max) = config.solar_battery_range;
+        if !(solar_min..=solar_max).contains(&self.solar_battery) {
+            problems.push(format!("solar battery '{}' V outside of [{}, {}]", self.solar_battery, solar_min, solar_max));
+        }
+
+        let (lithium_min, lithium_max) = config.lithium_battery_range;
+        if !(lithium_min..=lithium_max).contains(&self.lithium_battery) {
+            problems.push(format!("lithium battery '{}' V outside of [{}, {}]", self.lithium_battery, lithium_min, lithium_max));
+        }
+
+        if self.lithium_battery >= self.solar_battery {
+            problems.push(format!("lithium battery '{}' V not below solar battery '{}' V", self.lithium_battery, self.solar_battery));
+        }
+
+        let fields = [("solar_battery", self.solar_battery), ("lithium_battery", self.lithium_battery), ("wind_diag", self.wind_diag),
+            ("battery_temperature", self.battery_temperature.unwrap_or(f64::NAN))];
+
+        for (field, value) in fields {
+            if value == 0.0 && config.reject_zero.iter().any(|name| name == field) {
+                problems.push(format!("{} is zero", field));
+            }
+        }
+
+        problems
+    }
+}
+
+// Logs a tagged warning when a battery is below its minimum, returns true in that case
+pub fn check_battery(station_name: &str, status: &IWLoggerStatus, config: &IWConfiguration) -> bool {
+    let mut low = false;
+
+    if status.solar_battery < config.solar_battery_min {
+        warn!("[LOW BATTERY] Station '{}', solar battery '{}' V below '{}' V at '{}'", station_name, status.solar_battery, config.solar_battery_min, status.timestamp);
+        low = true;
+    }
+
+    if status.lithium_battery < config.lithium_battery_min {
+        warn!("[LOW BATTERY] Station '{}', lithium battery '{}' V below '{}' V at '{}'", station_name, status.lithium_battery, config.lithium_battery_min, status.timestamp);
+        low = true;
+    }
+
+    low
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct IWWeatherData {
     pub timestamp: String,
     pub air_temperature: f64,
@@ -66,22 +175,218 @@ pub struct IWWeatherData {
     pub wind_direction: f64,
     pub precipitation: f64,
     pub air_pressure: f64,
+    // Tip count before the conversion to mm, only with store_raw
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub precipitation_raw: Option<f64>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+impl IWWeatherData {
+    // Same order as WEATHER_FIELDS
+    pub fn field_values(&self) -> [f64; 10] {
+        [self.air_temperature, self.air_relative_humidity, self.solar_radiation, self.soil_water_content, self.soil_temperature,
+            self.wind_speed, self.wind_max, self.wind_direction, self.precipitation, self.air_pressure]
+    }
+
+    // A corrupted FP2 word decodes to a valid but absurd number, sentinel values (NAN, +/-INF) are not checked
+    pub fn validate(&self, config: &IWConfiguration) -> Vec<IWError> {
+        WEATHER_FIELDS.iter().zip(self.field_values())
+            .filter(|(_, value)| value.is_finite())
+            .filter(|(field, value)| config.weather_range(field).is_some_and(|(min, max)| !(min..=max).contains(value)))
+            .map(|(field, value)| IWError::OutOfRange { field: field.to_string(), value })
+            .collect()
+    }
+
+    // Fields holding one of the sentinel values, these end up as NULL in the database
+    pub fn special_fields(&self) -> Vec<&'static str> {
+        WEATHER_FIELDS.iter().copied().zip(self.field_values())
+            .filter(|(_, value)| !value.is_finite())
+            .map(|(field, _)| field)
+            .collect()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum IWStationData {
     SingleData(IWLoggerStatus),
     MultipleData(Vec<IWWeatherData>),
 }
 
-fn u32_to_timestamp(seconds: u32) -> String {
-    let datetime_base = NaiveDateTime::parse_from_str("1990-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
-    let result = datetime_base + Duration::seconds(seconds as i64);
-    // YYYY-DD-MM HH:MM:SS
+#[derive(Debug)]
+pub struct IWServerState {
+    pub config: IWConfiguration,
+    pub health: IWHealthCache,
+    pub precipitation_totals: Mutex<HashMap<String, f64>>,
+    pub wal: Option<IWWal>,
+    // Packets the database did not take, only there when a database is configured
+    pub db_buffer: Option<IWWal>,
+    // Held while the buffer is written to the database, so no packet is inserted twice
+    db_buffer_replay: Mutex<()>,
+    pub websocket: IWWebSocketClients,
+    pub sinks: Vec<IWSinkConfig>,
+    pub metrics: IWMetrics,
+    pub rate_limiter: Option<IWRateLimiter>,
+    // Receives the data instead of the CSV files in test mode
+    pub memory_sink: Mutex<Vec<IWWalEntry>>,
+    pub database: Option<IWDatabase>,
+    pub mqtt: Option<IWMqttPublisher>,
+    pub epoch_base: NaiveDateTime,
+    // Ports with at least one listener, reported by the health check
+    pub bound_ports: Vec<u16>,
+    // Stations without a time zone, the warning is only logged once per station
+    timezone_warned: Mutex<HashSet<String>>,
+    shutdown: AtomicBool,
+    in_flight: AtomicUsize,
+    // Clones of the accepted streams, to close them when the drain timeout is over
+    open_streams: Mutex<HashMap<u64, TcpStream>>,
+    next_stream_id: AtomicU64,
+}
+
+impl IWServerState {
+    pub fn new(config: IWConfiguration) -> Result<Self, IWError> {
+        let wal = match config.wal_dir {
+            Some(ref wal_dir) if !config.writes_disabled() => Some(IWWal::new(wal_dir, config.wal_fsync)?),
+            _ => None,
+        };
+
+        let epoch_base = config.epoch_base()?;
+        let sinks = config.output_sinks();
+        let ingest_enabled = config.metrics_port.is_some() || config.metrics_backend == IWMetricsBackend::Statsd;
+        let metrics = IWMetrics::new(config.metrics_label_ttl_secs.map(StdDuration::from_secs), ingest_enabled);
+        let rate_limiter = config.connection_rate_limit.map(|rate| IWRateLimiter::new(rate, config.connection_burst));
+        let precipitation_totals = load_precipitation_totals(&config.precip_state_file);
+
+        Ok(IWServerState {
+            config,
+            health: IWHealthCache::new(),
+            precipitation_totals: Mutex::new(precipitation_totals),
+            wal,
+            db_buffer: None,
+            db_buffer_replay: Mutex::new(()),
+            websocket: IWWebSocketClients::new(),
+            sinks,
+            metrics,
+            rate_limiter,
+            memory_sink: Mutex::new(Vec::new()),
+            database: None,
+            mqtt: None,
+            epoch_base,
+            bound_ports: Vec::new(),
+            timezone_warned: Mutex::new(HashSet::new()),
+            shutdown: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            open_streams: Mutex::new(HashMap::new()),
+            next_stream_id: AtomicU64::new(0),
+        })
+    }
+
+    // Level of the routine lines logged for every connection
+    fn accept_level(&self) -> Level {
+        self.config.accept_level().unwrap_or(Level::Info)
+    }
+
+    pub fn csv_enabled(&self) -> bool {
+        self.sinks.iter().any(|sink| matches!(sink, IWSinkConfig::Csv { .. }))
+    }
+
+    pub fn websocket_enabled(&self) -> bool {
+        self.sinks.iter().any(|sink| matches!(sink, IWSinkConfig::WebSocket { .. }))
+    }
+
+    pub fn request_shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    // All files are closed after every write, so once no connection is left nothing can be truncated.
+    // Connections still open after the timeout are closed, returns the number of drained and closed connections.
+    pub fn wait_for_connections(&self, timeout: StdDuration) -> (usize, usize) {
+        let start = Instant::now();
+        let in_flight = self.in_flight.load(Ordering::SeqCst);
+
+        while self.in_flight.load(Ordering::SeqCst) > 0 {
+            if start.elapsed() >= timeout {
+                let open_streams = self.open_streams.lock().unwrap();
+
+                for stream in open_streams.values() {
+                    let _ = stream.shutdown(Shutdown::Both);
+                }
+
+                let closed = open_streams.len();
+                return (in_flight.saturating_sub(closed), closed)
+            }
+
+            sleep(StdDuration::from_millis(LISTENER_POLL_INTERVAL_MS));
+        }
+
+        (in_flight, 0)
+    }
+}
+
+struct IWInFlightGuard<'a> {
+    state: &'a IWServerState,
+    stream_id: Option<u64>,
+}
+
+impl<'a> IWInFlightGuard<'a> {
+    // Counts the connection as in flight, an accepted stream can be closed by the shutdown
+    fn new(state: &'a IWServerState, stream: Option<&TcpStream>) -> Self {
+        state.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        let stream_id = stream.and_then(|stream| stream.try_clone().ok()).map(|stream| {
+            let id = state.next_stream_id.fetch_add(1, Ordering::SeqCst);
+            state.open_streams.lock().unwrap().insert(id, stream);
+            id
+        });
+
+        IWInFlightGuard { state, stream_id }
+    }
+}
+
+impl Drop for IWInFlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.stream_id {
+            self.state.open_streams.lock().unwrap().remove(&id);
+        }
+
+        self.state.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn u32_to_timestamp(seconds: u32, epoch_base: NaiveDateTime) -> String {
+    let result = epoch_base + Duration::seconds(seconds as i64);
+    // YYYY-MM-DD HH:MM:SS
     result.format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
-fn u16_to_f64(data: u16) -> f64 {
+pub fn timestamp_to_datetime(timestamp: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").ok()
+}
+
+// Local logger time to UTC, a time inside a DST gap is left unchanged
+fn timestamp_to_utc(timestamp: &str, timezone: Tz) -> String {
+    timestamp_to_datetime(timestamp)
+        .and_then(|datetime| timezone.from_local_datetime(&datetime).earliest())
+        .map(|datetime| datetime.naive_utc().format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn convert_to_utc(data: &mut IWStationData, timezone: Tz) {
+    match data {
+        IWStationData::SingleData(status) => {
+            status.timestamp = timestamp_to_utc(&status.timestamp, timezone);
+        }
+        IWStationData::MultipleData(data) => {
+            for entry in data.iter_mut() {
+                entry.timestamp = timestamp_to_utc(&entry.timestamp, timezone);
+            }
+        }
+    }
+}
+
+pub fn u16_to_f64(data: u16) -> f64 {
     // base16 2 byte floats:
     // https://en.wikipedia.org/wiki/Half-precision_floating-point_format
     // https://github.com/sgothel/jogl/blob/master/src/jogl/classes/com/jogamp/opengl/math/Binary16.java
@@ -113,20 +418,19 @@ fn u16_to_f64(data: u16) -> f64 {
     // 962 = 194 + (3 * 256) = 00000011 11000011 -> 963.0
     // 25576 = 232 + (99 * 256) = 01100011 11101000 -> 1.0
 
-    const F2_POS_INFINITY: u16 = 0b00011111_11111111; // 31, 255
-    const F2_NEG_INFINITY: u16 = 0b10011111_11111111; // 159, 255
-    const F2_NAN: u16 = 0b10011111_11111110; // 159, 254
-
     if data == F2_POS_INFINITY {
-        INFINITY
+        f64::INFINITY
     } else if data == F2_NEG_INFINITY {
-        NEG_INFINITY
+        f64::NEG_INFINITY
     } else if data == F2_NAN {
-        NAN
+        f64::NAN
     } else {
-        let sign = if data & 0b10000000_00000000 == 0 { 1.0 } else { - 1.0 };
+        let magnitude = data & 0b00011111_11111111;
 
-        let mantissa: f64 = ((data & 0b00011111_11111111) as f64) * sign;
+        // A set sign bit with a zero mantissa would give -0.0, report it as plain zero
+        let sign = if data & 0b10000000_00000000 == 0 || magnitude == 0 { 1.0 } else { - 1.0 };
+
+        let mantissa: f64 = (magnitude as f64) * sign;
         let exponent: u16 = (data & 0b01100000_00000000) >> 13;
 
         match exponent {
@@ -138,7 +442,46 @@ fn u16_to_f64(data: u16) -> f64 {
     }
 }
 
-fn parse_logger_status1(buffer: &[u8]) -> Result<IWStationData, IWError> {
+// Not used by the server itself, only for test fixtures and simulators
+pub fn f64_to_u16(value: f64) -> u16 {
+    f64_to_u16_with_loss(value, f64::INFINITY).0
+}
+
+pub fn f64_to_u16_with_loss(value: f64, warn_threshold: f64) -> (u16, f64) {
+    // Reverse of u16_to_f64: pick the decimal position that keeps the most digits
+    // while the mantissa stays below the allowed maximum of 7999.
+    // Also returns the value that is actually encoded, so the caller can see the precision loss.
+
+    if value.is_nan() {
+        return (F2_NAN, f64::NAN)
+    }
+
+    let sign: u16 = if value < 0.0 { 0b10000000_00000000 } else { 0 };
+    let magnitude = value.abs();
+
+    // Too large for FP2, the loggers report this as infinity
+    let mut result = if sign == 0 { F2_POS_INFINITY } else { F2_NEG_INFINITY };
+
+    for exponent in (0..4).rev() {
+        let mantissa = (magnitude * 10.0_f64.powi(exponent)).round();
+
+        if mantissa <= F2_MAX_MANTISSA {
+            result = sign | ((exponent as u16) << 13) | (mantissa as u16);
+            break
+        }
+    }
+
+    let achieved = u16_to_f64(result);
+    let loss = (value - achieved).abs();
+
+    if loss > warn_threshold {
+        warn!("FP2 precision loss: '{}' encoded as '{}' (loss: '{}')", value, achieved, loss);
+    }
+
+    (result, achieved)
+}
+
+fn parse_logger_status1(buffer: &[u8], epoch_base: NaiveDateTime) -> Result<IWStationData, IWError> {
     let mut read_bytes = Cursor::new(buffer);
 
     // Time stamp
@@ -152,17 +495,18 @@ fn parse_logger_status1(buffer: &[u8]) -> Result<IWStationData, IWError> {
     let wind_diag = read_bytes.read_u16::<BigEndian>()?;
 
     let result = IWLoggerStatus {
-        timestamp: u32_to_timestamp(seconds),
+        timestamp: u32_to_timestamp(seconds, epoch_base),
         solar_battery: u16_to_f64(solar_battery_voltage),
         lithium_battery: u16_to_f64(lithium_battery_voltage),
         wind_diag: u16_to_f64(wind_diag),
         cf_card: 0,
+        battery_temperature: None,
     };
 
     Ok(IWStationData::SingleData(result))
 }
 
-fn parse_logger_status2(buffer: &[u8]) -> Result<IWStationData, IWError> {
+fn parse_logger_status2(buffer: &[u8], epoch_base: NaiveDateTime) -> Result<IWStationData, IWError> {
     let mut read_bytes = Cursor::new(buffer);
 
     // Time stamp
@@ -177,18 +521,19 @@ fn parse_logger_status2(buffer: &[u8]) -> Result<IWStationData, IWError> {
     let cf_card = read_bytes.read_u32::<BigEndian>()?;
 
     let result = IWLoggerStatus {
-        timestamp: u32_to_timestamp(seconds),
+        timestamp: u32_to_timestamp(seconds, epoch_base),
         solar_battery: u16_to_f64(solar_battery_voltage),
         lithium_battery: u16_to_f64(lithium_battery_voltage),
         wind_diag: u16_to_f64(wind_diag),
         cf_card,
+        battery_temperature: None,
     };
 
     Ok(IWStationData::SingleData(result))
 }
 
-fn parse_weather_data_single(buffer: &[u8]) -> Result<IWWeatherData, IWError> {
-    let mut read_bytes = Cursor::new(&buffer);
+fn parse_logger_status3(buffer: &[u8], epoch_base: NaiveDateTime) -> Result<IWStationData, IWError> {
+    let mut read_bytes = Cursor::new(buffer);
 
     // Time stamp
     let seconds = read_bytes.read_u32::<LittleEndian>()?;
@@ -196,366 +541,4143 @@ fn parse_weather_data_single(buffer: &[u8]) -> Result<IWWeatherData, IWError> {
     // Should be zero, not needed
     let _ = read_bytes.read_u32::<LittleEndian>()?;
 
-    let air_temperature = read_bytes.read_u16::<BigEndian>()?;
-    let air_relative_humidity = read_bytes.read_u16::<BigEndian>()?;
-    let solar_radiation = read_bytes.read_u16::<BigEndian>()?;
-    let soil_water_content = read_bytes.read_u16::<BigEndian>()?;
-    let soil_temperature = read_bytes.read_u16::<BigEndian>()?;
-    let wind_speed = read_bytes.read_u16::<BigEndian>()?;
-    let wind_max = read_bytes.read_u16::<BigEndian>()?;
-    let wind_direction = read_bytes.read_u16::<BigEndian>()?;
-    let precipitation = read_bytes.read_u16::<BigEndian>()?;
-    let air_pressure = read_bytes.read_u16::<BigEndian>()?;
+    let solar_battery_voltage = read_bytes.read_u16::<BigEndian>()?;
+    let lithium_battery_voltage = read_bytes.read_u16::<BigEndian>()?;
+    let wind_diag = read_bytes.read_u16::<BigEndian>()?;
+    let cf_card = read_bytes.read_u32::<BigEndian>()?;
+    let battery_temperature = read_bytes.read_u16::<BigEndian>()?;
 
-    let result = IWWeatherData {
-        timestamp: u32_to_timestamp(seconds),
-        air_temperature: u16_to_f64(air_temperature),
-        air_relative_humidity: u16_to_f64(air_relative_humidity),
-        solar_radiation: u16_to_f64(solar_radiation),
-        soil_water_content: u16_to_f64(soil_water_content),
-        soil_temperature: u16_to_f64(soil_temperature),
-        wind_speed: u16_to_f64(wind_speed),
-        wind_max: u16_to_f64(wind_max),
-        wind_direction: u16_to_f64(wind_direction),
-        precipitation: u16_to_f64(precipitation),
-        air_pressure: u16_to_f64(air_pressure),
+    let result = IWLoggerStatus {
+        timestamp: u32_to_timestamp(seconds, epoch_base),
+        solar_battery: u16_to_f64(solar_battery_voltage),
+        lithium_battery: u16_to_f64(lithium_battery_voltage),
+        wind_diag: u16_to_f64(wind_diag),
+        cf_card,
+        battery_temperature: Some(u16_to_f64(battery_temperature)),
     };
 
-    Ok(result)
+    Ok(IWStationData::SingleData(result))
 }
 
-fn parse_weather_data(buffer: &[u8]) -> Result<IWStationData, IWError> {
-    let mut result = Vec::new();
+fn ieee4_to_f64(data: [u8; 4], endian: IWEndian) -> f64 {
+    let bits = match endian {
+        IWEndian::Big => u32::from_be_bytes(data),
+        IWEndian::Little => u32::from_le_bytes(data),
+    };
 
-    for chunk in buffer.chunks(WEATHER_DATA_LENGTH) {
-        result.push(parse_weather_data_single(chunk)?);
+    let value = f32::from_bits(bits);
+
+    if !value.is_finite() {
+        return value as f64
     }
 
-    Ok(IWStationData::MultipleData(result))
+    // Go through the shortest decimal representation, so that 16.57 stays 16.57
+    // and does not become 16.569999694824219
+    value.to_string().parse().unwrap_or(value as f64)
 }
 
-fn get_data_length(buffer: &[u8]) -> usize {
-    let high = buffer[1] as u16;
-    let low = buffer[2] as u16;
-    (low + (256 * high)) as usize
+fn is_ieee4_field(name: &str, station_config: &IWStationConfiguration) -> bool {
+    station_config.ieee4_fields.iter().any(|field| field == name)
 }
 
-fn parse_binary_data(buffer: &[u8]) -> Result<IWStationData, IWError> {
-    debug!("Parse binary data");
-
-    let buffer_len = buffer.len();
-    debug!("buffer_len: '{}'", buffer_len);
+fn weather_data_length(station_config: &IWStationConfiguration) -> usize {
+    let ieee4_count = WEATHER_FIELDS.iter().filter(|name| is_ieee4_field(name, station_config)).count();
+    WEATHER_DATA_LENGTH + (ieee4_count * (IEEE4_LEN - FP2_LEN))
+}
 
-    if buffer_len < LOGGER_STATUS1_LENGTH {
-        return Err(IWError::DataTooShort(buffer_len))
+fn read_weather_field(read_bytes: &mut Cursor<&[u8]>, name: &str, station_config: &IWStationConfiguration) -> Result<f64, IWError> {
+    if is_ieee4_field(name, station_config) {
+        let mut data = [0; IEEE4_LEN];
+        read_bytes.read_exact(&mut data)?;
+        Ok(ieee4_to_f64(data, station_config.ieee4_endian))
+    } else {
+        Ok(u16_to_f64(read_bytes.read_u16::<BigEndian>()?))
     }
+}
 
-    let data_len = get_data_length(buffer);
-    debug!("data_len: '{}'", data_len);
+fn parse_weather_data_single(buffer: &[u8], station_config: &IWStationConfiguration, epoch_base: NaiveDateTime) -> Result<IWWeatherData, IWError> {
+    let mut read_bytes = Cursor::new(buffer);
 
-    if data_len != buffer_len - HEADER_LENGTH2 {
-        return Err(IWError::DataLengthMismatch(data_len))
-    }
+    // Time stamp
+    let seconds = read_bytes.read_u32::<LittleEndian>()?;
 
-    if buffer[0] != 2 {
-        return Err(IWError::InvalidDataHeader)
-    }
+    // Should be zero, not needed
+    let _ = read_bytes.read_u32::<LittleEndian>()?;
 
-    let data_buffer = &buffer[HEADER_LENGTH2..];
+    let result = IWWeatherData {
+        timestamp: u32_to_timestamp(seconds, epoch_base),
+        air_temperature: read_weather_field(&mut read_bytes, "air_temperature", station_config)?,
+        air_relative_humidity: read_weather_field(&mut read_bytes, "air_relative_humidity", station_config)?,
+        solar_radiation: read_weather_field(&mut read_bytes, "solar_radiation", station_config)?,
+        soil_water_content: read_weather_field(&mut read_bytes, "soil_water_content", station_config)?,
+        soil_temperature: read_weather_field(&mut read_bytes, "soil_temperature", station_config)?,
+        wind_speed: read_weather_field(&mut read_bytes, "wind_speed", station_config)?,
+        wind_max: read_weather_field(&mut read_bytes, "wind_max", station_config)?,
+        wind_direction: read_weather_field(&mut read_bytes, "wind_direction", station_config)?,
+        precipitation: read_weather_field(&mut read_bytes, "precipitation", station_config)?,
+        air_pressure: read_weather_field(&mut read_bytes, "air_pressure", station_config)?,
+        precipitation_raw: None,
+    };
 
-    if data_len == LOGGER_STATUS1_LENGTH {
-        parse_logger_status1(data_buffer)
-    } else if data_len == LOGGER_STATUS2_LENGTH {
-        parse_logger_status2(data_buffer)
+    let result = if station_config.normalize_360_to_0 {
+        IWWeatherData {
+            wind_direction: normalize_wind_direction(result.wind_direction),
+            ..result
+        }
     } else {
-        parse_weather_data(data_buffer)
-    }
+        result
+    };
+
+    let result = match station_config.precip_mm_per_tip {
+        Some(mm_per_tip) => IWWeatherData {
+            precipitation: result.precipitation * mm_per_tip,
+            precipitation_raw: if station_config.store_raw { Some(result.precipitation) } else { None },
+            ..result
+        },
+        None => result,
+    };
+
+    Ok(result)
 }
 
-fn write_single_data(folder: &str, data: &IWLoggerStatus, name: &str) -> Result<(), IWError> {
-    let file_name = format!("{}/all_data_battery.csv", folder);
+// Some sensors report due north as 360, others as 0
+fn normalize_wind_direction(direction: f64) -> f64 {
+    if direction == 360.0 { 0.0 } else { direction }
+}
 
-    // TODO: use File::fn metadata(&self) -> Result<Metadata>
-    // and then Metadata::fn len(&self) -> u64
-    // instead of Path::exists()
+fn parse_weather_data(buffer: &[u8], station_config: &IWStationConfiguration, epoch_base: NaiveDateTime) -> Result<IWStationData, IWError> {
+    let mut result = Vec::new();
+    let record_length = weather_data_length(station_config);
+
+    for (i, chunk) in buffer.chunks(record_length).enumerate() {
+        if chunk.len() < record_length {
+            if chunk.iter().all(|byte| *byte == 0) {
+                // Zero padding after the last record, not a truncated record
+                debug!("Trailing padding ignored: '{}' bytes", chunk.len());
+                break
+            }
 
-    let mut file = if Path::new(&file_name).exists() {
-        File::options().append(true).open(&file_name)?
-    } else {
-        let mut file = File::options().create_new(true).write(true).open(&file_name)?;
-        write!(file, "Timestamp,Station name,Battery voltage,Lithium voltage,Wind Diag,CF Card\n")?;
-        write!(file, "YYYY-DD-MM HH:MM:SS,String,[V],[V],Float,Int32\n")?;
-        file
-    };
+            // The records before were most likely decoded from shifted bytes as well
+            warn!("Record '{}' misaligned, '{}' bytes left over", i, chunk.len());
+            return Err(IWError::AlignmentError { expected: record_length, actual: chunk.len() })
+        }
 
-    write!(file, "{},{},{},{},{},{}\n",
-        data.timestamp,
-        name,
-        data.solar_battery,
-        data.lithium_battery,
-        data.wind_diag,
-        data.cf_card
-    )?;
+        result.push(parse_weather_data_single(chunk, station_config, epoch_base)?);
+    }
 
-    file.flush()?;
+    Ok(IWStationData::MultipleData(result))
+}
 
-    Ok(())
+fn precipitation_delta(previous: Option<f64>, current: f64) -> f64 {
+    match previous {
+        Some(previous) if current >= previous => current - previous,
+        // The counter was reset, so everything since the reset fell in this interval
+        Some(_) => current,
+        // No reference value yet, the amount for this interval is unknown
+        None => f64::NAN,
+    }
 }
 
-fn write_multiple_data(folder: &str, data: &[IWWeatherData], name: &str) -> Result<(), IWError> {
-    let file_name = format!("{}/all_data_multiple.csv", folder);
+fn apply_precip_mode(data: &mut [IWWeatherData], mode: IWPrecipMode, store_raw: bool, last_total: &mut Option<f64>) {
+    match mode {
+        IWPrecipMode::Interval => {
+            // Already the amount per interval, store as is
+        }
+        IWPrecipMode::Cumulative => {
+            for entry in data.iter_mut() {
+                let total = entry.precipitation;
 
-    // TODO: use File::fn metadata(&self) -> Result<Metadata>
-    // and then Metadata::fn len(&self) -> u64
-    // instead of Path::exists()
+                // A tip count from the conversion to mm is the rawer value and stays
+                if store_raw && entry.precipitation_raw.is_none() {
+                    entry.precipitation_raw = Some(total);
+                }
 
-    let mut file = if Path::new(&file_name).exists() {
-        File::options().append(true).open(&file_name)?
-    } else {
-        let mut file = File::options().create_new(true).write(true).open(&file_name)?;
-        write!(file, "Timestamp,Station name,Air temperature,Air relative humidity,Solar radiation,Soil water content,Soil temperature,Wind speed,Wind max,Wind direction,Precipitation,Air pressure\n")?;
-        write!(file, "YYYY-MM-DD HH:MM:SS,String,Deg C,%,W/mA²,mA³/mA³,Deg C,m/s,m/s,degrees,mm,mbar\n")?;
-        file
-    };
+                entry.precipitation = precipitation_delta(*last_total, total);
+                *last_total = Some(total);
+            }
+        }
+    }
+}
 
-    for entry in data.iter() {
-        write!(file, "{},{},{},{},{},{},{},{},{},{},{},{}\n",
-            entry.timestamp,
-            name,
-            entry.air_temperature,
-            entry.air_relative_humidity,
-            entry.solar_radiation,
-            entry.soil_water_content,
-            entry.soil_temperature,
-            entry.wind_speed,
-            entry.wind_max,
-            entry.wind_direction,
-            entry.precipitation,
-            entry.air_pressure,
-        )?;
+// A missing file is normal on the first start, every other problem is logged and the totals start empty
+fn load_precipitation_totals(path: &str) -> HashMap<String, f64> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+            warn!("Could not read precipitation totals from '{}': '{}'", path, e);
+            HashMap::new()
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => {
+            warn!("Could not read precipitation totals from '{}': '{}'", path, e);
+            HashMap::new()
+        }
     }
+}
 
-    file.flush()?;
+// The rename keeps the old totals if writing fails
+fn save_precipitation_totals(path: &str, totals: &HashMap<String, f64>) -> Result<(), IWError> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, serde_json::to_string(totals)?)?;
+    fs::rename(&tmp_path, path)?;
 
     Ok(())
 }
 
-fn handle_connection(mut stream: TcpStream, socket: SocketAddr) -> Result<(), IWError> {
-    debug!("New connection from '{}'", socket);
-
-    let port = stream.local_addr()?.port();
-    let station_name = port_to_station(port);
-    debug!("Port: '{}', station: '{}'", port, station_name);
+fn is_skewed(timestamp: &str, received_at: NaiveDateTime, max_skew_secs: i64) -> bool {
+    match timestamp_to_datetime(timestamp) {
+        Some(timestamp) => (received_at - timestamp).num_seconds().abs() > max_skew_secs,
+        None => false,
+    }
+}
 
-    let mut tcp_buffer = Vec::new();
-    let len = stream.read_to_end(&mut tcp_buffer)?;
-    debug!("[{}], number of bytes received: '{}'", port, len);
+fn reject_skewed_records(data: &mut Vec<IWWeatherData>, received_at: NaiveDateTime, max_skew_secs: i64) {
+    data.retain(|entry| {
+        let skewed = is_skewed(&entry.timestamp, received_at, max_skew_secs);
 
-    if len < HEADER_LENGTH1 {
-        return Err(IWError::DataTooShort(len))
-    }
+        if skewed {
+            warn!("Record rejected, time stamp '{}' is too far from receive time '{}'", entry.timestamp, received_at);
+        }
 
-    let date_today = Local::now().format("%Y_%m_%d").to_string();
+        !skewed
+    });
+}
 
-    // Write received binary data to disk.
-    // Close binary file directly after this block.
-    {
-        let binary_filename = format!("old/binary/{}_{}.dat", station_name, date_today);
-        let mut binary_file = File::options().append(true).create(true).open(&binary_filename)?;
-        binary_file.write(&tcp_buffer)?;
-        binary_file.flush()?;
-        info!("Binary data written to: '{}'", binary_filename);
+// Records of a batch are in order, a jump backwards means the batch was misframed
+fn check_monotonic(data: &[IWWeatherData], tolerance_secs: i64) -> Result<(), IWError> {
+    for pair in data.windows(2) {
+        if let (Some(previous), Some(current)) = (timestamp_to_datetime(&pair[0].timestamp), timestamp_to_datetime(&pair[1].timestamp)) {
+            if (previous - current).num_seconds() > tolerance_secs {
+                return Err(IWError::NonMonotonicTimestamps { previous: pair[0].timestamp.clone(), current: pair[1].timestamp.clone() })
+            }
+        }
     }
 
-    let after_header = &tcp_buffer[HEADER_LENGTH1..];
+    Ok(())
+}
 
-    debug!("[{}] Binary data: {:?}", port, after_header);
+// Returns the time stamps before and after every gap larger than max_gap_secs
+fn find_gaps(data: &[IWWeatherData], max_gap_secs: i64) -> Vec<(String, String)> {
+    data.windows(2).filter(|pair| {
+        match (timestamp_to_datetime(&pair[0].timestamp), timestamp_to_datetime(&pair[1].timestamp)) {
+            (Some(previous), Some(current)) => (current - previous).num_seconds() > max_gap_secs,
+            _ => false,
+        }
+    }).map(|pair| (pair[0].timestamp.clone(), pair[1].timestamp.clone())).collect()
+}
 
-    let data = parse_binary_data(after_header)?;
+fn get_data_length(buffer: &[u8]) -> Result<usize, IWError> {
+    if buffer.len() < HEADER_LENGTH2 {
+        return Err(IWError::DataTooShort(buffer.len()))
+    }
 
-    let folder = match port {
-        2100 => "2100_Na",
-        2101 => "2101_SG",
-        2102 => "2102_PdA",
-        2103 => "2103_LC",
-        2104 => "2104_Tue",
-        _ => "unknown",
-    };
+    let high = buffer[1] as u16;
+    let low = buffer[2] as u16;
+    Ok((low + (256 * high)) as usize)
+}
 
-    // Export data as CSV
-    match data {
-        IWStationData::SingleData(data) => {
-            debug!("Number of entries: 1");
-            write_single_data(folder, &data, &station_name)?;
+// PakBus signature as calculated by the Campbell loggers
+fn pakbus_signature(buffer: &[u8], seed: u16) -> u16 {
+    buffer.iter().fold(seed, |sig, byte| {
+        let mut new_seed = (sig << 1) & 0x1FF;
+        if new_seed >= 0x100 {
+            new_seed += 1;
         }
-        IWStationData::MultipleData(data) => {
-            debug!("Number of entries: {}", data.len());
-            write_multiple_data(folder, &data, &station_name)?;
+        ((new_seed + (sig >> 8) + *byte as u16) & 0xFF) | (sig << 8)
+    })
+}
+
+// The two bytes that bring the signature of the whole frame down to zero
+fn pakbus_nullifier(sig: u16) -> u16 {
+    let mut sig = sig;
+    let mut result = 0;
+
+    for _ in 0..2 {
+        let mut new_seed = (sig << 1) & 0x1FF;
+        if new_seed >= 0x100 {
+            new_seed += 1;
         }
+        let null = 0x100u16.wrapping_sub(new_seed + (sig >> 8)) as u8;
+        sig = pakbus_signature(&[null], sig);
+        result = (result << 8) | null as u16;
     }
 
-    Ok(())
+    result
 }
 
-pub fn start_server(config: &IWConfiguration) {
-    let mut listeners = Vec::new();
+// CRC-16/CCITT-FALSE
+fn crc16(buffer: &[u8]) -> u16 {
+    buffer.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
 
-    for port in config.ports.iter() {
-        match TcpListener::bind(("0.0.0.0", *port)) {
-            Ok(listener) => {
-                debug!("Create listener for port: '{}'", port);
-                listeners.push(listener);
-            }
-            Err(e) => {
-                error!("An error occurred while binding to port: '{}'", e);
-            }
-        }
+// Returns the frame without the trailing checksum bytes
+fn strip_checksum(buffer: &[u8], checksum: IWChecksum) -> Result<&[u8], IWError> {
+    if checksum == IWChecksum::None {
+        return Ok(buffer)
     }
 
-    for listener in listeners {
-        spawn(move || {
-            loop {
-                match listener.accept() {
-                    Ok((stream, socket)) => {
-                        match handle_connection(stream, socket) {
-                            Ok(_) => {
-                                info!("Data was processed successfully");
-                                let line = "#".repeat(60);
-                                info!("{}", line);
-                            }
-                            Err(e) => {
-                                error!("An error occurred while processing the data: '{}'", e);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("An error occurred while accepting the connection: '{}'", e);
-                    }
-                }
-            }
-        });
+    if buffer.len() < checksum.byte_len() {
+        return Err(IWError::DataTooShort(buffer.len()))
     }
-}
 
+    let (frame, trailer) = buffer.split_at(buffer.len() - checksum.byte_len());
+    let received = BigEndian::read_u16(trailer);
 
-#[cfg(test)]
-mod tests {
-    use std::thread::sleep;
-    use std::time::Duration;
-    use std::net::TcpStream;
-    use std::io::Write;
-    use std::fs::File;
+    let calculated = match checksum {
+        IWChecksum::Signature => pakbus_nullifier(pakbus_signature(frame, 0xAAAA)),
+        _ => crc16(frame),
+    };
 
-    use simplelog::{WriteLogger, LevelFilter, ConfigBuilder};
+    if received != calculated {
+        return Err(IWError::ChecksumMismatch { received, calculated })
+    }
+
+    Ok(frame)
+}
+
+pub fn parse_binary_data(buffer: &[u8], station_config: &IWStationConfiguration, epoch_base: NaiveDateTime) -> Result<IWStationData, IWError> {
+    debug!("Parse binary data");
+
+    let buffer = strip_checksum(buffer, station_config.checksum)?;
+
+    let buffer_len = buffer.len();
+    debug!("buffer_len: '{}'", buffer_len);
+
+    if buffer_len < LOGGER_STATUS1_LENGTH {
+        return Err(IWError::DataTooShort(buffer_len))
+    }
+
+    let data_len = get_data_length(buffer)?;
+    debug!("data_len: '{}'", data_len);
+
+    if data_len != buffer_len - HEADER_LENGTH2 {
+        return Err(IWError::DataLengthMismatch(data_len))
+    }
+
+    if buffer[0] != 2 {
+        return Err(IWError::InvalidDataHeader)
+    }
+
+    // All fields are 2 or 4 bytes long, so an odd length means the data is corrupted
+    if !data_len.is_multiple_of(2) {
+        return Err(IWError::OddDataLength(data_len))
+    }
+
+    let data_buffer = &buffer[HEADER_LENGTH2..];
+
+    let schema = match station_config.schema {
+        Some(schema) => schema,
+        None => {
+            if data_len == LOGGER_STATUS1_LENGTH {
+                return parse_logger_status1(data_buffer, epoch_base)
+            } else if data_len == LOGGER_STATUS2_LENGTH {
+                return parse_logger_status2(data_buffer, epoch_base)
+            } else if data_len == LOGGER_STATUS3_LENGTH {
+                return parse_logger_status3(data_buffer, epoch_base)
+            } else {
+                return parse_weather_data(data_buffer, station_config, epoch_base)
+            }
+        }
+    };
+
+    match parse_with_schema(data_buffer, schema, station_config, epoch_base) {
+        Err(IWError::SchemaMismatch(_)) if station_config.schema_autodetect => {
+            // Try the other known schemas, useful during firmware transitions
+            for other in [IWSchema::Status1, IWSchema::Status2, IWSchema::Status3, IWSchema::Weather] {
+                if other == schema {
+                    continue
+                }
+
+                if let Ok(data) = parse_with_schema(data_buffer, other, station_config, epoch_base) {
+                    info!("Data length '{}' does not match schema '{:?}', decoded as '{:?}'", data_len, schema, other);
+                    return Ok(data)
+                }
+            }
+
+            Err(IWError::SchemaMismatch(data_len))
+        }
+        result => result,
+    }
+}
+
+fn parse_with_schema(data_buffer: &[u8], schema: IWSchema, station_config: &IWStationConfiguration, epoch_base: NaiveDateTime) -> Result<IWStationData, IWError> {
+    let data_len = data_buffer.len();
+
+    match schema {
+        IWSchema::Status1 if data_len == LOGGER_STATUS1_LENGTH => parse_logger_status1(data_buffer, epoch_base),
+        IWSchema::Status2 if data_len == LOGGER_STATUS2_LENGTH => parse_logger_status2(data_buffer, epoch_base),
+        IWSchema::Status3 if data_len == LOGGER_STATUS3_LENGTH => parse_logger_status3(data_buffer, epoch_base),
+        IWSchema::Weather if data_len >= weather_data_length(station_config) => parse_weather_data(data_buffer, station_config, epoch_base),
+        _ => Err(IWError::SchemaMismatch(data_len)),
+    }
+}
+
+fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, IWError> {
+    let hex: String = hex.split_whitespace().collect();
+
+    if !hex.len().is_multiple_of(2) {
+        return Err(IWError::InvalidHex(format!("odd number of digits: '{}'", hex.len())))
+    }
+
+    (0..hex.len()).step_by(2).map(|i| {
+        hex.get(i..i + 2)
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| IWError::InvalidHex(format!("invalid digits at position '{}'", i)))
+    }).collect()
+}
+
+// Detect the SBS header by the length field that follows it
+fn has_sbs_header(buffer: &[u8]) -> bool {
+    buffer.len() >= HEADER_LENGTH1 + HEADER_LENGTH2 &&
+        get_data_length(&buffer[HEADER_LENGTH1..]).is_ok_and(|data_len| data_len == buffer.len() - HEADER_LENGTH1 - HEADER_LENGTH2)
+}
+
+// Decodes a packet given as hex string, with or without the SBS header
+pub fn decode_hex(hex: &str) -> Result<IWStationData, IWError> {
+    let buffer = hex_to_bytes(hex)?;
+    let epoch_base = default_epoch_base();
+
+    if has_sbs_header(&buffer) {
+        debug!("SBS header detected");
+        parse_binary_data(&buffer[HEADER_LENGTH1..], &IWStationConfiguration::default(), epoch_base)
+    } else {
+        parse_binary_data(&buffer, &IWStationConfiguration::default(), epoch_base)
+    }
+}
+
+fn trace_fields(data_buffer: &[u8], trace: &mut Vec<String>, epoch_base: NaiveDateTime) {
+    let (record_length, fields): (usize, &[&str]) = match data_buffer.len() {
+        LOGGER_STATUS1_LENGTH => (LOGGER_STATUS1_LENGTH, &["solar_battery", "lithium_battery", "wind_diag"]),
+        LOGGER_STATUS2_LENGTH => (LOGGER_STATUS2_LENGTH, &["solar_battery", "lithium_battery", "wind_diag"]),
+        LOGGER_STATUS3_LENGTH => (LOGGER_STATUS3_LENGTH, &["solar_battery", "lithium_battery", "wind_diag"]),
+        _ => (WEATHER_DATA_LENGTH, &WEATHER_FIELDS),
+    };
+
+    for (i, record) in data_buffer.chunks(record_length).enumerate() {
+        trace.push(format!("Record {}:", i));
+
+        if record.len() < (2 * ULONG_LEN) + (fields.len() * FP2_LEN) {
+            trace.push(format!("  incomplete record: {:?}", record));
+            continue
+        }
+
+        let seconds = LittleEndian::read_u32(record);
+        trace.push(format!("  timestamp: raw: '{}', decoded: '{}'", seconds, u32_to_timestamp(seconds, epoch_base)));
+        trace.push(format!("  zero: raw: '{}'", LittleEndian::read_u32(&record[ULONG_LEN..])));
+
+        for (j, field) in fields.iter().enumerate() {
+            let raw = BigEndian::read_u16(&record[(2 * ULONG_LEN) + (j * FP2_LEN)..]);
+            trace.push(format!("  {}: raw: '{}' ({:#06x}), decoded: '{}'", field, raw, raw, u16_to_f64(raw)));
+        }
+
+        if record_length == LOGGER_STATUS2_LENGTH || record_length == LOGGER_STATUS3_LENGTH {
+            let cf_card = BigEndian::read_u32(&record[LOGGER_STATUS1_LENGTH..]);
+            trace.push(format!("  cf_card: raw: '{}'", cf_card));
+        }
+
+        if record_length == LOGGER_STATUS3_LENGTH {
+            let raw = BigEndian::read_u16(&record[LOGGER_STATUS2_LENGTH..]);
+            trace.push(format!("  battery_temperature: raw: '{}' ({:#06x}), decoded: '{}'", raw, raw, u16_to_f64(raw)));
+        }
+    }
+}
+
+// Every intermediate step of decoding a packet given as hex string, for debugging new framings
+pub fn trace_decode(hex: &str) -> Result<Vec<String>, IWError> {
+    let mut trace = Vec::new();
+    let epoch_base = default_epoch_base();
+
+    let buffer = hex_to_bytes(hex)?;
+    trace.push(format!("Raw bytes ({}): {:?}", buffer.len(), buffer));
+
+    let payload = if has_sbs_header(&buffer) {
+        trace.push("SBS header:".to_string());
+        trace.extend(hex_dump(&buffer[..HEADER_LENGTH1]).lines().map(|line| format!("  {}", line)));
+        &buffer[HEADER_LENGTH1..]
+    } else {
+        trace.push("SBS header: not present".to_string());
+        &buffer[..]
+    };
+
+    trace.push(format!("Payload ({}): {:?}", payload.len(), payload));
+
+    if payload.len() >= HEADER_LENGTH2 {
+        trace.push(format!("Frame header: '{}', declared length: '{}', actual length: '{}'",
+            payload[0], get_data_length(payload)?, payload.len() - HEADER_LENGTH2));
+        trace_fields(&payload[HEADER_LENGTH2..], &mut trace, epoch_base);
+    }
+
+    match parse_binary_data(payload, &IWStationConfiguration::default(), epoch_base) {
+        Ok(data) => trace.push(format!("Result: {:?}", data)),
+        Err(e) => trace.push(format!("Error: '{}'", e)),
+    }
+
+    Ok(trace)
+}
+
+fn format_csv_float(value: f64, config: &IWConfiguration) -> String {
+    if !value.is_finite() && config.nan_policy == IWNanPolicy::Null {
+        return config.csv_null_token.clone()
+    }
+
+    let result = format!("{:.*}", config.csv_decimals, value);
+
+    if config.decimal_separator == '.' {
+        result
+    } else {
+        result.replace('.', &config.decimal_separator.to_string())
+    }
+}
+
+fn write_csv_line<W: Write>(file: &mut W, fields: &[String], config: &IWConfiguration) -> Result<(), IWError> {
+    writeln!(file, "{}", fields.join(&config.csv_delimiter.to_string()))?;
+    Ok(())
+}
+
+fn write_csv_header<W: Write>(file: &mut W, header: &str, config: &IWConfiguration) -> Result<(), IWError> {
+    let fields: Vec<String> = header.split(',').map(|field| field.to_string()).collect();
+    write_csv_line(file, &fields, config)
+}
+
+// The schema version is an extra column at the end, so the existing column positions stay the same
+fn schema_version_header(header: &str, column: &str, config: &IWConfiguration) -> String {
+    if config.annotate_schema_version {
+        format!("{},{}", header, column)
+    } else {
+        header.to_string()
+    }
+}
+
+fn push_schema_version(fields: &mut Vec<String>, schema_version: Option<&str>, config: &IWConfiguration) {
+    if config.annotate_schema_version {
+        fields.push(schema_version.unwrap_or(DEFAULT_SCHEMA_VERSION).to_string());
+    }
+}
+
+// The MOMSN goes after the schema version, empty when the SBS header could not be parsed
+fn momsn_header(header: String, column: &str, config: &IWConfiguration) -> String {
+    if config.annotate_momsn {
+        format!("{},{}", header, column)
+    } else {
+        header
+    }
+}
+
+fn push_momsn(fields: &mut Vec<String>, momsn: Option<u16>, config: &IWConfiguration) {
+    if config.annotate_momsn {
+        fields.push(momsn.map(|momsn| momsn.to_string()).unwrap_or_default());
+    }
+}
+
+// Number of header lines at the start of every CSV file
+const CSV_HEADER_LINES: usize = 2;
+
+fn csv_key(line: &str, config: &IWConfiguration) -> Option<(String, String)> {
+    let mut fields = line.split(config.csv_delimiter);
+    let timestamp = fields.next()?;
+    let station = fields.next()?;
+    Some((timestamp.to_string(), station.to_string()))
+}
+
+// Returns which of the incoming records (given by their time stamps) should be appended
+fn apply_file_dedup(file_name: &str, timestamps: &[&str], name: &str, config: &IWConfiguration) -> Result<Vec<bool>, IWError> {
+    if config.file_dedup == IWFileDedup::Off || !Path::new(file_name).exists() {
+        return Ok(vec![true; timestamps.len()])
+    }
+
+    let content = fs::read_to_string(file_name)?;
+
+    match config.file_dedup {
+        IWFileDedup::Off => {
+            Ok(vec![true; timestamps.len()])
+        }
+        IWFileDedup::First => {
+            // Keep the records in the file, skip the incoming ones
+            let existing: HashSet<(String, String)> = content.lines()
+                .skip(CSV_HEADER_LINES)
+                .filter_map(|line| csv_key(line, config))
+                .collect();
+
+            Ok(timestamps.iter().map(|timestamp| !existing.contains(&(timestamp.to_string(), name.to_string()))).collect())
+        }
+        IWFileDedup::Last => {
+            // Remove the records from the file, append all incoming ones
+            let incoming: HashSet<(String, String)> = timestamps.iter()
+                .map(|timestamp| (timestamp.to_string(), name.to_string()))
+                .collect();
+
+            let lines: Vec<&str> = content.lines().enumerate()
+                .filter(|(i, line)| *i < CSV_HEADER_LINES || !csv_key(line, config).is_some_and(|key| incoming.contains(&key)))
+                .map(|(_, line)| line)
+                .collect();
+
+            if lines.len() != content.lines().count() {
+                let tmp_file_name = format!("{}.tmp", file_name);
+                let mut tmp_file = File::create(&tmp_file_name)?;
+                for line in lines {
+                    writeln!(tmp_file, "{}", line)?;
+                }
+                tmp_file.flush()?;
+                fs::rename(&tmp_file_name, file_name)?;
+            }
+
+            Ok(vec![true; timestamps.len()])
+        }
+    }
+}
+
+fn write_single_data(folder: &str, data: &IWLoggerStatus, name: &str, schema_version: Option<&str>, momsn: Option<u16>, config: &IWConfiguration) -> Result<(), IWError> {
+    let file_name = format!("{}/all_data_battery.csv", folder);
+
+    if !apply_file_dedup(&file_name, &[&data.timestamp], name, config)?[0] {
+        debug!("Logger status '{}' already in file, skipped", data.timestamp);
+        return Ok(())
+    }
+
+    let mut file = File::options().append(true).create(true).open(&file_name)?;
+    let mut lines = Vec::new();
+
+    // Also after a failed first write, which leaves an empty file
+    if file.metadata()?.len() == 0 {
+        write_csv_header(&mut lines, &momsn_header(schema_version_header("Timestamp,Station name,Battery voltage,Lithium voltage,Wind Diag,CF Card", "Schema version", config), "MOMSN", config), config)?;
+        write_csv_header(&mut lines, &momsn_header(schema_version_header("YYYY-MM-DD HH:MM:SS,String,[V],[V],Float,Int32", "String", config), "UInt16", config), config)?;
+    }
+
+    let mut fields = vec![
+        data.timestamp.clone(),
+        name.to_string(),
+        format_csv_float(data.solar_battery, config),
+        format_csv_float(data.lithium_battery, config),
+        format_csv_float(data.wind_diag, config),
+        data.cf_card.to_string(),
+    ];
+    push_schema_version(&mut fields, schema_version, config);
+    push_momsn(&mut fields, momsn, config);
+    write_csv_line(&mut lines, &fields, config)?;
+
+    append_all(&mut file, &lines)
+}
+
+fn write_multiple_data(folder: &str, data: &[IWWeatherData], name: &str, schema_version: Option<&str>, momsn: Option<u16>, config: &IWConfiguration) -> Result<(), IWError> {
+    let file_name = format!("{}/all_data_multiple.csv", folder);
+
+    let timestamps: Vec<&str> = data.iter().map(|entry| entry.timestamp.as_str()).collect();
+    let keep = apply_file_dedup(&file_name, &timestamps, name, config)?;
+
+    let mut file = File::options().append(true).create(true).open(&file_name)?;
+    let mut lines = Vec::new();
+
+    // Also after a failed first write, which leaves an empty file
+    if file.metadata()?.len() == 0 {
+        write_csv_header(&mut lines, &momsn_header(schema_version_header("Timestamp,Station name,Air temperature,Air relative humidity,Solar radiation,Soil water content,Soil temperature,Wind speed,Wind max,Wind direction,Precipitation,Air pressure", "Schema version", config), "MOMSN", config), config)?;
+        write_csv_header(&mut lines, &momsn_header(schema_version_header("YYYY-MM-DD HH:MM:SS,String,Deg C,%,W/mA²,mA³/mA³,Deg C,m/s,m/s,degrees,mm,mbar", "String", config), "UInt16", config), config)?;
+    }
+
+    for (entry, _) in data.iter().zip(keep).filter(|(_, keep)| *keep) {
+        let mut fields = vec![
+            entry.timestamp.clone(),
+            name.to_string(),
+            format_csv_float(entry.air_temperature, config),
+            format_csv_float(entry.air_relative_humidity, config),
+            format_csv_float(entry.solar_radiation, config),
+            format_csv_float(entry.soil_water_content, config),
+            format_csv_float(entry.soil_temperature, config),
+            format_csv_float(entry.wind_speed, config),
+            format_csv_float(entry.wind_max, config),
+            format_csv_float(entry.wind_direction, config),
+            format_csv_float(entry.precipitation, config),
+            format_csv_float(entry.air_pressure, config),
+        ];
+        push_schema_version(&mut fields, schema_version, config);
+        push_momsn(&mut fields, momsn, config);
+        write_csv_line(&mut lines, &fields, config)?;
+    }
+
+    append_all(&mut file, &lines)
+}
+
+fn read_payload_station_id<'a>(buffer: &'a [u8], station_config: &IWStationConfiguration, port_station: &str) -> Result<(String, &'a [u8]), IWError> {
+    if buffer.len() < STATION_ID_LENGTH {
+        return Err(IWError::DataTooShort(buffer.len()))
+    }
+
+    let station_id = BigEndian::read_u16(buffer);
+    debug!("Payload station id: '{}'", station_id);
+
+    let station_name = match station_config.id_to_station.get(&station_id) {
+        Some(name) => name.clone(),
+        None => {
+            warn!("Unknown payload station id '{}', using station name '{}'", station_id, port_station);
+            port_station.to_string()
+        }
+    };
+
+    Ok((station_name, &buffer[STATION_ID_LENGTH..]))
+}
+
+fn read_identity(buffer: &[u8], field: &IWIdentityField) -> Result<String, IWError> {
+    let end = field.offset + field.length;
+
+    if buffer.len() < end {
+        return Err(IWError::DataTooShort(buffer.len()))
+    }
+
+    let bytes = &buffer[field.offset..end];
+
+    let identity = match field.encoding {
+        IWIdentityEncoding::Ascii => String::from_utf8_lossy(bytes).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string(),
+        IWIdentityEncoding::Hex => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        IWIdentityEncoding::Integer => bytes.iter().fold(0u64, |value, byte| (value << 8) | *byte as u64).to_string(),
+    };
+
+    Ok(identity)
+}
+
+// The identity in the packet takes precedence over the port, so stations behind NAT keep their name
+fn resolve_identity(buffer: &[u8], config: &IWConfiguration, port_station: String) -> String {
+    let field = match &config.identity_field {
+        Some(field) => field,
+        None => return port_station,
+    };
+
+    match read_identity(buffer, field) {
+        Ok(identity) => {
+            debug!("Station identity: '{}'", identity);
+
+            match config.identity_to_station.get(&identity) {
+                Some(name) => name.clone(),
+                None => {
+                    warn!("Unknown station identity '{}', using station name '{}'", identity, port_station);
+                    port_station
+                }
+            }
+        }
+        Err(e) => {
+            warn!("Could not read station identity: {}, using station name '{}'", e, port_station);
+            port_station
+        }
+    }
+}
+
+fn hex_dump(buffer: &[u8]) -> String {
+    let mut result = String::new();
+
+    for (line, chunk) in buffer.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = chunk.iter().map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' }).collect();
+
+        result.push_str(&format!("{:08x}  {:<48}|{}|\n", line * 16, hex.join(" "), ascii));
+    }
+
+    result
+}
+
+fn write_failed_data(folder: &str, buffer: &[u8], station_name: &str, received_at: &str) -> Result<String, IWError> {
+    fs::create_dir_all(folder)?;
+
+    let file_name = format!("{}/{}_{}.hex", folder, station_name, received_at);
+    let mut file = File::options().create(true).write(true).truncate(true).open(&file_name)?;
+    file.write_all(hex_dump(buffer).as_bytes())?;
+    file.flush()?;
+
+    Ok(file_name)
+}
+
+// Iridium SBD DirectIP header in front of the data: protocol header, MO header and location
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct IWSbsHeader {
+    pub protocol_revision: u8,
+    pub message_length: u16,
+    pub cdr_reference: u32,
+    pub imei: String,
+    pub session_status: u8,
+    pub momsn: u16,
+    pub mtmsn: u16,
+    // Unix time of the Iridium session
+    pub session_time: u32,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub cep_radius: u32,
+}
+
+// All values are big endian, the ids of the information elements are not checked
+pub fn parse_sbs_header(buffer: &[u8]) -> Result<IWSbsHeader, IWError> {
+    if buffer.len() < HEADER_LENGTH1 {
+        return Err(IWError::DataTooShort(buffer.len()))
+    }
+
+    let u16_at = |i: usize| u16::from_be_bytes([buffer[i], buffer[i + 1]]);
+    let u32_at = |i: usize| u32::from_be_bytes([buffer[i], buffer[i + 1], buffer[i + 2], buffer[i + 3]]);
+
+    // Degrees and thousandths of a minute, the signs are in the format byte
+    let format = buffer[37];
+    let latitude = buffer[38] as f64 + u16_at(39) as f64 / 60000.0;
+    let longitude = buffer[41] as f64 + u16_at(42) as f64 / 60000.0;
+
+    Ok(IWSbsHeader {
+        protocol_revision: buffer[0],
+        message_length: u16_at(1),
+        cdr_reference: u32_at(6),
+        imei: String::from_utf8_lossy(&buffer[10..25]).trim_end_matches('\0').to_string(),
+        session_status: buffer[25],
+        momsn: u16_at(26),
+        mtmsn: u16_at(28),
+        session_time: u32_at(30),
+        latitude: if format & 0x02 != 0 { -latitude } else { latitude },
+        longitude: if format & 0x01 != 0 { -longitude } else { longitude },
+        cep_radius: u32_at(44),
+    })
+}
+
+#[derive(Serialize)]
+struct IWJsonRecord<'a, T> {
+    station: &'a str,
+    // From the SBS header, to find the message in the logs of the Iridium gateway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    imei: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    momsn: Option<u16>,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+// Newline-delimited JSON, one object per record
+fn write_json_data(dir: &str, data: &IWStationData, station_name: &str, date: &str, header: Option<&IWSbsHeader>) -> Result<String, IWError> {
+    let file_name = format!("{}/{}_{}.json", dir, station_name, date);
+    let imei = header.map(|header| header.imei.as_str());
+    let momsn = header.map(|header| header.momsn);
+    let mut lines = Vec::new();
+
+    match data {
+        IWStationData::SingleData(data) => {
+            serde_json::to_writer(&mut lines, &IWJsonRecord { station: station_name, imei, momsn, data })?;
+            writeln!(lines)?;
+        }
+        IWStationData::MultipleData(data) => {
+            for entry in data.iter() {
+                serde_json::to_writer(&mut lines, &IWJsonRecord { station: station_name, imei, momsn, data: entry })?;
+                writeln!(lines)?;
+            }
+        }
+    }
+
+    let mut file = File::options().append(true).create(true).open(&file_name)?;
+    append_all(&mut file, &lines)?;
+
+    Ok(file_name)
+}
+
+fn export_data(folder: &str, data: &IWStationData, station_name: &str, schema_version: Option<&str>, momsn: Option<u16>, config: &IWConfiguration) -> Result<(), IWError> {
+    // Export data as CSV
+    match data {
+        IWStationData::SingleData(data) => {
+            write_single_data(folder, data, station_name, schema_version, momsn, config)
+        }
+        IWStationData::MultipleData(data) => {
+            write_multiple_data(folder, data, station_name, schema_version, momsn, config)
+        }
+    }
+}
+
+// A failed write is cut off again, so retry_io can repeat it without writing the data twice
+fn append_all(file: &mut File, bytes: &[u8]) -> Result<(), IWError> {
+    let start = file.metadata()?.len();
+
+    if let Err(e) = file.write_all(bytes).and_then(|_| file.flush()) {
+        if let Err(e) = file.set_len(start) {
+            error!("Could not remove a partial write: '{}'", e);
+        }
+
+        return Err(e.into())
+    }
+
+    Ok(())
+}
+
+// Only IO errors are retried, everything else would fail the same way again.
+// All retried writes go through append_all or create a new file.
+fn retry_io<T, F: FnMut() -> Result<T, IWError>>(config: &IWConfiguration, mut f: F) -> Result<T, IWError> {
+    let mut attempt = 1;
+
+    loop {
+        match f() {
+            Err(IWError::IO(e)) if attempt < config.io_retry_attempts => {
+                warn!("IO error in attempt {} of {}, retrying: '{}'", attempt, config.io_retry_attempts, e);
+                sleep(StdDuration::from_millis(config.io_retry_delay_ms));
+                attempt += 1;
+            }
+            result => {
+                return result
+            }
+        }
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, socket: SocketAddr, state: &IWServerState) -> Result<(), IWError> {
+    debug!("New connection from '{}'", socket);
+
+    if let Some(ref rate_limiter) = state.rate_limiter {
+        if !rate_limiter.allow(socket.ip(), Instant::now()) {
+            warn!("Connection from '{}' rejected, rate limit exceeded", socket);
+            return Err(IWError::RateLimited(socket.ip()))
+        }
+    }
+
+    let port = stream.local_addr()?.port();
+    let station_name = port_to_station(port);
+    let station_config = state.config.station(port);
+    debug!("Port: '{}', station: '{}'", port, station_name);
+
+    if station_config.streaming_connection {
+        if !station_config.enabled {
+            info!("Station '{}' is disabled, streaming connection closed", station_name);
+            return Ok(())
+        }
+
+        return handle_streaming_connection(stream, port, &station_name, &station_config, state)
+    }
+
+    // A stalled iridium link must not hold this thread forever, zero disables the timeout
+    if state.config.read_timeout_secs > 0 {
+        stream.set_read_timeout(Some(StdDuration::from_secs(state.config.read_timeout_secs)))?;
+    }
+
+    let mut tcp_buffer = Vec::new();
+
+    // One byte more than allowed, to tell a packet that hits the cap from one that fits exactly
+    let read_result = match state.config.max_packet_bytes {
+        Some(max_packet_bytes) => (&mut stream).take(max_packet_bytes as u64 + 1).read_to_end(&mut tcp_buffer),
+        None => stream.read_to_end(&mut tcp_buffer),
+    };
+
+    let len = match read_result {
+        Ok(len) => len,
+        Err(ref e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+            warn!("[{}] Read timeout after '{}' bytes", port, tcp_buffer.len());
+
+            // Keep the partial data for later inspection
+            if !tcp_buffer.is_empty() && !state.config.writes_disabled() {
+                write_binary_data(&tcp_buffer, &station_name, &Local::now().format(station_config.file_granularity.date_format()).to_string(), state)?;
+            }
+
+            let e = IWError::Timeout(tcp_buffer.len());
+            state.metrics.inc_parse_error(&station_name, &e);
+            return Err(e)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    debug!("[{}], number of bytes received: '{}'", port, len);
+
+    if let Some(max_packet_bytes) = state.config.max_packet_bytes {
+        if len > max_packet_bytes {
+            // The rest is never read, so the packet is rejected instead of parsed truncated
+            let e = IWError::PacketTooLarge { declared: declared_length(&tcp_buffer, station_config.sbs_header_length), max: max_packet_bytes };
+            warn!("[{}] Packet from '{}' ('{}') rejected: {}", port, station_name, socket, e);
+
+            // Keep what was read up to the limit for later inspection
+            if !state.config.writes_disabled() {
+                write_binary_data(&tcp_buffer[..max_packet_bytes], &station_name, &Local::now().format(station_config.file_granularity.date_format()).to_string(), state)?;
+            }
+
+            state.metrics.inc_parse_error(&station_name, &e);
+            return Err(e)
+        }
+    }
+
+    if len == 0 {
+        debug!("[{}] Empty connection (likely probe)", port);
+        return Ok(())
+    }
+
+    if !station_config.enabled {
+        info!("Station '{}' is disabled, '{}' bytes discarded", station_name, len);
+        return Ok(())
+    }
+
+    process_buffer(&tcp_buffer, port, station_name, &station_config, state)
+}
+
+// SBS header and the optional station id in front of the frame
+fn prefix_length(station_config: &IWStationConfiguration) -> usize {
+    if station_config.payload_station_id {
+        station_config.sbs_header_length + STATION_ID_LENGTH
+    } else {
+        station_config.sbs_header_length
+    }
+}
+
+// Some firmware sends several frames after one SBS header, the buffer starts after the header (and station id).
+// None for a single frame or when the frames do not add up to the buffer length, the parser reports that case.
+fn split_frames(buffer: &[u8], checksum_len: usize) -> Option<Vec<&[u8]>> {
+    let mut rest = buffer;
+    let mut frames = Vec::new();
+
+    while !rest.is_empty() {
+        let frame_len = HEADER_LENGTH2 + get_data_length(rest).ok()? + checksum_len;
+        frames.push(rest.get(..frame_len)?);
+        rest = &rest[frame_len..];
+    }
+
+    if frames.len() > 1 {
+        Some(frames)
+    } else {
+        None
+    }
+}
+
+// Total packet length according to the frame header after the SBS header
+fn declared_length(buffer: &[u8], header_len: usize) -> Option<usize> {
+    get_data_length(buffer.get(header_len..)?).ok().map(|data_len| header_len + HEADER_LENGTH2 + data_len)
+}
+
+// Reads one message (SBS header, frame header, data and checksum), returns None if the stream ended between messages
+fn read_framed_message<R: Read>(reader: &mut R, prefix_len: usize, checksum_len: usize, max_packet_bytes: Option<usize>) -> Result<Option<Vec<u8>>, IWError> {
+    let mut buffer = vec![0; prefix_len + HEADER_LENGTH2];
+
+    if reader.read(&mut buffer[..1])? == 0 {
+        return Ok(None)
+    }
+
+    reader.read_exact(&mut buffer[1..])?;
+
+    let data_len = get_data_length(&buffer[prefix_len..])? + checksum_len;
+
+    if let Some(max) = max_packet_bytes {
+        let declared = prefix_len + HEADER_LENGTH2 + data_len;
+
+        if declared > max {
+            // The framing is lost, the rest of the stream can not be read
+            return Err(IWError::PacketTooLarge { declared: Some(declared), max })
+        }
+    }
+
+    buffer.resize(prefix_len + HEADER_LENGTH2 + data_len, 0);
+    reader.read_exact(&mut buffer[prefix_len + HEADER_LENGTH2..])?;
+
+    Ok(Some(buffer))
+}
+
+fn handle_streaming_connection(mut stream: TcpStream, port: u16, station_name: &str, station_config: &IWStationConfiguration, state: &IWServerState) -> Result<(), IWError> {
+    debug!("[{}] Streaming connection", port);
+
+    // A half-open link must not hold this worker forever
+    if station_config.idle_timeout_secs > 0 {
+        stream.set_read_timeout(Some(StdDuration::from_secs(station_config.idle_timeout_secs)))?;
+    }
+
+    let prefix_len = prefix_length(station_config);
+
+    loop {
+        let buffer = match read_framed_message(&mut stream, prefix_len, station_config.checksum.byte_len(), state.config.max_packet_bytes) {
+            Ok(Some(buffer)) => buffer,
+            Ok(None) => break,
+            Err(IWError::IO(ref e)) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+                warn!("[{}] No data from '{}' for '{}' seconds, streaming connection closed", port, station_name, station_config.idle_timeout_secs);
+                break
+            }
+            Err(e) => return Err(e),
+        };
+
+        debug!("[{}], number of bytes received: '{}'", port, buffer.len());
+
+        // The framing is still intact, so a bad message does not end the connection
+        if let Err(e) = process_buffer(&buffer, port, station_name.to_string(), station_config, state) {
+            error!("[{}] Could not process streamed message: '{}'", port, e);
+        }
+
+        if state.is_shutdown() {
+            break
+        }
+    }
+
+    debug!("[{}] Streaming connection closed", port);
+
+    Ok(())
+}
+
+fn poll_station(target: &IWPollTarget, state: &IWServerState) -> Result<(), IWError> {
+    let request = hex_to_bytes(&target.request)?;
+    let station_config = state.config.station(target.port);
+
+    debug!("Poll station '{}' at '{}'", target.station, target.address);
+
+    let mut stream = TcpStream::connect(&target.address)?;
+
+    if state.config.read_timeout_secs > 0 {
+        stream.set_read_timeout(Some(StdDuration::from_secs(state.config.read_timeout_secs)))?;
+    }
+
+    if !request.is_empty() {
+        stream.write_all(&request)?;
+        stream.flush()?;
+    }
+
+    let prefix_len = prefix_length(&station_config);
+
+    // The logger may keep the connection open, so only one framed response is read
+    match read_framed_message(&mut stream, prefix_len, station_config.checksum.byte_len(), state.config.max_packet_bytes)? {
+        Some(buffer) => {
+            debug!("[{}], number of bytes received: '{}'", target.port, buffer.len());
+            process_buffer(&buffer, target.port, target.station.clone(), &station_config, state)
+        }
+        None => {
+            warn!("Station '{}' at '{}' closed the connection without data", target.station, target.address);
+            Ok(())
+        }
+    }
+}
+
+fn start_poller(target: IWPollTarget, state: Arc<IWServerState>) {
+    spawn(move || {
+        while !state.is_shutdown() {
+            {
+                let _guard = IWInFlightGuard::new(&state, None);
+
+                match poll_station(&target, &state) {
+                    Ok(_) => log!(state.accept_level(), "Data from polled station '{}' was processed successfully", target.station),
+                    Err(e) => error!("An error occurred while polling station '{}': '{}'", target.station, e),
+                }
+            }
+
+            sleep(StdDuration::from_secs(target.interval_secs));
+        }
+    });
+}
+
+fn write_binary_data(tcp_buffer: &[u8], station_name: &str, file_date: &str, state: &IWServerState) -> Result<(), IWError> {
+    for sink in state.sinks.iter() {
+        if let IWSinkConfig::Binary { dir, .. } = sink {
+            let binary_filename = format!("{}/{}_{}.dat", dir, station_name, file_date);
+            // Close binary file directly after writing
+            retry_io(&state.config, || {
+                let mut binary_file = File::options().append(true).create(true).open(&binary_filename)?;
+                append_all(&mut binary_file, tcp_buffer)
+            })?;
+            log!(state.accept_level(), "Binary data written to: '{}'", binary_filename);
+        }
+    }
+
+    Ok(())
+}
+
+fn create_sink_dirs(sinks: &[IWSinkConfig]) -> Result<(), IWError> {
+    for sink in sinks.iter() {
+        match sink {
+            IWSinkConfig::Binary { dir, .. } => {
+                fs::create_dir_all(dir)?;
+                debug!("Binary dump directory: '{}'", dir);
+            }
+            IWSinkConfig::Json { dir, .. } => {
+                fs::create_dir_all(dir)?;
+                debug!("JSON directory: '{}'", dir);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn process_buffer(tcp_buffer: &[u8], port: u16, station_name: String, station_config: &IWStationConfiguration, state: &IWServerState) -> Result<(), IWError> {
+    let len = tcp_buffer.len();
+
+    if len < station_config.sbs_header_length {
+        let e = IWError::DataTooShort(len);
+        state.metrics.inc_parse_error(&station_name, &e);
+        return Err(e)
+    }
+
+    // Only the standard header has a known layout
+    let sbs_header = if station_config.sbs_header_length == HEADER_LENGTH1 {
+        parse_sbs_header(tcp_buffer).ok()
+    } else {
+        None
+    };
+    debug!("[{}] SBS header: {:?}", port, sbs_header);
+
+    let mut after_header = &tcp_buffer[station_config.sbs_header_length..];
+    let station_name = resolve_identity(tcp_buffer, &state.config, station_name);
+
+    let station_name = if station_config.payload_station_id {
+        let (name, rest) = read_payload_station_id(after_header, station_config, &station_name).inspect_err(|e| {
+            state.metrics.inc_parse_error(&station_name, e);
+        })?;
+        debug!("Station name from payload: '{}'", name);
+        after_header = rest;
+        name
+    } else {
+        station_name
+    };
+
+    let received_at = Local::now();
+    let file_date = received_at.format(station_config.file_granularity.date_format()).to_string();
+
+    state.metrics.touch(&station_name, Instant::now());
+    state.metrics.add_bytes_received(&station_name, tcp_buffer.len());
+
+    // Write received binary data to disk, but not in test or safe mode.
+    if !state.config.writes_disabled() {
+        write_binary_data(tcp_buffer, &station_name, &file_date, state)?;
+    }
+
+    let frames = match split_frames(after_header, station_config.checksum.byte_len()) {
+        Some(frames) => {
+            debug!("[{}] Number of frames: '{}'", port, frames.len());
+            frames
+        }
+        None => vec![after_header],
+    };
+    let multiple_frames = frames.len() > 1;
+    let mut failed_dumped = false;
+    let mut result = Ok(());
+
+    // A bad frame does not affect the others, the first error is returned
+    for frame in frames {
+        debug!("[{}] Binary data: {:?}", port, frame);
+
+        let frame_result = match parse_binary_data(frame, station_config, state.epoch_base) {
+            Ok(data) => {
+                state.metrics.inc_frames_parsed(&station_name);
+                process_station_data(data, port, &station_name, station_config, sbs_header.as_ref(), received_at, state)
+            }
+            Err(e) => {
+                state.metrics.inc_parse_error(&station_name, &e);
+
+                // The dump holds everything that was received, so it is only written once
+                if !state.config.writes_disabled() && !failed_dumped {
+                    let timestamp = received_at.format("%Y_%m_%d_%H_%M_%S").to_string();
+
+                    match write_failed_data(&state.config.failed_dir, tcp_buffer, &station_name, &timestamp) {
+                        Ok(file_name) => info!("Hex dump of failed data written to: '{}'", file_name),
+                        Err(e) => error!("Could not write hex dump of failed data: '{}'", e),
+                    }
+
+                    failed_dumped = true;
+                }
+
+                Err(e)
+            }
+        };
+
+        if let Err(e) = frame_result {
+            if multiple_frames {
+                error!("[{}] An error occurred while processing a frame: '{}'", port, e);
+            }
+
+            result = result.and(Err(e));
+        }
+    }
+
+    result
+}
+
+// Checks, converts and stores the data of one frame
+fn process_station_data(mut data: IWStationData, port: u16, station_name: &str, station_config: &IWStationConfiguration, sbs_header: Option<&IWSbsHeader>,
+    received_at: DateTime<Local>, state: &IWServerState) -> Result<(), IWError> {
+    let station_name = station_name.to_string();
+    let file_date = received_at.format(station_config.file_granularity.date_format()).to_string();
+
+    if let IWStationData::MultipleData(ref data) = data {
+        // A single garbage record can pass all length checks
+        if data.len() < station_config.min_records {
+            warn!("[{}] Batch from '{}' rejected, '{}' records received, at least '{}' expected", port, station_name, data.len(), station_config.min_records);
+            let e = IWError::TooFewRecords { got: data.len(), min: station_config.min_records };
+            state.metrics.inc_parse_error(&station_name, &e);
+            return Err(e)
+        }
+
+        if station_config.require_monotonic {
+            check_monotonic(data, station_config.monotonic_tolerance_secs).inspect_err(|e| {
+                warn!("[{}] Batch from '{}' rejected: {}", port, station_name, e);
+                state.metrics.inc_parse_error(&station_name, e);
+            })?;
+        }
+    }
+
+    // All checks below compare in UTC, time stamps without a time zone are taken as UTC
+    match station_config.tz() {
+        Ok(Some(timezone)) => convert_to_utc(&mut data, timezone),
+        _ => {
+            if state.timezone_warned.lock().unwrap().insert(station_name.clone()) {
+                warn!("No time zone configured for station '{}', time stamps are stored as sent", station_name);
+            }
+        }
+    }
+
+    let received_at_utc = received_at.naive_utc();
+
+    if let Some(max_skew_secs) = station_config.max_skew_reject_secs {
+        match data {
+            IWStationData::SingleData(ref status) => {
+                if is_skewed(&status.timestamp, received_at_utc, max_skew_secs) {
+                    warn!("Logger status rejected, time stamp '{}' is too far from receive time '{}'", status.timestamp, received_at);
+                    return Ok(())
+                }
+            }
+            IWStationData::MultipleData(ref mut data) => {
+                reject_skewed_records(data, received_at_utc, max_skew_secs);
+            }
+        }
+    }
+
+    if let IWStationData::MultipleData(ref mut data) = data {
+        let mut totals = state.precipitation_totals.lock().unwrap();
+        let mut last_total = totals.get(&station_name).copied();
+        apply_precip_mode(data, station_config.precip_mode, station_config.store_raw, &mut last_total);
+
+        if let Some(last_total) = last_total {
+            if totals.insert(station_name.clone(), last_total) != Some(last_total) && !state.config.writes_disabled() {
+                if let Err(e) = save_precipitation_totals(&state.config.precip_state_file, &totals) {
+                    error!("Could not save precipitation totals: '{}'", e);
+                }
+            }
+        }
+    }
+
+    let schema_version = if state.config.annotate_schema_version {
+        Some(station_config.schema_version.clone())
+    } else {
+        None
+    };
+
+    match data {
+        IWStationData::SingleData(ref data) => {
+            debug!("Number of entries: 1");
+
+            for problem in data.validate(&state.config) {
+                warn!("[{}] Implausible logger status from '{}': {}", port, station_name, problem);
+            }
+
+            check_battery(&station_name, data, &state.config);
+
+            state.health.update_status(&station_name, data, received_at_utc);
+        }
+        IWStationData::MultipleData(ref data) => {
+            debug!("Number of entries: {}", data.len());
+
+            for entry in data.iter() {
+                for e in entry.validate(&state.config) {
+                    warn!("[{}] Implausible weather data from '{}' at '{}': {}", port, station_name, entry.timestamp, e);
+                }
+
+                let special_fields = entry.special_fields();
+                if !special_fields.is_empty() {
+                    warn!("[{}] NAN or INF from '{}' at '{}' in: {}", port, station_name, entry.timestamp, special_fields.join(", "));
+                }
+            }
+
+            let max_gap_secs = station_config.max_gap_secs(state.config.expected_interval_secs);
+
+            for (start, end) in find_gaps(data, max_gap_secs) {
+                warn!("[{}] Data gap from '{}' between '{}' and '{}'", port, station_name, start, end);
+            }
+
+            state.health.update_weather(&station_name, data, received_at_utc, max_gap_secs);
+
+            if let Some(last_record) = data.iter().filter_map(|entry| timestamp_to_datetime(&entry.timestamp)).max() {
+                state.metrics.set_last_record(&station_name, last_record);
+            }
+        }
+    }
+
+    if state.websocket_enabled() {
+        if let IWStationData::MultipleData(ref data) = data {
+            state.websocket.broadcast_weather(&station_name, data, schema_version.as_deref());
+        }
+    }
+
+    let mut entry = IWWalEntry {
+        station_name,
+        folder: port_to_folder(port).to_string(),
+        data,
+        schema_version,
+        file_date: Some(file_date),
+        sbs_header: sbs_header.cloned(),
+        done: Vec::new(),
+    };
+
+    if state.config.safe_mode {
+        info!("[{}] Safe mode, decoded data from '{}' not written: {:?}", port, entry.station_name, entry.data);
+        return Ok(())
+    }
+
+    if state.config.test_mode {
+        if state.csv_enabled() {
+            state.memory_sink.lock().unwrap().push(entry);
+        }
+
+        return Ok(())
+    }
+
+    // Record the data in the write-ahead log before any sink, so it survives a crash in between
+    let wal_path = match state.wal {
+        Some(ref wal) => Some(retry_io(&state.config, || wal.append(&entry))?),
+        None => None,
+    };
+
+    let result = store_entry(&mut entry, state);
+
+    if let (Some(wal), Some(wal_path)) = (&state.wal, wal_path) {
+        match result {
+            Ok(_) => wal.remove(&wal_path)?,
+            // Remember the sinks that have the data, so the replay does not write it twice
+            Err(_) => wal.update(&wal_path, &entry)?,
+        }
+    }
+
+    result
+}
+
+// Passes the data to all sinks that do not have it yet. A failing sink does not stop the others,
+// the first error is returned and the finished sinks are recorded in the entry.
+fn store_entry(entry: &mut IWWalEntry, state: &IWServerState) -> Result<(), IWError> {
+    let station_name = entry.station_name.clone();
+    let station_name = &station_name;
+    let data = entry.data.clone();
+    let data = &data;
+    let sbs_header = entry.sbs_header.clone();
+    let sbs_header = sbs_header.as_ref();
+    // Entries written before the date was recorded go into today's daily file
+    let file_date = match entry.file_date {
+        Some(ref file_date) => file_date.clone(),
+        None => Local::now().format(IWFileGranularity::Day.date_format()).to_string(),
+    };
+    let mut result = Ok(());
+
+    if let Some(ref mqtt) = state.mqtt {
+        if !entry.is_done(WAL_SINK_MQTT) {
+            match mqtt.publish(station_name, data) {
+                Ok(_) => entry.set_done(WAL_SINK_MQTT),
+                Err(e) => {
+                    error!("Could not publish data to MQTT: '{}'", e);
+                    result = result.and(Err(e));
+                }
+            }
+        }
+    }
+
+    for sink in state.sinks.iter() {
+        if let IWSinkConfig::Json { dir, .. } = sink {
+            let name = format!("{}:{}", WAL_SINK_JSON, dir);
+
+            if entry.is_done(&name) {
+                continue
+            }
+
+            match retry_io(&state.config, || write_json_data(dir, data, station_name, &file_date, sbs_header)) {
+                Ok(file_name) => {
+                    log!(state.accept_level(), "JSON data written to: '{}'", file_name);
+                    entry.set_done(&name);
+                }
+                Err(e) => {
+                    error!("Could not write JSON data: '{}'", e);
+                    result = result.and(Err(e));
+                }
+            }
+        }
+    }
+
+    if let Some(ref database) = state.database {
+        if !entry.is_done(WAL_SINK_DATABASE) {
+            // The files are still written when the database is not reachable
+            match database.store(station_name, data) {
+                Ok(skipped) => {
+                    if skipped == 0 {
+                        log!(state.accept_level(), "Data stored in database");
+                    } else {
+                        log!(state.accept_level(), "Data stored in database, '{}' duplicate records skipped", skipped);
+                    }
+
+                    entry.set_done(WAL_SINK_DATABASE);
+
+                    // The database is reachable again
+                    if let Some(ref db_buffer) = state.db_buffer {
+                        if let Ok(_guard) = state.db_buffer_replay.try_lock() {
+                            if let Err(e) = replay_db_buffer(db_buffer, database) {
+                                warn!("Database buffer not written completely: '{}'", e);
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Could not store data in database: '{}'", e);
+                    state.metrics.inc_db_insert_failure(station_name);
+
+                    // Once in the buffer the data is safe, the database sink counts as done
+                    match state.db_buffer {
+                        Some(ref db_buffer) => match buffer_for_database(db_buffer, entry, &state.config) {
+                            Ok(_) => entry.set_done(WAL_SINK_DATABASE),
+                            Err(buffer_error) => {
+                                error!("Could not write data not stored in database: '{}'", buffer_error);
+                                result = result.and(Err(e));
+                            }
+                        },
+                        None => result = result.and(Err(e)),
+                    }
+                }
+            }
+        }
+    }
+
+    if state.csv_enabled() && !entry.is_done(WAL_SINK_CSV) {
+        match retry_io(&state.config, || export_data(&entry.folder, data, station_name, entry.schema_version.as_deref(), sbs_header.map(|header| header.momsn), &state.config)) {
+            Ok(_) => entry.set_done(WAL_SINK_CSV),
+            Err(e) => {
+                error!("Could not export data as CSV: '{}'", e);
+                result = result.and(Err(e));
+            }
+        }
+    }
+
+    result
+}
+
+// Keeps the buffer below max_spool_files and max_spool_bytes. Data that is shed is still in the CSV files,
+// so it counts as handled and Ok is returned.
+fn buffer_for_database(db_buffer: &IWWal, entry: &IWWalEntry, config: &IWConfiguration) -> Result<(), IWError> {
+    let mut files = db_buffer.files()?;
+    let mut total_bytes: u64 = files.iter().map(|(_, size)| size).sum();
+    let entry_bytes = serde_json::to_vec(entry)?.len() as u64;
+    let over_limit = |count: usize, bytes: u64| {
+        config.max_spool_files.is_some_and(|max| count > max) || config.max_spool_bytes.is_some_and(|max| bytes > max)
+    };
+
+    while over_limit(files.len() + 1, total_bytes + entry_bytes) {
+        if config.spool_overflow == IWSpoolOverflow::Stop || files.is_empty() {
+            error!("Database buffer full, data of '{}' is lost for the database", entry.station_name);
+            return Ok(())
+        }
+
+        let (path, size) = files.remove(0);
+        db_buffer.remove(&path)?;
+        total_bytes -= size;
+        error!("Database buffer full, oldest entry '{}' dropped, its data is lost for the database", path.display());
+    }
+
+    let path = db_buffer.append(entry)?;
+    warn!("Data not stored in database written to: '{}'", path.display());
+
+    Ok(())
+}
+
+// Stops at the first failure, the rest stays for the next successful insert
+fn replay_db_buffer(db_buffer: &IWWal, database: &IWDatabase) -> Result<(), IWError> {
+    for (path, entry) in db_buffer.pending()? {
+        database.store(&entry.station_name, &entry.data)?;
+        db_buffer.remove(&path)?;
+        info!("Buffered data of '{}' stored in database", entry.station_name);
+    }
+
+    Ok(())
+}
+
+// Entries that still fail stay in the write-ahead log for the next start, only the missing sinks are run again
+fn replay_wal(wal: &IWWal, state: &IWServerState) -> Result<(), IWError> {
+    let pending = wal.pending()?;
+
+    if !pending.is_empty() {
+        info!("Replaying {} entries from the write-ahead log", pending.len());
+    }
+
+    for (path, mut entry) in pending {
+        match store_entry(&mut entry, state) {
+            Ok(_) => wal.remove(&path)?,
+            Err(e) => {
+                error!("Could not replay '{}': '{}'", path.display(), e);
+                wal.update(&path, &entry)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn bind_listener(address: SocketAddr, backlog: i32) -> Result<TcpListener, IWError> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+    // Same as TcpListener::bind does on unix
+    socket.set_reuse_address(true)?;
+
+    if address.is_ipv6() {
+        // Otherwise some systems map IPv4 into this socket and the IPv4 bind on the same port fails
+        socket.set_only_v6(true)?;
+    }
+
+    socket.bind(&address.into())?;
+    socket.listen(backlog)?;
+
+    Ok(socket.into())
+}
+
+fn process_connection(stream: TcpStream, socket: SocketAddr, state: &IWServerState) {
+    // Count the connection before looking at the flag, so the shutdown can not miss it
+    let _guard = IWInFlightGuard::new(state, Some(&stream));
+
+    if state.is_shutdown() {
+        debug!("Connection from '{}' closed, shutdown in progress", socket);
+        return
+    }
+
+    match handle_connection(stream, socket, state) {
+        Ok(_) => {
+            log!(state.accept_level(), "Data was processed successfully");
+            let line = "#".repeat(60);
+            log!(state.accept_level(), "{}", line);
+        }
+        Err(IWError::RateLimited(_)) => {
+            // Already logged, a noisy peer gets only one line per connection
+        }
+        Err(e) => {
+            error!("An error occurred while processing the data: '{}'", e);
+        }
+    }
+}
+
+// Serves all listeners from one thread by polling them, the connections are handled by a fixed number of worker threads
+fn serve_listeners(listeners: Vec<TcpListener>, threads: usize, state: Arc<IWServerState>) {
+    for listener in listeners.iter() {
+        if let Err(e) = listener.set_nonblocking(true) {
+            error!("Could not set listener to non-blocking: '{}'", e);
+        }
+    }
+
+    // When all workers are busy the accept loop waits, new connections queue up in the accept backlog
+    let (sender, receiver) = sync_channel::<(TcpStream, SocketAddr)>(threads);
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    for _ in 0..threads {
+        let receiver = receiver.clone();
+        let state = state.clone();
+
+        spawn(move || {
+            loop {
+                // Only held while waiting, so the other workers can take the next connection
+                let next = receiver.lock().unwrap().recv();
+
+                match next {
+                    Ok((stream, socket)) => process_connection(stream, socket, &state),
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+
+    while !state.is_shutdown() {
+        let mut accepted = false;
+
+        for listener in listeners.iter() {
+            match listener.accept() {
+                Ok((stream, socket)) => {
+                    accepted = true;
+
+                    if let Err(e) = stream.set_nonblocking(false) {
+                        error!("Could not set connection to blocking: '{}'", e);
+                        continue
+                    }
+
+                    // Streaming connections keep their worker until the station disconnects
+                    if sender.send((stream, socket)).is_err() {
+                        error!("No worker left for the connection from '{}'", socket);
+                    }
+                }
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                }
+                Err(e) => {
+                    error!("An error occurred while accepting the connection: '{}'", e);
+                }
+            }
+        }
+
+        if !accepted {
+            sleep(StdDuration::from_millis(LISTENER_POLL_INTERVAL_MS));
+        }
+    }
+}
+
+// Returns the configured ports and whether they are still free
+fn check_ports(ip: IpAddr, ports: &[u16]) -> Vec<(u16, bool)> {
+    ports.iter().map(|port| {
+        let free = TcpListener::bind((ip, *port)).is_ok();
+        (*port, free)
+    }).collect()
+}
+
+pub fn start_server(config: &IWConfiguration) -> Result<Arc<IWServerState>, IWError> {
+    config.validate()?;
+    let bind_ip = config.bind_ip()?;
+
+    // Give the sockets of a previous instance time to go away
+    if config.startup_delay_secs > 0 {
+        info!("Waiting '{}' seconds before binding", config.startup_delay_secs);
+        sleep(StdDuration::from_secs(config.startup_delay_secs));
+    }
+
+    let port_status = check_ports(bind_ip, &config.ports);
+    let mut ports_in_use = Vec::new();
+
+    info!("Port check:");
+    for (port, free) in port_status {
+        info!("{}: {}", port, if free { "free" } else { "in-use" });
+
+        if !free {
+            ports_in_use.push(port);
+        }
+    }
+
+    if !ports_in_use.is_empty() {
+        if config.strict_port_check {
+            return Err(IWError::PortsInUse(ports_in_use))
+        }
+
+        warn!("Ports already in use: '{:?}'", ports_in_use);
+    }
+
+    let mut state = IWServerState::new(config.clone())?;
+
+    // A fresh install does not have the dump and JSON directories yet
+    if !config.writes_disabled() {
+        create_sink_dirs(&state.sinks)?;
+    }
+
+    if !config.writes_disabled() {
+        state.database = IWDatabase::new(config)?;
+
+        if state.database.is_some() {
+            state.db_buffer = Some(IWWal::new(&config.db_buffer_dir, config.wal_fsync)?);
+        }
+    }
+
+    if !config.writes_disabled() {
+        state.mqtt = IWMqttPublisher::new(config);
+    }
+
+    let mut listeners = Vec::new();
+
+    info!("Accept backlog: '{}'", config.accept_backlog);
+
+    for port in config.ports.iter() {
+        let mut addresses = vec![SocketAddr::new(bind_ip, *port)];
+
+        // Only next to the IPv4 wildcard, a specific address is bound alone
+        if config.bind_ipv6 && bind_ip == IpAddr::from([0, 0, 0, 0]) {
+            addresses.push(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], *port)));
+        }
+
+        for address in addresses {
+            match bind_listener(address, config.accept_backlog) {
+                Ok(listener) => {
+                    debug!("Create listener for: '{}'", address);
+                    listeners.push(listener);
+                }
+                Err(IWError::IO(ref e)) if address.is_ipv6() && e.kind() == std::io::ErrorKind::AddrInUse => {
+                    warn!("IPv6 bind skipped, address already in use: '{}'", address);
+                }
+                Err(e) => {
+                    error!("An error occurred while binding to port: '{}'", e);
+                }
+            }
+        }
+    }
+
+    state.bound_ports = listeners.iter().filter_map(|listener| listener.local_addr().ok()).map(|address| address.port()).collect();
+    state.bound_ports.sort_unstable();
+    state.bound_ports.dedup();
+
+    let state = Arc::new(state);
+
+    if let Some(ref wal) = state.wal {
+        replay_wal(wal, &state)?;
+    }
+
+    if let Some(health_port) = config.health_port {
+        start_http_server(bind_ip, health_port, state.clone());
+    }
+
+    if let Some(metrics_port) = config.metrics_port {
+        start_http_server(bind_ip, metrics_port, state.clone());
+    }
+
+    for target in config.poll_targets.iter() {
+        info!("Poll station '{}' at '{}' every '{}' seconds", target.station, target.address, target.interval_secs);
+        start_poller(target.clone(), state.clone());
+    }
+
+    if let (IWMetricsBackend::Statsd, Some(statsd_addr)) = (config.metrics_backend, &config.statsd_addr) {
+        start_statsd_emitter(statsd_addr.clone(), config.statsd_interval_secs, state.clone())?;
+    }
+
+    match config.listener_threads {
+        Some(threads) if threads > 0 => {
+            let state = state.clone();
+            spawn(move || serve_listeners(listeners, threads, state));
+        }
+        _ => {
+            for listener in listeners {
+                let state = state.clone();
+
+                spawn(move || {
+                    while !state.is_shutdown() {
+                        match listener.accept() {
+                            Ok((stream, socket)) => {
+                                process_connection(stream, socket, &state);
+                            }
+                            Err(e) => {
+                                error!("An error occurred while accepting the connection: '{}'", e);
+                            }
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    Ok(state)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+    use std::net::{TcpListener, TcpStream, SocketAddr, IpAddr};
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::fs::File;
+    use std::cell::RefCell;
+    use std::sync::Once;
+
+    use byteorder::{LittleEndian, BigEndian, WriteBytesExt};
+    use chrono::{Local, Utc, NaiveDateTime};
+    use log::{Level, Log, Metadata, Record};
+    use simplelog::{CombinedLogger, WriteLogger, SharedLogger, LevelFilter, Config, ConfigBuilder};
+
+    use super::{u32_to_timestamp, u16_to_f64, f64_to_u16, f64_to_u16_with_loss, parse_logger_status1, parse_logger_status2, parse_logger_status3, check_battery,
+        parse_weather_data_single, parse_weather_data, get_data_length, parse_binary_data,
+        precipitation_delta, apply_precip_mode, read_payload_station_id, parse_sbs_header, read_identity, resolve_identity, split_frames, timestamp_to_utc, write_json_data, HEADER_LENGTH1, pakbus_signature, pakbus_nullifier, crc16, replay_wal, store_entry, buffer_for_database, bind_listener, reject_skewed_records, handle_connection, hex_dump, retry_io, export_data, check_ports, ieee4_to_f64, port_to_station, decode_hex, trace_decode, serve_listeners, process_connection, create_sink_dirs, read_framed_message, poll_station, check_monotonic, find_gaps, normalize_wind_direction, start_server,
+        IWServerState, IWStationData, IWLoggerStatus, IWCfCardStatus, IWWeatherData, F2_NEG_INFINITY, F2_POS_INFINITY, F2_NAN};
+
+    use crate::error::IWError;
+    use crate::config::{IWConfiguration, IWStationConfiguration, IWPollTarget, IWChecksum, IWFileGranularity, IWIdentityField, IWIdentityEncoding, IWPrecipMode, IWSinkConfig, IWEndian, IWFileDedup, IWNanPolicy, IWSchema, IWSpoolOverflow, default_epoch_base};
+    use crate::wal::{IWWal, IWWalEntry};
+
+    const SBS_HEADER: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    thread_local! {
+        static CAPTURED_LINES: RefCell<Option<Vec<(Level, String)>>> = const { RefCell::new(None) };
+    }
+
+    // Keeps the log lines of the threads that asked for it, the tests run in parallel
+    struct IWCaptureLogger;
+
+    impl Log for IWCaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            CAPTURED_LINES.with(|lines| {
+                if let Some(ref mut lines) = *lines.borrow_mut() {
+                    lines.push((record.level(), record.args().to_string()));
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    impl SharedLogger for IWCaptureLogger {
+        fn level(&self) -> LevelFilter {
+            LevelFilter::Debug
+        }
+
+        fn config(&self) -> Option<&Config> {
+            None
+        }
+
+        fn as_log(self: Box<Self>) -> Box<dyn Log> {
+            self
+        }
+    }
+
+    // There is only one logger per process, so all tests share it
+    fn init_test_logger() {
+        static INIT: Once = Once::new();
+
+        INIT.call_once(|| {
+            let log_config = ConfigBuilder::new()
+                .set_time_to_local(true)
+                .set_time_format_str("%Y.%m.%d - %H:%M:%S")
+                .build();
+
+            let _ = CombinedLogger::init(vec![
+                WriteLogger::new(
+                    LevelFilter::Debug,
+                    log_config,
+                    File::options().append(true).create(true).open("test_iridium_weatherstation.log").unwrap()
+                ),
+                Box::new(IWCaptureLogger),
+            ]);
+        });
+    }
+
+    // Runs f and returns all lines it logged on this thread
+    fn capture_log<F: FnOnce()>(f: F) -> Vec<(Level, String)> {
+        init_test_logger();
+        CAPTURED_LINES.with(|lines| *lines.borrow_mut() = Some(Vec::new()));
+        f();
+        CAPTURED_LINES.with(|lines| lines.borrow_mut().take().unwrap_or_default())
+    }
+
+    fn timestamp_to_u32(timestamp: &str) -> u32 {
+        let datetime_base = NaiveDateTime::parse_from_str("1990-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let datetime = NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").unwrap();
+        (datetime - datetime_base).num_seconds() as u32
+    }
+
+    fn encode_weather_packet(records: &[IWWeatherData]) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        for record in records {
+            data.write_u32::<LittleEndian>(timestamp_to_u32(&record.timestamp)).unwrap();
+            data.write_u32::<LittleEndian>(0).unwrap();
+
+            for value in [record.air_temperature, record.air_relative_humidity, record.solar_radiation,
+                record.soil_water_content, record.soil_temperature, record.wind_speed, record.wind_max,
+                record.wind_direction, record.precipitation, record.air_pressure] {
+                data.write_u16::<BigEndian>(f64_to_u16(value)).unwrap();
+            }
+        }
+
+        let mut packet = vec![2];
+        packet.write_u16::<BigEndian>(data.len() as u16).unwrap();
+        packet.extend(data);
+        packet
+    }
+
+    fn with_sbs_header(packet: &[u8]) -> Vec<u8> {
+        [SBS_HEADER, packet].concat()
+    }
+
+    fn assert_fp2_eq(a: f64, b: f64) {
+        // FP2 keeps at most four significant digits
+        let tolerance = a.abs().max(1.0) * 1e-3;
+        assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+    }
+
+    fn send_test_connection(listener: &TcpListener, state: &IWServerState, data: &[u8]) -> Result<(), IWError> {
+        let port = listener.local_addr().unwrap().port();
+
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client.write_all(data).unwrap();
+        drop(client);
+
+        let (stream, socket) = listener.accept().unwrap();
+        handle_connection(stream, socket, state)
+    }
+
+    fn weather_data(timestamp: &str, precipitation: f64) -> IWWeatherData {
+        IWWeatherData {
+            timestamp: timestamp.to_string(),
+            air_temperature: 16.57,
+            air_relative_humidity: 76.58,
+            solar_radiation: 820.0,
+            soil_water_content: 0.048,
+            soil_temperature: 20.6,
+            wind_speed: 6.046,
+            wind_max: 8.27,
+            wind_direction: 258.5,
+            precipitation,
+            air_pressure: 978.0,
+            precipitation_raw: None,
+        }
+    }
+
+    #[test]
+    fn test_u32_to_timestamp() {
+        let result = u32_to_timestamp(843091200, default_epoch_base());
+        assert_eq!(result, "2016-09-19 00:00:00");
+
+        let epoch_base = NaiveDateTime::parse_from_str("2000-01-01 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let result = u32_to_timestamp(86400, epoch_base);
+        assert_eq!(result, "2000-01-02 00:00:00");
+    }
+
+    #[test]
+    fn test_u16_to_f64_1() {
+        assert_eq!(u16_to_f64(17660), 12.76);
+    }
+
+    #[test]
+    fn test_u16_to_f64_2() {
+        assert_eq!(u16_to_f64(17662), 12.78);
+    }
+
+    #[test]
+    fn test_u16_to_f64_3() {
+        assert_eq!(u16_to_f64(17664), 12.80);
+    }
+
+    #[test]
+    fn test_u16_to_f64_4() {
+        assert_eq!(u16_to_f64(24576), 0.0);
+    }
+
+    #[test]
+    fn test_u16_to_f64_5() {
+        assert_eq!(u16_to_f64(962), 962.0);
+    }
+
+    #[test]
+    fn test_u16_to_f64_6() {
+        assert_eq!(u16_to_f64(25576), 1.0);
+    }
+
+    #[test]
+    fn test_u16_to_f64_7() {
+        // Negative sign with zero mantissa
+        let result = u16_to_f64(0b11100000_00000000);
+        assert_eq!(result, 0.0);
+        assert!(result.is_sign_positive());
+
+        assert!(u16_to_f64(0b10000000_00000000).is_sign_positive());
+    }
+
+    #[test]
+    fn test_f64_to_u16_1() {
+        assert_eq!(f64_to_u16(12.76), 17660);
+        assert_eq!(f64_to_u16(12.78), 17662);
+        assert_eq!(f64_to_u16(12.80), 17664);
+        assert_eq!(f64_to_u16(0.0), 24576);
+        assert_eq!(f64_to_u16(962.0), 962);
+        assert_eq!(f64_to_u16(1.0), 25576);
+    }
+
+    #[test]
+    fn test_f64_to_u16_2() {
+        for value in [12.76, 12.78, 12.80, 0.0, 962.0, 1.0] {
+            assert_eq!(u16_to_f64(f64_to_u16(value)), value);
+        }
+
+        // Nearest representable value
+        assert_eq!(u16_to_f64(f64_to_u16(12.7649)), 12.76);
+        assert_eq!(u16_to_f64(f64_to_u16(1.23456)), 1.235);
+        assert_eq!(u16_to_f64(f64_to_u16(-4.56789)), -4.568);
+    }
+
+    #[test]
+    fn test_f64_to_u16_3() {
+        assert_eq!(f64_to_u16(f64::INFINITY), F2_POS_INFINITY);
+        assert_eq!(f64_to_u16(f64::NEG_INFINITY), F2_NEG_INFINITY);
+        assert_eq!(f64_to_u16(f64::NAN), F2_NAN);
+    }
+
+    #[test]
+    fn test_f64_to_u16_with_loss1() {
+        // Only four digits fit into the mantissa
+        let (result, achieved) = f64_to_u16_with_loss(1234.567, 0.1);
+
+        assert_eq!(result, 1235);
+        assert_eq!(achieved, 1235.0);
+        assert!(((1234.567 - achieved).abs() - 0.433).abs() < 1e-9);
+
+        let (result, achieved) = f64_to_u16_with_loss(12.76, 0.0);
+
+        assert_eq!(result, 17660);
+        assert_eq!(achieved, 12.76);
+
+        let (result, achieved) = f64_to_u16_with_loss(-9000.0, 0.0);
+
+        assert_eq!(result, F2_NEG_INFINITY);
+        assert_eq!(achieved, f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_get_data_length1() {
+        assert_eq!(get_data_length(&[0, 0, 0]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_get_data_length2() {
+        assert_eq!(get_data_length(&[0, 0, 27]).unwrap(), 27);
+    }
+
+    #[test]
+    fn test_get_data_length3() {
+        assert_eq!(get_data_length(&[0, 1, 0]).unwrap(), 256);
+    }
+
+    #[test]
+    fn test_get_data_length4() {
+        assert_eq!(get_data_length(&[0, 1, 4]).unwrap(), 260);
+    }
+
+    #[test]
+    fn test_get_data_length5() {
+        let result = get_data_length(&[2, 0]);
+
+        match result {
+            Err(IWError::DataTooShort(2)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_logger_status1() {
+        let result = parse_logger_status1(&[0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 99, 240, 99, 220], default_epoch_base()).unwrap();
+        let expected = IWLoggerStatus {
+            timestamp: "2016-09-19 00:00:00".to_string(),
+            solar_battery: 12.76,
+            lithium_battery: 1.008,
+            wind_diag: 0.988,
+            cf_card: 0,
+            battery_temperature: None,
+        };
+
+        assert_eq!(result, IWStationData::SingleData(expected));
+    }
+
+    #[test]
+    fn test_parse_logger_status2() {
+        let result = parse_logger_status2(&[0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 109, 31, 96, 0, 255, 255, 255, 127], default_epoch_base()).unwrap();
+        let expected = IWLoggerStatus {
+            timestamp: "2016-09-19 00:00:00".to_string(),
+            solar_battery: 12.76,
+            lithium_battery: 3.359,
+            wind_diag: 0.0,
+            cf_card: 4294967167,
+            battery_temperature: None,
+        };
+
+        assert_eq!(result, IWStationData::SingleData(expected));
+    }
+
+    #[test]
+    fn test_parse_logger_status3() {
+        let data = [2, 0, 20, 0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 109, 31, 96, 0, 255, 255, 255, 127, 72, 87];
+        let result = parse_binary_data(&data, &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+        let expected = IWLoggerStatus {
+            timestamp: "2016-09-19 00:00:00".to_string(),
+            solar_battery: 12.76,
+            lithium_battery: 3.359,
+            wind_diag: 0.0,
+            cf_card: 4294967167,
+            battery_temperature: Some(21.35),
+        };
+
+        assert_eq!(result, IWStationData::SingleData(expected));
+
+        let station_config = IWStationConfiguration {
+            schema: Some(IWSchema::Status3),
+            ..Default::default()
+        };
+        assert_eq!(parse_binary_data(&data, &station_config, default_epoch_base()).unwrap(), result);
+
+        let result = parse_logger_status3(&data[3..21], default_epoch_base());
+
+        match result {
+            Err(IWError::IO(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_logger_status1_error() {
+        let result = parse_logger_status1(&[0], default_epoch_base());
+
+        match result {
+            Err(IWError::IO(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_logger_status2_error() {
+        let result = parse_logger_status2(&[0], default_epoch_base());
+
+        match result {
+            Err(IWError::IO(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_logger_status1() {
+        let status = match parse_logger_status2(&[0, 233, 172, 60, 0, 0, 0, 0, 68, 223, 109, 41, 96, 0, 255, 255, 255, 127], default_epoch_base()).unwrap() {
+            IWStationData::SingleData(status) => status,
+            data => panic!("Expected SingleData, got: '{:?}'", data),
+        };
+
+        assert!(status.validate(&IWConfiguration::default()).is_empty());
+    }
+
+    #[test]
+    fn test_validate_logger_status3() {
+        let status = IWLoggerStatus {
+            timestamp: "2022-04-05 00:00:00".to_string(),
+            solar_battery: 0.0,
+            lithium_battery: 3.369,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        };
+        let config = IWConfiguration {
+            solar_battery_range: (0.0, 16.0),
+            reject_zero: vec!["solar_battery".to_string(), "lithium_battery".to_string()],
+            ..Default::default()
+        };
+        let problems = status.validate(&config);
+
+        // The zero wind_diag is a normal reading
+        assert!(problems.contains(&"solar_battery is zero".to_string()));
+        assert!(!problems.iter().any(|problem| problem.contains("wind_diag")));
+
+        let config = IWConfiguration {
+            reject_zero: vec!["wind_diag".to_string()],
+            ..config
+        };
+        assert!(status.validate(&config).contains(&"wind_diag is zero".to_string()));
+    }
+
+    #[test]
+    fn test_cf_card_status() {
+        let status = match parse_logger_status2(&[0, 233, 172, 60, 0, 0, 0, 0, 68, 223, 109, 41, 96, 0, 255, 255, 255, 127], default_epoch_base()).unwrap() {
+            IWStationData::SingleData(status) => status,
+            data => panic!("Expected SingleData, got: '{:?}'", data),
+        };
+
+        // 0xFFFFFF7F is -129 as signed integer
+        assert_eq!(status.cf_card, 4294967167);
+        assert_eq!(status.cf_card_status(), IWCfCardStatus::CardFault);
+
+        assert_eq!(IWLoggerStatus { cf_card: 1953, ..status.clone() }.cf_card_status(), IWCfCardStatus::Ok(1953));
+        assert_eq!(IWLoggerStatus { cf_card: 0, ..status.clone() }.cf_card_status(), IWCfCardStatus::Ok(0));
+        assert_eq!(IWLoggerStatus { cf_card: -1i32 as u32, ..status }.cf_card_status(), IWCfCardStatus::Error(-1));
+    }
+
+    #[test]
+    fn test_validate_logger_status2() {
+        // Lithium battery value from a misaligned decode
+        let status = match parse_logger_status1(&[0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 99, 240, 99, 220], default_epoch_base()).unwrap() {
+            IWStationData::SingleData(status) => status,
+            data => panic!("Expected SingleData, got: '{:?}'", data),
+        };
+        let problems = status.validate(&IWConfiguration::default());
+
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("lithium battery '1.008' V"));
+
+        let status = IWLoggerStatus {
+            solar_battery: 3.2,
+            lithium_battery: 12.4,
+            ..status
+        };
+
+        assert_eq!(status.validate(&IWConfiguration::default()).len(), 3);
+    }
+
+    #[test]
+    fn test_check_battery() {
+        let config = IWConfiguration::default();
+        let status = IWLoggerStatus {
+            timestamp: "2022-04-03 13:00:00".to_string(),
+            solar_battery: 11.5,
+            lithium_battery: 3.0,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        };
+
+        // Exactly at the minimum is still fine
+        assert!(!check_battery("Nahuelbuta", &status, &config));
+        assert!(!check_battery("Nahuelbuta", &IWLoggerStatus { solar_battery: 12.47, lithium_battery: 3.369, ..status.clone() }, &config));
+
+        assert!(check_battery("Nahuelbuta", &IWLoggerStatus { solar_battery: 11.49, ..status.clone() }, &config));
+        assert!(check_battery("Nahuelbuta", &IWLoggerStatus { lithium_battery: 2.99, ..status }, &config));
+    }
+
+    #[test]
+    fn test_parse_weather_data_single() {
+        let result = parse_weather_data_single(&[0, 141, 64, 50, 0, 0, 0, 0, 69, 222, 35, 229, 92, 249, 96, 77, 70, 100, 97, 103, 98, 238, 43, 190, 99, 232, 3, 194], &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+        let expected = IWWeatherData {
+            timestamp: "2016-09-19 00:00:00".to_string(),
+            air_temperature: 15.02,
+            air_relative_humidity: 99.7,
+            solar_radiation: 74.17,
+            soil_water_content: 0.077,
+            soil_temperature: 16.36,
+            wind_speed: 0.359,
+            wind_max: 0.75,
+            wind_direction: 300.6,
+            precipitation: 1.0,
+            air_pressure: 962.0,
+            precipitation_raw: None,
+        };
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_ieee4_to_f64() {
+        assert_eq!(ieee4_to_f64([63, 128, 0, 0], IWEndian::Big), 1.0);
+        assert_eq!(ieee4_to_f64([192, 32, 0, 0], IWEndian::Big), -2.5);
+        assert_eq!(ieee4_to_f64([65, 132, 143, 92], IWEndian::Big), 16.57);
+        assert_eq!(ieee4_to_f64([92, 143, 132, 65], IWEndian::Little), 16.57);
+        assert_eq!(ieee4_to_f64([0, 80, 125, 68], IWEndian::Little), 1013.25);
+        assert_eq!(ieee4_to_f64([127, 128, 0, 0], IWEndian::Big), f64::INFINITY);
+        assert!(ieee4_to_f64([127, 192, 0, 0], IWEndian::Big).is_nan());
+    }
+
+    #[test]
+    fn test_parse_weather_data_ieee4() {
+        let station_config = IWStationConfiguration {
+            ieee4_fields: vec!["air_temperature".to_string(), "air_pressure".to_string()],
+            ..Default::default()
+        };
+
+        let result = parse_weather_data(&[208, 252, 170, 60, 0, 0, 0, 0, 65, 132, 143, 92, 93, 234, 3, 52, 96, 48, 72, 12,
+            119, 158, 67, 59, 42, 25, 96, 0, 68, 125, 80, 0], &station_config, default_epoch_base()).unwrap();
+
+        let expected = IWWeatherData {
+            air_pressure: 1013.25,
+            ..weather_data("2022-04-03 13:00:00", 0.0)
+        };
+
+        assert_eq!(result, IWStationData::MultipleData(vec![expected]));
+    }
+
+    #[test]
+    fn test_weather_data_validate() {
+        let config = IWConfiguration::default();
+
+        assert!(weather_data("2022-04-03 13:00:00", f64::NAN).validate(&config).is_empty());
+
+        let data = IWWeatherData {
+            air_relative_humidity: 5000.0,
+            solar_radiation: -3.0,
+            ..weather_data("2022-04-03 13:00:00", 0.0)
+        };
+        let problems = data.validate(&config);
+
+        assert_eq!(problems.len(), 2);
+
+        match problems[0] {
+            IWError::OutOfRange { ref field, value } if field == "air_relative_humidity" && value == 5000.0 => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", problems[0]);
+            }
+        }
+
+        // A different sensor with its own limits
+        let mut config = IWConfiguration::default();
+        config.weather_ranges.insert("air_pressure".to_string(), (900.0, 1100.0));
+        config.weather_ranges.insert("solar_radiation".to_string(), (-5.0, 2000.0));
+
+        let problems = IWWeatherData { air_pressure: 500.0, ..data }.validate(&config);
+        let fields: Vec<String> = problems.iter().map(|e| match e {
+            IWError::OutOfRange { field, .. } => field.clone(),
+            _ => panic!("Expected IWError, got: '{:?}'", e),
+        }).collect();
+
+        assert_eq!(fields, vec!["air_relative_humidity", "air_pressure"]);
+    }
+
+    #[test]
+    fn test_check_monotonic() {
+        let data = vec![weather_data("2022-04-03 13:00:00", 0.0), weather_data("2022-04-03 14:00:00", 0.0), weather_data("2022-04-03 14:00:00", 0.0)];
+        assert!(check_monotonic(&data, 0).is_ok());
+
+        let data = vec![weather_data("2022-04-03 13:00:00", 0.0), weather_data("2022-04-03 14:00:00", 0.0), weather_data("2022-04-03 13:59:30", 0.0)];
+        let result = check_monotonic(&data, 0);
+
+        match result {
+            Err(IWError::NonMonotonicTimestamps { ref previous, ref current }) if previous == "2022-04-03 14:00:00" && current == "2022-04-03 13:59:30" => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+
+        assert!(check_monotonic(&data, 60).is_ok());
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let data = vec![weather_data("2022-04-03 13:00:00", 0.0), weather_data("2022-04-03 14:00:00", 0.0), weather_data("2022-04-03 16:00:00", 0.0)];
+        let station_config = IWStationConfiguration::default();
+
+        assert_eq!(find_gaps(&data, station_config.max_gap_secs(3600)), vec![("2022-04-03 14:00:00".to_string(), "2022-04-03 16:00:00".to_string())]);
+
+        // Two hours are still within the tolerance
+        let station_config = IWStationConfiguration { gap_tolerance: 2.0, ..Default::default() };
+        assert!(find_gaps(&data, station_config.max_gap_secs(3600)).is_empty());
+
+        // The station interval replaces the global one
+        let station_config = IWStationConfiguration { expected_interval_secs: Some(600), ..Default::default() };
+        assert_eq!(find_gaps(&data, station_config.max_gap_secs(3600)).len(), 2);
+    }
+
+    #[test]
+    fn test_require_monotonic() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = IWConfiguration {
+            test_mode: true,
+            ..Default::default()
+        };
+        config.stations.insert(port, IWStationConfiguration {
+            require_monotonic: true,
+            ..Default::default()
+        });
+        let state = IWServerState::new(config).unwrap();
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 14:00:00", 0.0), weather_data("2022-04-03 13:00:00", 0.0)]));
+        let result = send_test_connection(&listener, &state, &data);
+
+        match result {
+            Err(IWError::NonMonotonicTimestamps { .. }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+
+        assert!(state.memory_sink.lock().unwrap().is_empty());
+        assert_eq!(state.metrics.parse_errors(&port_to_station(port), "NonMonotonicTimestamps"), 1);
+    }
+
+    #[test]
+    fn test_precip_mm_per_tip() {
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 5.0)]);
+
+        let station_config = IWStationConfiguration {
+            precip_mm_per_tip: Some(0.2),
+            ..Default::default()
+        };
+
+        match parse_binary_data(&packet, &station_config, default_epoch_base()).unwrap() {
+            IWStationData::MultipleData(data) => {
+                assert_eq!(data[0].precipitation, 1.0);
+                assert_eq!(data[0].precipitation_raw, None);
+            }
+            data => panic!("Expected MultipleData, got: '{:?}'", data),
+        }
+
+        let station_config = IWStationConfiguration {
+            precip_mm_per_tip: Some(0.2),
+            store_raw: true,
+            ..Default::default()
+        };
+
+        match parse_binary_data(&packet, &station_config, default_epoch_base()).unwrap() {
+            IWStationData::MultipleData(data) => {
+                assert_eq!(data[0].precipitation, 1.0);
+                assert_eq!(data[0].precipitation_raw, Some(5.0));
+            }
+            data => panic!("Expected MultipleData, got: '{:?}'", data),
+        }
+    }
+
+    #[test]
+    fn test_pipelines() {
+        let config: IWConfiguration = serde_json::from_str(r#"{
+            "ports": [2001, 2002],
+            "alive_message_intervall": 3600,
+            "pipelines": {
+                "tipping_bucket": { "precip_mm_per_tip": 0.2, "store_raw": true },
+                "plain": { "precip_mode": "cumulative" }
+            },
+            "stations": {
+                "2001": { "pipeline": "tipping_bucket" },
+                "2002": { "pipeline": "plain", "enabled": false }
+            }
+        }"#).unwrap();
+        assert!(config.validate().is_ok());
+
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 5.0)]);
+
+        match parse_binary_data(&packet, &config.station(2001), default_epoch_base()).unwrap() {
+            IWStationData::MultipleData(data) => {
+                assert_eq!(data[0].precipitation, 1.0);
+                assert_eq!(data[0].precipitation_raw, Some(5.0));
+            }
+            data => panic!("Expected MultipleData, got: '{:?}'", data),
+        }
+
+        match parse_binary_data(&packet, &config.station(2002), default_epoch_base()).unwrap() {
+            IWStationData::MultipleData(data) => {
+                assert_eq!(data[0].precipitation, 5.0);
+                assert_eq!(data[0].precipitation_raw, None);
+            }
+            data => panic!("Expected MultipleData, got: '{:?}'", data),
+        }
+
+        // The station keeps its own enabled flag, everything else comes from the pipeline
+        assert!(config.station(2001).enabled);
+        assert!(!config.station(2002).enabled);
+        assert_eq!(config.station(2002).precip_mode, IWPrecipMode::Cumulative);
+
+        let config: IWConfiguration = serde_json::from_str(r#"{
+            "ports": [2001],
+            "alive_message_intervall": 3600,
+            "stations": { "2001": { "pipeline": "unknown" } }
+        }"#).unwrap();
+        let result = config.validate();
+
+        match result {
+            Err(IWError::InvalidConfig(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_normalize_wind_direction() {
+        assert_eq!(normalize_wind_direction(360.0), 0.0);
+        assert_eq!(normalize_wind_direction(258.5), 258.5);
+
+        let record = IWWeatherData {
+            wind_direction: 360.0,
+            ..weather_data("2022-04-03 13:00:00", 0.0)
+        };
+        let packet = encode_weather_packet(&[record]);
+
+        match parse_binary_data(&packet, &IWStationConfiguration::default(), default_epoch_base()).unwrap() {
+            IWStationData::MultipleData(data) => assert_eq!(data[0].wind_direction, 0.0),
+            data => panic!("Expected MultipleData, got: '{:?}'", data),
+        }
+
+        let station_config = IWStationConfiguration {
+            normalize_360_to_0: false,
+            ..Default::default()
+        };
+
+        match parse_binary_data(&packet, &station_config, default_epoch_base()).unwrap() {
+            IWStationData::MultipleData(data) => assert_eq!(data[0].wind_direction, 360.0),
+            data => panic!("Expected MultipleData, got: '{:?}'", data),
+        }
+    }
+
+    #[test]
+    fn test_parse_weather_data_single_error() {
+        let result = parse_weather_data_single(&[0], &IWStationConfiguration::default(), default_epoch_base());
+
+        match result {
+            Err(IWError::IO(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_weather_data() {
+        let result = parse_weather_data(&[
+            208, 252, 170, 60, 0, 0, 0, 0, 70, 121, 93, 234, 3, 52, 96, 48, 72, 12, 119, 158, 67, 59, 42, 25, 96, 0, 3, 210,
+            224, 10, 171, 60, 0, 0, 0, 0, 70, 146, 92, 255, 3, 108, 96, 48, 72, 12, 120, 106, 67, 66, 42, 30, 96, 0, 3, 210], &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+
+        let data1 = IWWeatherData {
+            timestamp: "2022-04-03 13:00:00".to_string(),
+            air_temperature: 16.57,
+            air_relative_humidity: 76.58,
+            solar_radiation: 820.0,
+            soil_water_content: 0.048,
+            soil_temperature: 20.6,
+            wind_speed: 6.046,
+            wind_max: 8.27,
+            wind_direction: 258.5,
+            precipitation: 0.0,
+            air_pressure: 978.0,
+            precipitation_raw: None,
+        };
+
+        let data2 = IWWeatherData {
+            timestamp: "2022-04-03 14:00:00".to_string(),
+            air_temperature: 16.82,
+            air_relative_humidity: 74.23,
+            solar_radiation: 876.0,
+            soil_water_content: 0.048,
+            soil_temperature: 20.6,
+            wind_speed: 6.25,
+            wind_max: 8.34,
+            wind_direction: 259.0,
+            precipitation: 0.0,
+            air_pressure: 978.0,
+            precipitation_raw: None,
+        };
+
+        let combined = IWStationData::MultipleData(vec![data1, data2]);
+
+        assert_eq!(result, combined);
+    }
+
+    #[test]
+    fn test_parse_weather_data_padding() {
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]);
+        let result = parse_weather_data(&[&packet[3..], &[0, 0]].concat(), &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+
+        assert_eq!(result, IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.0)]));
+        assert_eq!(parse_weather_data(&[0], &IWStationConfiguration::default(), default_epoch_base()).unwrap(), IWStationData::MultipleData(Vec::new()));
+    }
+
+    #[test]
+    fn test_parse_weather_data_error() {
+        let result = parse_weather_data(&[1], &IWStationConfiguration::default(), default_epoch_base());
+
+        match result {
+            Err(IWError::AlignmentError { expected: 28, actual: 1 }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_weather_data_misaligned() {
+        // Two stray bytes between the records shift everything after them
+        let packet1 = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]);
+        let packet2 = encode_weather_packet(&[weather_data("2022-04-03 14:00:00", 0.0)]);
+        let data = [&packet1[3..], &[7, 7], &packet2[3..]].concat();
+
+        let result = parse_weather_data(&data, &IWStationConfiguration::default(), default_epoch_base());
+
+        match result {
+            Err(IWError::AlignmentError { expected: 28, actual: 2 }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_binary_data1() {
+        let result = parse_binary_data(&[2, 0, 14, 128, 151, 171, 60, 0, 0, 0, 0, 68, 209, 109, 116, 96, 0], &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+
+        let data1 = IWLoggerStatus {
+            timestamp: "2022-04-04 00:00:00".to_string(),
+            solar_battery: 12.33,
+            lithium_battery: 3.444,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        };
+
+        let data2 = IWStationData::SingleData(data1);
+
+        assert_eq!(result, data2);
+    }
+
+    #[test]
+    fn test_parse_binary_data2() {
+        let result = parse_binary_data(&[2, 0, 18, 0, 233, 172, 60, 0, 0, 0, 0, 68, 223, 109, 41, 96, 0, 255, 255, 255, 127], &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+
+        let data1 = IWLoggerStatus {
+            timestamp: "2022-04-05 00:00:00".to_string(),
+            solar_battery: 12.47,
+            lithium_battery: 3.369,
+            wind_diag: 0.0,
+            cf_card: 4294967167,
+            battery_temperature: None,
+        };
+
+        let data2 = IWStationData::SingleData(data1);
+
+        assert_eq!(result, data2);
+    }
+
+    #[test]
+    fn test_parse_binary_data3() {
+        let result = parse_binary_data(&[2, 0, 28, 208, 252, 170, 60, 0, 0, 0, 0, 70, 121, 93, 234, 3, 52, 96, 48, 72, 12, 119, 158, 67, 59, 42, 25, 96, 0, 3, 210], &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+
+        let data1 = IWWeatherData {
+            timestamp: "2022-04-03 13:00:00".to_string(),
+            air_temperature: 16.57,
+            air_relative_humidity: 76.58,
+            solar_radiation: 820.0,
+            soil_water_content: 0.048,
+            soil_temperature: 20.6,
+            wind_speed: 6.046,
+            wind_max: 8.27,
+            wind_direction: 258.5,
+            precipitation: 0.0,
+            air_pressure: 978.0,
+            precipitation_raw: None,
+        };
+
+        let data2 = IWStationData::MultipleData(vec![data1]);
+
+        assert_eq!(result, data2);
+    }
+
+    #[test]
+    fn test_parse_binary_data_error1() {
+        let result = parse_binary_data(&[0], &IWStationConfiguration::default(), default_epoch_base());
+
+        match result {
+            Err(IWError::DataTooShort(1)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_binary_data_error2() {
+        let result = parse_binary_data(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], &IWStationConfiguration::default(), default_epoch_base());
+
+        match result {
+            Err(IWError::DataLengthMismatch(0)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_binary_data_error3() {
+        let result = parse_binary_data(&[0, 0, 14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], &IWStationConfiguration::default(), default_epoch_base());
+
+        match result {
+            Err(IWError::InvalidDataHeader) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_binary_data_error4() {
+        let result = parse_binary_data(&[2, 0, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], &IWStationConfiguration::default(), default_epoch_base());
+
+        match result {
+            Err(IWError::OddDataLength(15)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+
+        let frame = [2, 0, 14, 128, 151, 171, 60, 0, 0, 0, 0, 68, 209, 109, 116, 96, 0];
+
+        // Signature nullifier
+        let mut data = frame.to_vec();
+        data.extend_from_slice(&pakbus_nullifier(pakbus_signature(&frame, 0xAAAA)).to_be_bytes());
+        assert_eq!(pakbus_signature(&data, 0xAAAA), 0);
+
+        let station_config = IWStationConfiguration {
+            checksum: IWChecksum::Signature,
+            ..Default::default()
+        };
+        let expected = parse_binary_data(&frame, &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+        assert_eq!(parse_binary_data(&data, &station_config, default_epoch_base()).unwrap(), expected);
+
+        // CRC-16
+        let mut data = frame.to_vec();
+        data.extend_from_slice(&crc16(&frame).to_be_bytes());
+
+        let station_config = IWStationConfiguration {
+            checksum: IWChecksum::Crc16,
+            ..Default::default()
+        };
+        assert_eq!(parse_binary_data(&data, &station_config, default_epoch_base()).unwrap(), expected);
+
+        // A single flipped bit passes the length check, but not the checksum
+        data[12] ^= 0x10;
+        let result = parse_binary_data(&data, &station_config, default_epoch_base());
+
+        match result {
+            Err(IWError::ChecksumMismatch { .. }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_binary_data_schema1() {
+        let data = [2, 0, 18, 0, 233, 172, 60, 0, 0, 0, 0, 68, 223, 109, 41, 96, 0, 255, 255, 255, 127];
+        let mut station_config = IWStationConfiguration {
+            schema: Some(IWSchema::Status1),
+            ..Default::default()
+        };
+
+        let result = parse_binary_data(&data, &station_config, default_epoch_base());
+
+        match result {
+            Err(IWError::SchemaMismatch(18)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+
+        station_config.schema_autodetect = true;
+
+        assert_eq!(parse_binary_data(&data, &station_config, default_epoch_base()).unwrap(), parse_binary_data(&data, &IWStationConfiguration::default(), default_epoch_base()).unwrap());
+    }
+
+    #[test]
+    fn test_parse_binary_data_schema2() {
+        let station_config = IWStationConfiguration {
+            schema: Some(IWSchema::Weather),
+            schema_autodetect: true,
+            ..Default::default()
+        };
+
+        let result = parse_binary_data(&[2, 0, 16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], &station_config, default_epoch_base());
+
+        match result {
+            Err(IWError::SchemaMismatch(16)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_precipitation_delta() {
+        assert!(precipitation_delta(None, 2.0).is_nan());
+        assert_eq!(precipitation_delta(Some(2.0), 2.5), 0.5);
+        assert_eq!(precipitation_delta(Some(2.5), 0.2), 0.2);
+    }
+
+    #[test]
+    fn test_apply_precip_mode_interval() {
+        let mut data = vec![
+            weather_data("2022-04-03 13:00:00", 1.0),
+            weather_data("2022-04-03 14:00:00", 0.0),
+            weather_data("2022-04-03 15:00:00", 0.4),
+        ];
+        let mut last_total = Some(5.0);
+
+        apply_precip_mode(&mut data, IWPrecipMode::Interval, true, &mut last_total);
+
+        let precipitation: Vec<f64> = data.iter().map(|entry| entry.precipitation).collect();
+        assert_eq!(precipitation, vec![1.0, 0.0, 0.4]);
+        assert_eq!(last_total, Some(5.0));
+    }
+
+    #[test]
+    fn test_apply_precip_mode_cumulative() {
+        let mut data = vec![
+            weather_data("2022-04-03 13:00:00", 10.0),
+            weather_data("2022-04-03 14:00:00", 10.5),
+            weather_data("2022-04-03 15:00:00", 0.5),
+            weather_data("2022-04-03 16:00:00", 1.0),
+        ];
+        let mut last_total = Some(9.0);
+
+        apply_precip_mode(&mut data, IWPrecipMode::Cumulative, false, &mut last_total);
+
+        let precipitation: Vec<f64> = data.iter().map(|entry| entry.precipitation).collect();
+        assert_eq!(precipitation, vec![1.0, 0.5, 0.5, 0.5]);
+        assert_eq!(last_total, Some(1.0));
+        assert!(data.iter().all(|entry| entry.precipitation_raw.is_none()));
+
+        // The counter is reset after the second record, the raw values keep the counter as sent
+        let mut data = vec![
+            weather_data("2022-04-03 13:00:00", 10.0),
+            weather_data("2022-04-03 14:00:00", 10.5),
+            weather_data("2022-04-03 15:00:00", 0.5),
+            IWWeatherData { precipitation_raw: Some(5.0), ..weather_data("2022-04-03 16:00:00", 1.0) },
+        ];
+        let mut last_total = Some(9.0);
+
+        apply_precip_mode(&mut data, IWPrecipMode::Cumulative, true, &mut last_total);
+
+        let precipitation: Vec<f64> = data.iter().map(|entry| entry.precipitation).collect();
+        assert_eq!(precipitation, vec![1.0, 0.5, 0.5, 0.5]);
+        let raw: Vec<Option<f64>> = data.iter().map(|entry| entry.precipitation_raw).collect();
+        assert_eq!(raw, vec![Some(10.0), Some(10.5), Some(0.5), Some(5.0)]);
+        assert_eq!(last_total, Some(1.0));
+    }
+
+    #[test]
+    fn test_precipitation_totals_restart() {
+        let dir = std::env::temp_dir().join(format!("iw_test_precipitation_totals_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = IWConfiguration {
+            sinks: vec![IWSinkConfig::WebSocket { enabled: true }],
+            precip_state_file: dir.join("precipitation_totals.json").to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        config.stations.insert(port, IWStationConfiguration {
+            precip_mode: IWPrecipMode::Cumulative,
+            ..Default::default()
+        });
+
+        let state = IWServerState::new(config.clone()).unwrap();
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 5.0)]);
+        send_test_connection(&listener, &state, &with_sbs_header(&packet)).unwrap();
+        drop(state);
+
+        // After a restart the saved total is the reference for the first record
+        let state = IWServerState::new(config).unwrap();
+        let receiver = state.websocket.subscribe();
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 14:00:00", 6.5)]);
+        send_test_connection(&listener, &state, &with_sbs_header(&packet)).unwrap();
+
+        assert!(receiver.try_recv().unwrap().contains("\"precipitation\":1.5"));
+        assert_eq!(state.precipitation_totals.lock().unwrap().get(&port_to_station(port)), Some(&6.5));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encode_weather_packet() {
+        let records = vec![
+            weather_data("2022-04-03 13:00:00", 0.0),
+            IWWeatherData {
+                air_temperature: -3.456,
+                wind_direction: 359.94,
+                ..weather_data("2022-04-03 14:00:00", 1.2)
+            },
+        ];
+
+        let packet = encode_weather_packet(&records);
+        assert_eq!(with_sbs_header(&packet).len(), 48 + 3 + (2 * 28));
+
+        match parse_binary_data(&packet, &IWStationConfiguration::default(), default_epoch_base()).unwrap() {
+            IWStationData::MultipleData(result) => {
+                assert_eq!(result.len(), records.len());
+
+                for (a, b) in result.iter().zip(records.iter()) {
+                    assert_eq!(a.timestamp, b.timestamp);
+                    assert_fp2_eq(a.air_temperature, b.air_temperature);
+                    assert_fp2_eq(a.air_relative_humidity, b.air_relative_humidity);
+                    assert_fp2_eq(a.solar_radiation, b.solar_radiation);
+                    assert_fp2_eq(a.soil_water_content, b.soil_water_content);
+                    assert_fp2_eq(a.soil_temperature, b.soil_temperature);
+                    assert_fp2_eq(a.wind_speed, b.wind_speed);
+                    assert_fp2_eq(a.wind_max, b.wind_max);
+                    assert_fp2_eq(a.wind_direction, b.wind_direction);
+                    assert_fp2_eq(a.precipitation, b.precipitation);
+                    assert_fp2_eq(a.air_pressure, b.air_pressure);
+                }
+            }
+            result => {
+                panic!("Expected MultipleData, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_sbs_header() {
+        let header = parse_sbs_header(SBS_HEADER).unwrap();
+        assert_eq!(header.imei, "");
+        assert_eq!(header.momsn, 0);
+
+        let mut buffer = vec![1, 0, 73, 1, 0, 28, 0, 0, 48, 57];
+        buffer.extend(b"300234010753370");
+        buffer.extend([0, 0, 42, 0, 0, 0x62, 0x49, 0x9a, 0x50]);
+        // South and west, 37 deg 48.000 min and 73 deg 0.000 min
+        buffer.extend([3, 0, 11, 3, 37, 0xbb, 0x80, 73, 0, 0, 0, 0, 0, 10]);
+
+        let header = parse_sbs_header(&buffer).unwrap();
+        assert_eq!(header.message_length, 73);
+        assert_eq!(header.cdr_reference, 12345);
+        assert_eq!(header.imei, "300234010753370");
+        assert_eq!(header.momsn, 42);
+        assert_eq!(header.session_time, 1648990800);
+        assert!((header.latitude + 37.8).abs() < 1e-9);
+        assert!((header.longitude + 73.0).abs() < 1e-9);
+        assert_eq!(header.cep_radius, 10);
+
+        let result = parse_sbs_header(&buffer[..47]);
+
+        match result {
+            Err(IWError::DataTooShort(47)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_payload_station_id() {
+        let station_config = IWStationConfiguration {
+            payload_station_id: true,
+            id_to_station: [(1, "Logger_A".to_string()), (2, "Logger_B".to_string())].into_iter().collect(),
+            ..Default::default()
+        };
+
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]);
+
+        let buffer1 = [&[0, 1], packet.as_slice()].concat();
+        let buffer2 = [&[0, 2], packet.as_slice()].concat();
+
+        let (name1, rest1) = read_payload_station_id(&buffer1, &station_config, "test1").unwrap();
+        let (name2, rest2) = read_payload_station_id(&buffer2, &station_config, "test1").unwrap();
+        let (name3, _) = read_payload_station_id(&[0, 3], &station_config, "test1").unwrap();
+
+        assert_eq!(name1, "Logger_A");
+        assert_eq!(name2, "Logger_B");
+        assert_eq!(name3, "test1");
+        assert_eq!(rest1, packet);
+        assert_eq!(rest2, packet);
+    }
+
+    #[test]
+    fn test_replay_wal() {
+        let dir = std::env::temp_dir().join(format!("iw_test_replay_wal_{}", std::process::id()));
+        let wal_dir = dir.join("wal");
+        let folder = dir.join("2100_Na");
+        std::fs::create_dir_all(&folder).unwrap();
+
+        // Simulate a crash: the entry was logged, but never exported and removed
+        {
+            let wal = IWWal::new(&wal_dir, true).unwrap();
+            let entry = IWWalEntry {
+                station_name: "Nahuelbuta".to_string(),
+                folder: folder.to_str().unwrap().to_string(),
+                data: IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]),
+                schema_version: None,
+                file_date: None,
+                sbs_header: None,
+                done: Vec::new(),
+            };
+            wal.append(&entry).unwrap();
+        }
+
+        let wal = IWWal::new(&wal_dir, true).unwrap();
+        assert_eq!(wal.pending().unwrap().len(), 1);
+
+        // Every sink gets the data again, not only the CSV files
+        let config = IWConfiguration {
+            sinks: vec![IWSinkConfig::Csv { enabled: true }, IWSinkConfig::Json { enabled: true, dir: dir.to_str().unwrap().to_string() }],
+            ..Default::default()
+        };
+        replay_wal(&wal, &IWServerState::new(config).unwrap()).unwrap();
+
+        assert!(wal.pending().unwrap().is_empty());
+        let csv = std::fs::read_to_string(folder.join("all_data_multiple.csv")).unwrap();
+        assert!(csv.contains("2022-04-03 13:00:00,Nahuelbuta,16.570,"));
+        let json = std::fs::read_to_string(dir.join(format!("Nahuelbuta_{}.json", Local::now().format("%Y_%m_%d")))).unwrap();
+        assert!(json.contains("\"timestamp\":\"2022-04-03 13:00:00\""));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_buffer_for_database() {
+        let dir = std::env::temp_dir().join(format!("iw_test_buffer_for_database_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let entry = |timestamp: &str| IWWalEntry {
+            station_name: "Nahuelbuta".to_string(),
+            folder: "2100_Na".to_string(),
+            data: IWStationData::MultipleData(vec![weather_data(timestamp, 0.5)]),
+            schema_version: None,
+            file_date: None,
+            sbs_header: None,
+            done: Vec::new(),
+        };
+        let buffered = |db_buffer: &IWWal| -> Vec<IWStationData> {
+            db_buffer.pending().unwrap().into_iter().map(|(_, entry)| entry.data).collect()
+        };
+
+        // The oldest entry makes room for the new one
+        let db_buffer = IWWal::new(dir.join("drop_oldest"), false).unwrap();
+        let config = IWConfiguration {
+            max_spool_files: Some(2),
+            ..Default::default()
+        };
+
+        for timestamp in ["2022-04-03 13:00:00", "2022-04-03 14:00:00", "2022-04-03 15:00:00"] {
+            buffer_for_database(&db_buffer, &entry(timestamp), &config).unwrap();
+        }
+
+        assert_eq!(buffered(&db_buffer), vec![
+            IWStationData::MultipleData(vec![weather_data("2022-04-03 14:00:00", 0.5)]),
+            IWStationData::MultipleData(vec![weather_data("2022-04-03 15:00:00", 0.5)]),
+        ]);
+
+        // The buffer keeps what it has, new data is not buffered any more
+        let db_buffer = IWWal::new(dir.join("stop"), false).unwrap();
+        let config = IWConfiguration {
+            max_spool_files: Some(2),
+            spool_overflow: IWSpoolOverflow::Stop,
+            ..Default::default()
+        };
+
+        for timestamp in ["2022-04-03 13:00:00", "2022-04-03 14:00:00", "2022-04-03 15:00:00"] {
+            buffer_for_database(&db_buffer, &entry(timestamp), &config).unwrap();
+        }
+
+        assert_eq!(buffered(&db_buffer), vec![
+            IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]),
+            IWStationData::MultipleData(vec![weather_data("2022-04-03 14:00:00", 0.5)]),
+        ]);
+
+        // Room for one entry only
+        let db_buffer = IWWal::new(dir.join("bytes"), false).unwrap();
+        let config = IWConfiguration {
+            max_spool_bytes: Some(serde_json::to_vec(&entry("2022-04-03 13:00:00")).unwrap().len() as u64),
+            ..Default::default()
+        };
+
+        for timestamp in ["2022-04-03 13:00:00", "2022-04-03 14:00:00"] {
+            buffer_for_database(&db_buffer, &entry(timestamp), &config).unwrap();
+        }
+
+        assert_eq!(buffered(&db_buffer), vec![IWStationData::MultipleData(vec![weather_data("2022-04-03 14:00:00", 0.5)])]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_replay_wal_missing_sinks() {
+        let dir = std::env::temp_dir().join(format!("iw_test_replay_wal_missing_sinks_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let folder = dir.join("2100_Na");
+        let json_dir = dir.join("json");
+        std::fs::create_dir_all(&json_dir).unwrap();
+
+        let wal = IWWal::new(dir.join("wal"), true).unwrap();
+        let mut entry = IWWalEntry {
+            station_name: "Nahuelbuta".to_string(),
+            folder: folder.to_str().unwrap().to_string(),
+            data: IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]),
+            schema_version: None,
+            file_date: Some("2022_04_03".to_string()),
+            sbs_header: None,
+            done: Vec::new(),
+        };
+        let path = wal.append(&entry).unwrap();
+
+        let config = IWConfiguration {
+            sinks: vec![IWSinkConfig::Csv { enabled: true }, IWSinkConfig::Json { enabled: true, dir: json_dir.to_str().unwrap().to_string() }],
+            io_retry_attempts: 1,
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+
+        // The CSV folder is missing, the JSON sink succeeds
+        assert!(store_entry(&mut entry, &state).is_err());
+        wal.update(&path, &entry).unwrap();
+
+        std::fs::create_dir_all(&folder).unwrap();
+        replay_wal(&wal, &state).unwrap();
+
+        assert!(wal.pending().unwrap().is_empty());
+        let csv = std::fs::read_to_string(folder.join("all_data_multiple.csv")).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        let json = std::fs::read_to_string(json_dir.join("Nahuelbuta_2022_04_03.json")).unwrap();
+        assert_eq!(json.lines().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_payload_station_id_error() {
+        let result = read_payload_station_id(&[0], &IWStationConfiguration::default(), "test1");
+
+        match result {
+            Err(IWError::DataTooShort(1)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_identity() {
+        let mut buffer = vec![0; HEADER_LENGTH1];
+        buffer[10..25].copy_from_slice(b"300234010123450");
+        buffer.extend_from_slice(&[2, 0, 14, 0, 42]);
+
+        let mut config = IWConfiguration {
+            identity_field: Some(IWIdentityField { offset: 10, length: 15, encoding: IWIdentityEncoding::Ascii }),
+            ..Default::default()
+        };
+        config.identity_to_station.insert("300234010123450".to_string(), "Nahuelbuta".to_string());
+        config.identity_to_station.insert("42".to_string(), "Santa_Gracia".to_string());
+
+        assert_eq!(resolve_identity(&buffer, &config, "test1".to_string()), "Nahuelbuta");
+
+        // Identity inside the payload, after the SBS header
+        config.identity_field = Some(IWIdentityField { offset: HEADER_LENGTH1 + 3, length: 2, encoding: IWIdentityEncoding::Integer });
+        assert_eq!(resolve_identity(&buffer, &config, "test1".to_string()), "Santa_Gracia");
+
+        // Unknown identities and short packets keep the port based name
+        config.identity_field = Some(IWIdentityField { offset: 10, length: 2, encoding: IWIdentityEncoding::Hex });
+        assert_eq!(read_identity(&buffer, config.identity_field.as_ref().unwrap()).unwrap(), "3330");
+        assert_eq!(resolve_identity(&buffer, &config, "test1".to_string()), "test1");
+        assert_eq!(resolve_identity(&buffer[..20], &IWConfiguration {
+            identity_field: Some(IWIdentityField { offset: 10, length: 15, encoding: IWIdentityEncoding::Ascii }),
+            ..config.clone()
+        }, "test1".to_string()), "test1");
+    }
+
+    #[test]
+    fn test_bind_listener_backlog() {
+        let listener = bind_listener(SocketAddr::from(([0, 0, 0, 0], 0)), 32).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Nobody accepts yet, so all of these have to wait in the backlog
+        let streams: Vec<TcpStream> = (0..16).map(|_| TcpStream::connect(("127.0.0.1", port)).unwrap()).collect();
+
+        for _ in streams.iter() {
+            listener.accept().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_bind_listener_dual_stack() {
+        let listener4 = bind_listener(SocketAddr::from(([0, 0, 0, 0], 0)), 32).unwrap();
+        let port = listener4.local_addr().unwrap().port();
+        let listener6 = bind_listener(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 0], port)), 32).unwrap();
+
+        let _stream4 = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (_, socket4) = listener4.accept().unwrap();
+        assert!(socket4.is_ipv4());
+
+        let _stream6 = TcpStream::connect(("::1", port)).unwrap();
+        let (_, socket6) = listener6.accept().unwrap();
+        assert!(socket6.is_ipv6());
+    }
+
+    #[test]
+    fn test_serve_listeners() {
+        let config = IWConfiguration {
+            sinks: vec![IWSinkConfig::WebSocket { enabled: true }],
+            read_timeout_secs: 2,
+            ..Default::default()
+        };
+        let state = Arc::new(IWServerState::new(config).unwrap());
+        let receiver = state.websocket.subscribe();
+
+        let listeners: Vec<TcpListener> = (0..8).map(|_| bind_listener(SocketAddr::from(([0, 0, 0, 0], 0)), 32).unwrap()).collect();
+        let ports: Vec<u16> = listeners.iter().map(|listener| listener.local_addr().unwrap().port()).collect();
+
+        {
+            let state = state.clone();
+            std::thread::spawn(move || serve_listeners(listeners, 3, state));
+        }
+
+        // A stalled station only blocks one worker, the other ports are still served
+        let _stalled = TcpStream::connect(("127.0.0.1", ports[0])).unwrap();
+        sleep(Duration::from_millis(100));
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]));
+
+        for port in ports[1..].iter() {
+            let mut client = TcpStream::connect(("127.0.0.1", *port)).unwrap();
+            client.write_all(&data).unwrap();
+        }
+
+        for _ in ports[1..].iter() {
+            receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reject_skewed_records() {
+        let received_at = NaiveDateTime::parse_from_str("2022-04-03 16:05:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let mut data = vec![
+            weather_data("2022-04-03 15:00:00", 0.0),
+            weather_data("2031-01-01 00:00:00", 0.0),
+            weather_data("2022-04-03 16:00:00", 0.0),
+        ];
+
+        reject_skewed_records(&mut data, received_at, 24 * 3600);
+
+        let timestamps: Vec<&str> = data.iter().map(|entry| entry.timestamp.as_str()).collect();
+        assert_eq!(timestamps, vec!["2022-04-03 15:00:00", "2022-04-03 16:00:00"]);
+    }
+
+    #[test]
+    fn test_skew_station_timezone() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = IWConfiguration {
+            test_mode: true,
+            ..Default::default()
+        };
+        config.stations.insert(port, IWStationConfiguration {
+            timezone: Some("Asia/Kolkata".to_string()),
+            max_skew_reject_secs: Some(3600),
+            ..Default::default()
+        });
+        let state = IWServerState::new(config).unwrap();
+
+        // The logger sends the local time of the station, UTC+05:30
+        let now = Utc::now();
+        let utc_time = now.format("%Y-%m-%d %H:%M:%S").to_string();
+        let station_time = now.with_timezone(&chrono_tz::Asia::Kolkata).format("%Y-%m-%d %H:%M:%S").to_string();
+
+        let packet = encode_weather_packet(&[weather_data(&utc_time, 0.0), weather_data(&station_time, 0.0)]);
+        send_test_connection(&listener, &state, &with_sbs_header(&packet)).unwrap();
+
+        // Read as station time the UTC time stamp is 5.5 hours old
+        let memory_sink = state.memory_sink.lock().unwrap();
+        assert_eq!(memory_sink[0].data, IWStationData::MultipleData(vec![weather_data(&utc_time, 0.0)]));
+
+        let summary = state.health.summary(&state.config, Utc::now().naive_utc());
+        assert!(summary[0].clock_skew_secs.unwrap().abs() < 60);
+    }
+
+    #[test]
+    fn test_disabled_station() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = IWConfiguration::default();
+        config.stations.insert(port, IWStationConfiguration {
+            enabled: false,
+            ..Default::default()
+        });
+        let state = IWServerState::new(config).unwrap();
+
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]);
+        send_test_connection(&listener, &state, &with_sbs_header(&packet)).unwrap();
+
+        assert!(state.health.summary(&state.config, Utc::now().naive_utc()).is_empty());
+    }
+
+    #[test]
+    fn test_sbs_header_length() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = IWConfiguration {
+            test_mode: true,
+            ..Default::default()
+        };
+        config.stations.insert(port, IWStationConfiguration {
+            sbs_header_length: 32,
+            ..Default::default()
+        });
+        let state = IWServerState::new(config).unwrap();
+
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.5)]);
+        send_test_connection(&listener, &state, &[&[0; 32], packet.as_slice()].concat()).unwrap();
+
+        // The default 48 byte header does not fit this transport anymore
+        let result = send_test_connection(&listener, &state, &with_sbs_header(&packet));
+
+        match result {
+            Err(IWError::DataLengthMismatch(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+
+        let result = send_test_connection(&listener, &state, &[0; 20]);
+
+        match result {
+            Err(IWError::DataTooShort(20)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+
+        let memory_sink = state.memory_sink.lock().unwrap();
+        assert_eq!(memory_sink.len(), 1);
+        assert_eq!(memory_sink[0].data, IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]));
+    }
+
+    #[test]
+    fn test_timezone() {
+        assert_eq!(timestamp_to_utc("2022-04-03 13:00:00", chrono_tz::Europe::Berlin), "2022-04-03 11:00:00");
+        assert_eq!(timestamp_to_utc("2022-01-03 13:00:00", chrono_tz::Europe::Berlin), "2022-01-03 12:00:00");
+        assert_eq!(timestamp_to_utc("2022-04-03 13:00:00", chrono_tz::America::Santiago), "2022-04-03 17:00:00");
+        // Does not exist, the clocks jump from 02:00 to 03:00
+        assert_eq!(timestamp_to_utc("2022-03-27 02:30:00", chrono_tz::Europe::Berlin), "2022-03-27 02:30:00");
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = IWConfiguration {
+            test_mode: true,
+            sinks: vec![IWSinkConfig::Csv { enabled: true }, IWSinkConfig::WebSocket { enabled: true }],
+            ..Default::default()
+        };
+        config.stations.insert(port, IWStationConfiguration {
+            timezone: Some("Europe/Berlin".to_string()),
+            ..Default::default()
+        });
+        let state = IWServerState::new(config).unwrap();
+        let receiver = state.websocket.subscribe();
+
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.5)]);
+        send_test_connection(&listener, &state, &with_sbs_header(&packet)).unwrap();
+
+        let memory_sink = state.memory_sink.lock().unwrap();
+        assert_eq!(memory_sink[0].data, IWStationData::MultipleData(vec![weather_data("2022-04-03 11:00:00", 0.5)]));
+
+        // WebSocket clients get the same UTC time stamps as the other sinks
+        assert!(receiver.try_recv().unwrap().contains("\"timestamp\":\"2022-04-03 11:00:00\""));
+
+        let config = IWConfiguration {
+            stations: [(port, IWStationConfiguration { timezone: Some("Mars/Olympus_Mons".to_string()), ..Default::default() })].into_iter().collect(),
+            ..Default::default()
+        };
+        let result = config.validate();
+
+        match result {
+            Err(IWError::InvalidConfig(_)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_multiple_frames() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = IWConfiguration {
+            test_mode: true,
+            metrics_port: Some(0),
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+
+        let frame1 = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.5)]);
+        let frame2 = encode_weather_packet(&[weather_data("2022-04-03 14:00:00", 1.0), weather_data("2022-04-03 15:00:00", 0.0)]);
+        let data = with_sbs_header(&[frame1.as_slice(), frame2.as_slice()].concat());
+
+        let frames = split_frames(&data[SBS_HEADER.len()..], 0).unwrap();
+        assert_eq!(frames, vec![frame1.as_slice(), frame2.as_slice()]);
+
+        // A single frame or frames that do not add up are left to the parser
+        assert!(split_frames(&frame1, 0).is_none());
+        assert!(split_frames(&data[SBS_HEADER.len()..data.len() - 1], 0).is_none());
+
+        send_test_connection(&listener, &state, &data).unwrap();
+
+        let memory_sink = state.memory_sink.lock().unwrap();
+        assert_eq!(memory_sink.len(), 2);
+        assert_eq!(memory_sink[0].data, IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]));
+        assert_eq!(memory_sink[1].data, IWStationData::MultipleData(vec![weather_data("2022-04-03 14:00:00", 1.0), weather_data("2022-04-03 15:00:00", 0.0)]));
+
+        // The SBS header is only counted once
+        let text = state.metrics.render();
+        assert!(text.contains(&format!("iw_bytes_received_total{{station=\"{}\"}} {}\n", port_to_station(port), data.len())));
+        assert!(text.contains(&format!("iw_frames_parsed_total{{station=\"{}\"}} 2\n", port_to_station(port))));
+    }
+
+    #[test]
+    fn test_streaming_connection() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = IWConfiguration {
+            sinks: vec![IWSinkConfig::WebSocket { enabled: true }],
+            ..Default::default()
+        };
+        config.stations.insert(port, IWStationConfiguration {
+            streaming_connection: true,
+            ..Default::default()
+        });
+        let state = IWServerState::new(config).unwrap();
+        let receiver = state.websocket.subscribe();
+
+        let mut data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]));
+        data.extend(with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 14:00:00", 0.0)])));
+        send_test_connection(&listener, &state, &data).unwrap();
+
+        let messages: Vec<String> = receiver.try_iter().collect();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("2022-04-03 13:00:00"));
+        assert!(messages[1].contains("2022-04-03 14:00:00"));
+    }
+
+    #[test]
+    fn test_streaming_idle_timeout() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = IWConfiguration {
+            sinks: vec![IWSinkConfig::WebSocket { enabled: true }],
+            ..Default::default()
+        };
+        config.stations.insert(port, IWStationConfiguration {
+            streaming_connection: true,
+            idle_timeout_secs: 1,
+            ..Default::default()
+        });
+        let state = IWServerState::new(config).unwrap();
+        let receiver = state.websocket.subscribe();
+
+        // The peer sends one message and then stalls without closing the connection
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client.write_all(&with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]))).unwrap();
+
+        let (stream, socket) = listener.accept().unwrap();
+        let start = Instant::now();
+        handle_connection(stream, socket, &state).unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(receiver.try_iter().count(), 1);
+        drop(client);
+    }
+
+    #[test]
+    fn test_accept_log_level() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]));
+
+        for (accept_log_level, level) in [("info", Level::Info), ("warn", Level::Warn)] {
+            let config = IWConfiguration {
+                test_mode: true,
+                accept_log_level: accept_log_level.to_string(),
+                ..Default::default()
+            };
+            let state = IWServerState::new(config).unwrap();
+
+            let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            client.write_all(&data).unwrap();
+            drop(client);
+
+            let (stream, socket) = listener.accept().unwrap();
+            let lines = capture_log(|| process_connection(stream, socket, &state));
+
+            assert!(lines.contains(&(level, "Data was processed successfully".to_string())));
+
+            if level == Level::Warn {
+                // A quiet log for busy servers, a successful connection has no info lines left
+                assert!(lines.iter().all(|(level, _)| *level != Level::Info));
+            }
+        }
+    }
+
+    #[test]
+    fn test_connection_rate_limit() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+
+        let config = IWConfiguration {
+            sinks: vec![IWSinkConfig::WebSocket { enabled: true }],
+            connection_rate_limit: Some(0.001),
+            connection_burst: 2,
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+        let receiver = state.websocket.subscribe();
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]));
+
+        for _ in 0..2 {
+            send_test_connection(&listener, &state, &data).unwrap();
+        }
+
+        let result = send_test_connection(&listener, &state, &data);
+
+        match result {
+            Err(IWError::RateLimited(ip)) => {
+                assert_eq!(ip, IpAddr::from([127, 0, 0, 1]));
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_min_records() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut config = IWConfiguration {
+            sinks: vec![IWSinkConfig::WebSocket { enabled: true }],
+            ..Default::default()
+        };
+        config.stations.insert(port, IWStationConfiguration {
+            min_records: 2,
+            ..Default::default()
+        });
+        let state = IWServerState::new(config).unwrap();
+        let receiver = state.websocket.subscribe();
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]));
+        let result = send_test_connection(&listener, &state, &data);
+
+        match result {
+            Err(IWError::TooFewRecords { got: 1, min: 2 }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+        assert_eq!(receiver.try_iter().count(), 0);
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0), weather_data("2022-04-03 14:00:00", 0.0)]));
+        send_test_connection(&listener, &state, &data).unwrap();
+
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_test_mode() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = IWConfiguration {
+            test_mode: true,
+            wal_dir: Some("/nonexistent/wal".to_string()),
+            failed_dir: "/nonexistent/failed".to_string(),
+            sinks: vec![IWSinkConfig::Binary { enabled: true, dir: "/nonexistent/binary".to_string() }, IWSinkConfig::Csv { enabled: true }],
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.5)]));
+        send_test_connection(&listener, &state, &data).unwrap();
+
+        // Failed data is not dumped either
+        assert!(send_test_connection(&listener, &state, &with_sbs_header(&[1, 0, 14])).is_err());
+
+        let memory_sink = state.memory_sink.lock().unwrap();
+        assert_eq!(memory_sink.len(), 1);
+        assert_eq!(memory_sink[0].station_name, port_to_station(port));
+        assert_eq!(memory_sink[0].data, IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]));
+    }
+
+    #[test]
+    fn test_safe_mode() {
+        let dir = std::env::temp_dir().join(format!("iw_test_safe_mode_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_name = dir.to_str().unwrap().to_string();
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = IWConfiguration {
+            safe_mode: true,
+            wal_dir: Some(format!("{}/wal", dir_name)),
+            failed_dir: format!("{}/failed", dir_name),
+            sinks: vec![
+                IWSinkConfig::Binary { enabled: true, dir: format!("{}/binary", dir_name) },
+                IWSinkConfig::Json { enabled: true, dir: dir_name.clone() },
+                IWSinkConfig::Csv { enabled: true },
+            ],
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.5)]));
+        send_test_connection(&listener, &state, &data).unwrap();
+        assert!(send_test_connection(&listener, &state, &with_sbs_header(&[1, 0, 14])).is_err());
+
+        // The packet was decoded, but nothing ended up on disk or in the memory sink
+        let summary = state.health.summary(&state.config, Utc::now().naive_utc());
+        assert_eq!(summary.len(), 1);
+        assert_eq!(summary[0].station, port_to_station(port));
+        assert!(state.memory_sink.lock().unwrap().is_empty());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        // Nothing is published to the broker either
+        let config = IWConfiguration {
+            ports: Vec::new(),
+            mqtt_host: Some("127.0.0.1".to_string()),
+            ..state.config.clone()
+        };
+        assert!(start_server(&config).unwrap().mqtt.is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_granularity() {
+        let dir = std::env::temp_dir().join(format!("iw_test_file_granularity_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let received = [
+            NaiveDateTime::parse_from_str("2022-04-03 13:59:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+            NaiveDateTime::parse_from_str("2022-04-03 14:01:00", "%Y-%m-%d %H:%M:%S").unwrap(),
+        ];
+
+        for (granularity, expected) in [(IWFileGranularity::Day, vec!["Nahuelbuta_2022_04_03.json"]),
+            (IWFileGranularity::Hour, vec!["Nahuelbuta_2022_04_03_13.json", "Nahuelbuta_2022_04_03_14.json"])] {
+            let granularity_dir = dir.join(format!("{:?}", granularity));
+            std::fs::create_dir_all(&granularity_dir).unwrap();
+
+            for (time, precipitation) in received.iter().zip([0.5, 1.0]) {
+                let data = IWStationData::MultipleData(vec![weather_data(&time.format("%Y-%m-%d %H:00:00").to_string(), precipitation)]);
+                write_json_data(granularity_dir.to_str().unwrap(), &data, "Nahuelbuta", &time.format(granularity.date_format()).to_string(), None).unwrap();
+            }
+
+            let mut files: Vec<String> = std::fs::read_dir(&granularity_dir).unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            files.sort();
+
+            assert_eq!(files, expected);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_sink() {
+        let dir = std::env::temp_dir().join(format!("iw_test_json_sink_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = IWConfiguration {
+            sinks: vec![IWSinkConfig::Json { enabled: true, dir: dir.to_str().unwrap().to_string() }],
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.5), weather_data("2022-04-03 14:00:00", 1.0)]));
+        send_test_connection(&listener, &state, &data).unwrap();
+
+        let file_name = dir.join(format!("{}_{}.json", port_to_station(port), Local::now().format("%Y_%m_%d")));
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(file_name).unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["station"], port_to_station(port));
+        assert_eq!(lines[0]["timestamp"], "2022-04-03 13:00:00");
+        assert_eq!(lines[1]["precipitation"], 1.0);
+        assert_eq!(lines[1]["momsn"], 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let dir = std::env::temp_dir().join(format!("iw_test_read_timeout_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = IWConfiguration {
+            read_timeout_secs: 1,
+            sinks: vec![IWSinkConfig::Binary { enabled: true, dir: dir.to_str().unwrap().to_string() }],
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+
+        // The client stalls without closing the connection
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client.write_all(&SBS_HEADER[..10]).unwrap();
+
+        let (stream, socket) = listener.accept().unwrap();
+        let result = handle_connection(stream, socket, &state);
+
+        match result {
+            Err(IWError::Timeout(10)) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+
+        let file_name = dir.join(format!("{}_{}.dat", port_to_station(port), Local::now().format("%Y_%m_%d")));
+        assert_eq!(std::fs::read(file_name).unwrap(), &SBS_HEADER[..10]);
+        assert_eq!(state.metrics.parse_errors(&port_to_station(port), "Timeout"), 1);
+
+        drop(client);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = IWConfiguration {
+            test_mode: true,
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]));
+
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client.write_all(&data).unwrap();
+        drop(client);
+        let (stream, socket) = listener.accept().unwrap();
+        process_connection(stream, socket, &state);
+
+        assert_eq!(state.memory_sink.lock().unwrap().len(), 1);
+
+        state.request_shutdown();
+
+        // New connections are not processed any more
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client.write_all(&data).unwrap();
+        drop(client);
+        let (stream, socket) = listener.accept().unwrap();
+        process_connection(stream, socket, &state);
+
+        assert_eq!(state.memory_sink.lock().unwrap().len(), 1);
+
+        // Nothing in flight, so this returns immediately
+        assert_eq!(state.wait_for_connections(Duration::from_secs(10)), (0, 0));
+    }
+
+    #[test]
+    fn test_shutdown_drain_timeout() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = IWConfiguration {
+            test_mode: true,
+            ..Default::default()
+        };
+        let state = Arc::new(IWServerState::new(config).unwrap());
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]));
+
+        // Only a part of the packet is sent and the client never hangs up
+        let mut client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        client.write_all(&data[..10]).unwrap();
+        let (stream, socket) = listener.accept().unwrap();
+
+        let handler_state = state.clone();
+        let handler = std::thread::spawn(move || process_connection(stream, socket, &handler_state));
+
+        while state.in_flight.load(std::sync::atomic::Ordering::SeqCst) == 0 {
+            sleep(Duration::from_millis(10));
+        }
+
+        state.request_shutdown();
+
+        assert_eq!(state.wait_for_connections(Duration::from_millis(200)), (0, 1));
+        handler.join().unwrap();
+        assert_eq!(state.memory_sink.lock().unwrap().len(), 0);
+
+        drop(client);
+    }
+
+    #[test]
+    fn test_create_sink_dirs() {
+        let dir = std::env::temp_dir().join(format!("iw_test_create_sink_dirs_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let binary_dir = dir.join("old").join("binary");
+        let json_dir = dir.join("json");
+        let sinks = vec![
+            IWSinkConfig::Csv { enabled: true },
+            IWSinkConfig::Binary { enabled: true, dir: binary_dir.to_str().unwrap().to_string() },
+            IWSinkConfig::Json { enabled: true, dir: json_dir.to_str().unwrap().to_string() },
+        ];
+
+        create_sink_dirs(&sinks).unwrap();
+        assert!(binary_dir.is_dir());
+        assert!(json_dir.is_dir());
+
+        // Already existing is fine
+        create_sink_dirs(&sinks).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_max_packet_bytes() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let config = IWConfiguration {
+            test_mode: true,
+            max_packet_bytes: Some(100),
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+
+        // 48 + 3 + 2 * 28 = 107 bytes
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0), weather_data("2022-04-03 14:00:00", 0.0)]));
+        let result = send_test_connection(&listener, &state, &data);
+
+        match result {
+            Err(IWError::PacketTooLarge { declared: Some(107), max: 100 }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
+
+        assert_eq!(state.metrics.parse_errors(&port_to_station(port), "PacketTooLarge"), 1);
+
+        // 48 + 3 + 28 = 79 bytes
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]));
+        send_test_connection(&listener, &state, &data).unwrap();
+
+        assert_eq!(state.memory_sink.lock().unwrap().len(), 1);
+
+        // Outside of test mode the bytes up to the limit are dumped
+        let dir = std::env::temp_dir().join(format!("iw_test_max_packet_bytes_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let config = IWConfiguration {
+            max_packet_bytes: Some(100),
+            sinks: vec![IWSinkConfig::Binary { enabled: true, dir: dir.to_str().unwrap().to_string() }],
+            ..Default::default()
+        };
+        let dump_state = IWServerState::new(config).unwrap();
 
-    use super::{u32_to_timestamp, u16_to_f64, parse_logger_status1, parse_logger_status2,
-        parse_weather_data_single, parse_weather_data, get_data_length, parse_binary_data,
-        start_server, IWStationData, IWLoggerStatus, IWWeatherData};
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0), weather_data("2022-04-03 14:00:00", 0.0)]));
+        assert!(send_test_connection(&listener, &dump_state, &data).is_err());
 
-    use crate::error::IWError;
-    use crate::config::IWConfiguration;
+        let file_name = dir.join(format!("{}_{}.dat", port_to_station(port), Local::now().format("%Y_%m_%d")));
+        assert_eq!(std::fs::read(file_name).unwrap(), &data[..100]);
+        std::fs::remove_dir_all(&dir).unwrap();
 
-    #[test]
-    fn test_u32_to_timestamp() {
-        let result = u32_to_timestamp(843091200);
-        assert_eq!(result, "2016-09-19 00:00:00");
-    }
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0), weather_data("2022-04-03 14:00:00", 0.0)]));
+        let result = read_framed_message(&mut data.as_slice(), SBS_HEADER.len(), 0, Some(100));
 
-    #[test]
-    fn test_u16_to_f64_1() {
-        assert_eq!(u16_to_f64(17660), 12.76);
+        match result {
+            Err(IWError::PacketTooLarge { declared: Some(107), max: 100 }) => {
+                // OK
+            }
+            _ => {
+                panic!("Expected IWError, got: '{:?}'", result);
+            }
+        }
     }
 
     #[test]
-    fn test_u16_to_f64_2() {
-        assert_eq!(u16_to_f64(17662), 12.78);
-    }
+    fn test_poll_station() {
+        // Mock logger that answers a request with one packet
+        let logger = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = logger.local_addr().unwrap().to_string();
 
-    #[test]
-    fn test_u16_to_f64_3() {
-        assert_eq!(u16_to_f64(17664), 12.80);
-    }
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = logger.accept().unwrap();
 
-    #[test]
-    fn test_u16_to_f64_4() {
-        assert_eq!(u16_to_f64(24576), 0.0);
-    }
+            let mut request = [0; 3];
+            std::io::Read::read_exact(&mut stream, &mut request).unwrap();
+            assert_eq!(request, [0x01, 0xab, 0xff]);
 
-    #[test]
-    fn test_u16_to_f64_5() {
-        assert_eq!(u16_to_f64(962), 962.0);
-    }
+            let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.5)]));
+            stream.write_all(&data).unwrap();
 
-    #[test]
-    fn test_u16_to_f64_6() {
-        assert_eq!(u16_to_f64(25576), 1.0);
-    }
+            // Keep the connection open until the poller is done
+            let mut rest = Vec::new();
+            std::io::Read::read_to_end(&mut stream, &mut rest).unwrap();
+        });
 
-    #[test]
-    fn test_get_data_length1() {
-        assert_eq!(get_data_length(&[0, 0, 0]), 0);
-    }
+        let config = IWConfiguration {
+            test_mode: true,
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+
+        let target = IWPollTarget {
+            address,
+            station: "Polled".to_string(),
+            port: 2100,
+            interval_secs: 3600,
+            request: "01abff".to_string(),
+        };
 
-    #[test]
-    fn test_get_data_length2() {
-        assert_eq!(get_data_length(&[0, 0, 27]), 27);
-    }
+        poll_station(&target, &state).unwrap();
+        server.join().unwrap();
 
-    #[test]
-    fn test_get_data_length3() {
-        assert_eq!(get_data_length(&[0, 1, 0]), 256);
+        let memory_sink = state.memory_sink.lock().unwrap();
+        assert_eq!(memory_sink.len(), 1);
+        assert_eq!(memory_sink[0].station_name, "Polled");
+        assert_eq!(memory_sink[0].folder, "2100_Na");
+        assert_eq!(memory_sink[0].data, IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]));
     }
 
     #[test]
-    fn test_get_data_length4() {
-        assert_eq!(get_data_length(&[0, 1, 4]), 260);
+    fn test_empty_connection() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let state = IWServerState::new(IWConfiguration::default()).unwrap();
+
+        send_test_connection(&listener, &state, &[]).unwrap();
+
+        assert!(state.health.summary(&state.config, Utc::now().naive_utc()).is_empty());
     }
 
     #[test]
-    fn test_parse_logger_status1() {
-        let result = parse_logger_status1(&[0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 99, 240, 99, 220]).unwrap();
-        let expected = IWLoggerStatus {
-            timestamp: "2016-09-19 00:00:00".to_string(),
-            solar_battery: 12.76,
-            lithium_battery: 1.008,
-            wind_diag: 0.988,
-            cf_card: 0,
+    fn test_retry_io1() {
+        let dir = std::env::temp_dir().join(format!("iw_test_retry_io_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let folder = dir.join("2100_Na");
+
+        let config = IWConfiguration {
+            io_retry_delay_ms: 1,
+            ..Default::default()
         };
+        let data = IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]);
+        let mut attempts = 0;
+
+        // The folder is missing in the first attempt
+        retry_io(&config, || {
+            attempts += 1;
+            if attempts == 2 {
+                std::fs::create_dir_all(&folder).unwrap();
+            }
+            export_data(folder.to_str().unwrap(), &data, "Nahuelbuta", None, None, &config)
+        }).unwrap();
 
-        assert_eq!(result, IWStationData::SingleData(expected));
+        assert_eq!(attempts, 2);
+        let csv = std::fs::read_to_string(folder.join("all_data_multiple.csv")).unwrap();
+        assert!(csv.contains("2022-04-03 13:00:00,Nahuelbuta,16.570,"));
+        assert_eq!(csv.lines().count(), 3);
+
+        // A failed first write is cut back to an empty file, the retry still writes the header
+        std::fs::write(folder.join("all_data_battery.csv"), "").unwrap();
+        let status = IWStationData::SingleData(IWLoggerStatus {
+            timestamp: "2022-04-05 00:00:00".to_string(),
+            solar_battery: 12.47,
+            lithium_battery: 3.369,
+            wind_diag: 0.0,
+            cf_card: 0,
+            battery_temperature: None,
+        });
+        export_data(folder.to_str().unwrap(), &status, "Nahuelbuta", None, None, &config).unwrap();
+
+        let csv = std::fs::read_to_string(folder.join("all_data_battery.csv")).unwrap();
+        assert!(csv.starts_with("Timestamp,Station name,Battery voltage,"));
+        assert_eq!(csv.lines().count(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_parse_logger_status2() {
-        let result = parse_logger_status2(&[0, 141, 64, 50, 0, 0, 0, 0, 68, 252, 109, 31, 96, 0, 255, 255, 255, 127]).unwrap();
-        let expected = IWLoggerStatus {
-            timestamp: "2016-09-19 00:00:00".to_string(),
-            solar_battery: 12.76,
-            lithium_battery: 3.359,
-            wind_diag: 0.0,
-            cf_card: 4294967167,
+    fn test_retry_io_connection() {
+        let dir = std::env::temp_dir().join(format!("iw_test_retry_io_connection_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A file where the JSON directory should be, the first write fails
+        let json_dir = dir.join("json");
+        std::fs::write(&json_dir, "").unwrap();
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let config = IWConfiguration {
+            sinks: vec![IWSinkConfig::Json { enabled: true, dir: json_dir.to_str().unwrap().to_string() }],
+            io_retry_attempts: 3,
+            io_retry_delay_ms: 1000,
+            ..Default::default()
         };
+        let state = IWServerState::new(config).unwrap();
 
-        assert_eq!(result, IWStationData::SingleData(expected));
+        let fix_dir = json_dir.clone();
+        let fix = std::thread::spawn(move || {
+            sleep(Duration::from_millis(200));
+            std::fs::remove_file(&fix_dir).unwrap();
+            std::fs::create_dir(&fix_dir).unwrap();
+        });
+
+        let data = with_sbs_header(&encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.5)]));
+        let lines = capture_log(|| send_test_connection(&listener, &state, &data).unwrap());
+        fix.join().unwrap();
+
+        assert!(lines.iter().any(|(_, line)| line.starts_with("IO error in attempt 1 of 3")));
+
+        let files: Vec<_> = std::fs::read_dir(&json_dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let json = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert_eq!(json.lines().count(), 1);
+        assert!(json.contains("\"timestamp\":\"2022-04-03 13:00:00\""));
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_parse_logger_status1_error() {
-        let result = parse_logger_status1(&[0]);
+    fn test_retry_io2() {
+        let config = IWConfiguration {
+            io_retry_delay_ms: 1,
+            ..Default::default()
+        };
+        let mut attempts = 0;
+
+        let result: Result<(), IWError> = retry_io(&config, || {
+            attempts += 1;
+            Err(IWError::InvalidDataHeader)
+        });
 
         match result {
-            Err(IWError::IO(_)) => {
+            Err(IWError::InvalidDataHeader) => {
                 // OK
             }
             _ => {
                 panic!("Expected IWError, got: '{:?}'", result);
             }
         }
-    }
+        assert_eq!(attempts, 1);
 
-    #[test]
-    fn test_parse_logger_status2_error() {
-        let result = parse_logger_status2(&[0]);
+        let result: Result<(), IWError> = retry_io(&config, || {
+            attempts += 1;
+            Err(IWError::IO(std::io::Error::other("stall")))
+        });
 
         match result {
             Err(IWError::IO(_)) => {
@@ -565,34 +4687,58 @@ mod tests {
                 panic!("Expected IWError, got: '{:?}'", result);
             }
         }
+        assert_eq!(attempts, 4);
     }
 
     #[test]
-    fn test_parse_weather_data_single() {
-        let result = parse_weather_data_single(&[0, 141, 64, 50, 0, 0, 0, 0, 69, 222, 35, 229, 92, 249, 96, 77, 70, 100, 97, 103, 98, 238, 43, 190, 99, 232, 3, 194]).unwrap();
-        let expected = IWWeatherData {
-            timestamp: "2016-09-19 00:00:00".to_string(),
-            air_temperature: 15.02,
-            air_relative_humidity: 99.7,
-            solar_radiation: 74.17,
-            soil_water_content: 0.077,
-            soil_temperature: 16.36,
-            wind_speed: 0.359,
-            wind_max: 0.75,
-            wind_direction: 300.6,
-            precipitation: 1.0,
-            air_pressure: 962.0,
+    fn test_decimal_separator() {
+        let dir = std::env::temp_dir().join(format!("iw_test_decimal_separator_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let config = IWConfiguration {
+            csv_delimiter: ';',
+            decimal_separator: ',',
+            ..Default::default()
         };
+        let data = IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]);
+        export_data(dir.to_str().unwrap(), &data, "Nahuelbuta", None, None, &config).unwrap();
 
-        assert_eq!(result, expected);
+        let csv = std::fs::read_to_string(dir.join("all_data_multiple.csv")).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert!(lines[0].starts_with("Timestamp;Station name;Air temperature;"));
+        assert_eq!(lines[2], "2022-04-03 13:00:00;Nahuelbuta;16,570;76,580;820,000;0,048;20,600;6,046;8,270;258,500;0,500;978,000");
+
+        // Every float gets the same number of decimal places
+        let config = IWConfiguration {
+            csv_decimals: 1,
+            ..Default::default()
+        };
+        export_data(dir.to_str().unwrap(), &data, "Nahuelbuta", None, None, &config).unwrap();
+
+        let csv = std::fs::read_to_string(dir.join("all_data_multiple.csv")).unwrap();
+        assert_eq!(csv.lines().nth(3).unwrap(), "2022-04-03 13:00:00,Nahuelbuta,16.6,76.6,820.0,0.0,20.6,6.0,8.3,258.5,0.5,978.0");
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_parse_weather_data_single_error() {
-        let result = parse_weather_data_single(&[0]);
+    fn test_check_ports() {
+        let listener = TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert_eq!(check_ports(IpAddr::from([0, 0, 0, 0]), &[port]), vec![(port, false)]);
+
+        let config = IWConfiguration {
+            ports: vec![port],
+            strict_port_check: true,
+            ..Default::default()
+        };
+        let result = start_server(&config);
 
         match result {
-            Err(IWError::IO(_)) => {
+            Err(IWError::PortsInUse(ref ports)) if ports == &[port] => {
                 // OK
             }
             _ => {
@@ -602,135 +4748,229 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_weather_data() {
-        let result = parse_weather_data(&[
-            208, 252, 170, 60, 0, 0, 0, 0, 70, 121, 93, 234, 3, 52, 96, 48, 72, 12, 119, 158, 67, 59, 42, 25, 96, 0, 3, 210,
-            224, 10, 171, 60, 0, 0, 0, 0, 70, 146, 92, 255, 3, 108, 96, 48, 72, 12, 120, 106, 67, 66, 42, 30, 96, 0, 3, 210]).unwrap();
+    fn test_parse_error_metrics() {
+        let dir = std::env::temp_dir().join(format!("iw_test_parse_error_metrics_{}", std::process::id()));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let station = port_to_station(port);
 
-        let data1 = IWWeatherData {
-            timestamp: "2022-04-03 13:00:00".to_string(),
-            air_temperature: 16.57,
-            air_relative_humidity: 76.58,
-            solar_radiation: 820.0,
-            soil_water_content: 0.048,
-            soil_temperature: 20.6,
-            wind_speed: 6.046,
-            wind_max: 8.27,
-            wind_direction: 258.5,
-            precipitation: 0.0,
-            air_pressure: 978.0,
+        let config = IWConfiguration {
+            sinks: vec![IWSinkConfig::Csv { enabled: false }],
+            failed_dir: dir.to_str().unwrap().to_string(),
+            ..Default::default()
         };
+        let state = IWServerState::new(config).unwrap();
+
+        let packet = encode_weather_packet(&[weather_data("2022-04-03 13:00:00", 0.0)]);
+        let mut mismatch = packet.clone();
+        mismatch[2] = 26;
+        let mut invalid_header = packet.clone();
+        invalid_header[0] = 1;
+        let mut odd_length = packet.clone();
+        odd_length.push(1);
+        odd_length[2] = 29;
+        let mut truncated = packet.clone();
+        truncated.extend([1, 1]);
+        truncated[2] = 30;
+
+        let cases: [(&[u8], &str); 5] = [
+            (&[1, 2, 3], "DataTooShort"),
+            (&with_sbs_header(&mismatch), "DataLengthMismatch"),
+            (&with_sbs_header(&invalid_header), "InvalidDataHeader"),
+            (&with_sbs_header(&odd_length), "OddDataLength"),
+            (&with_sbs_header(&truncated), "AlignmentError"),
+        ];
+
+        for (data, kind) in cases.iter() {
+            let result = send_test_connection(&listener, &state, data);
+
+            match result {
+                Err(ref e) if e.kind() == *kind => {
+                    // OK
+                }
+                _ => {
+                    panic!("Expected IWError::{}, got: '{:?}'", kind, result);
+                }
+            }
 
-        let data2 = IWWeatherData {
-            timestamp: "2022-04-03 14:00:00".to_string(),
-            air_temperature: 16.82,
-            air_relative_humidity: 74.23,
-            solar_radiation: 876.0,
-            soil_water_content: 0.048,
-            soil_temperature: 20.6,
-            wind_speed: 6.25,
-            wind_max: 8.34,
-            wind_direction: 259.0,
-            precipitation: 0.0,
-            air_pressure: 978.0,
-        };
+            assert_eq!(state.metrics.parse_errors(&station, kind), 1);
+        }
 
-        let combined = IWStationData::MultipleData(vec![data1, data2]);
+        assert!(state.metrics.render().contains(&format!("iw_parse_errors_total{{station=\"{}\",kind=\"AlignmentError\"}} 1", station)));
 
-        assert_eq!(result, combined);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_parse_weather_data_error() {
-        let result = parse_weather_data(&[0]);
+    fn test_csv_null_token() {
+        let dir = std::env::temp_dir().join(format!("iw_test_csv_null_token_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
 
-        match result {
-            Err(IWError::IO(_)) => {
-                // OK
-            }
-            _ => {
-                panic!("Expected IWError, got: '{:?}'", result);
-            }
-        }
+        let config = IWConfiguration {
+            nan_policy: IWNanPolicy::Null,
+            csv_null_token: "-9999".to_string(),
+            ..Default::default()
+        };
+
+        // Sentinel words from the logger
+        let record = IWWeatherData {
+            wind_max: f64::INFINITY,
+            ..weather_data("2022-04-03 13:00:00", f64::NAN)
+        };
+        let data = parse_binary_data(&encode_weather_packet(&[record]), &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+        export_data(dir.to_str().unwrap(), &data, "Nahuelbuta", None, None, &config).unwrap();
+
+        let csv = std::fs::read_to_string(dir.join("all_data_multiple.csv")).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines[2], "2022-04-03 13:00:00,Nahuelbuta,16.570,76.580,820.000,0.048,20.600,6.046,-9999,258.500,-9999,978.000");
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_parse_binary_data1() {
-        let result = parse_binary_data(&[2, 0, 14, 128, 151, 171, 60, 0, 0, 0, 0, 68, 209, 109, 116, 96, 0]).unwrap();
+    fn test_schema_version() {
+        let dir = std::env::temp_dir().join(format!("iw_test_schema_version_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
 
-        let data1 = IWLoggerStatus {
-            timestamp: "2022-04-04 00:00:00".to_string(),
-            solar_battery: 12.33,
-            lithium_battery: 3.444,
-            wind_diag: 0.0,
-            cf_card: 0,
+        let config = IWConfiguration {
+            annotate_schema_version: true,
+            ..Default::default()
         };
+        let data = IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]);
+        export_data(dir.to_str().unwrap(), &data, "Nahuelbuta", Some("ieee4-pressure"), None, &config).unwrap();
+        export_data(dir.to_str().unwrap(), &data, "Nahuelbuta", None, None, &config).unwrap();
 
-        let data2 = IWStationData::SingleData(data1);
+        let csv = std::fs::read_to_string(dir.join("all_data_multiple.csv")).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
 
-        assert_eq!(result, data2);
+        assert!(lines[0].ends_with(",Air pressure,Schema version"));
+        assert!(lines[2].ends_with(",978.000,ieee4-pressure"));
+        assert!(lines[3].ends_with(",978.000,fp2-10field"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     #[test]
-    fn test_parse_binary_data2() {
-        let result = parse_binary_data(&[2, 0, 18, 0, 233, 172, 60, 0, 0, 0, 0, 68, 223, 109, 41, 96, 0, 255, 255, 255, 127]).unwrap();
+    fn test_annotate_momsn() {
+        let dir = std::env::temp_dir().join(format!("iw_test_annotate_momsn_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
 
-        let data1 = IWLoggerStatus {
-            timestamp: "2022-04-05 00:00:00".to_string(),
-            solar_battery: 12.47,
-            lithium_battery: 3.369,
-            wind_diag: 0.0,
-            cf_card: 4294967167,
+        let config = IWConfiguration {
+            annotate_schema_version: true,
+            annotate_momsn: true,
+            ..Default::default()
         };
+        let data = IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5)]);
+        export_data(dir.to_str().unwrap(), &data, "Nahuelbuta", None, Some(42), &config).unwrap();
+        export_data(dir.to_str().unwrap(), &data, "Nahuelbuta", None, None, &config).unwrap();
 
-        let data2 = IWStationData::SingleData(data1);
+        let csv = std::fs::read_to_string(dir.join("all_data_multiple.csv")).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
 
-        assert_eq!(result, data2);
+        assert!(lines[0].ends_with(",Air pressure,Schema version,MOMSN"));
+        assert!(lines[1].ends_with(",mbar,String,UInt16"));
+        assert!(lines[2].ends_with(",978.000,fp2-10field,42"));
+        assert!(lines[3].ends_with(",978.000,fp2-10field,"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn file_dedup_csv(file_dedup: IWFileDedup) -> Vec<String> {
+        let dir = std::env::temp_dir().join(format!("iw_test_file_dedup_{:?}_{}", file_dedup, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let config = IWConfiguration {
+            file_dedup,
+            ..Default::default()
+        };
+
+        let data1 = IWStationData::MultipleData(vec![weather_data("2022-04-03 13:00:00", 0.5), weather_data("2022-04-03 14:00:00", 1.0)]);
+        let data2 = IWStationData::MultipleData(vec![weather_data("2022-04-03 14:00:00", 2.0), weather_data("2022-04-03 15:00:00", 3.0)]);
+        export_data(dir.to_str().unwrap(), &data1, "Nahuelbuta", None, None, &config).unwrap();
+        export_data(dir.to_str().unwrap(), &data2, "Nahuelbuta", None, None, &config).unwrap();
+
+        let csv = std::fs::read_to_string(dir.join("all_data_multiple.csv")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Time stamp and precipitation
+        csv.lines().skip(2).map(|line| {
+            let fields: Vec<&str> = line.split(',').collect();
+            format!("{} {}", fields[0], fields[10])
+        }).collect()
     }
 
     #[test]
-    fn test_parse_binary_data3() {
-        let result = parse_binary_data(&[2, 0, 28, 208, 252, 170, 60, 0, 0, 0, 0, 70, 121, 93, 234, 3, 52, 96, 48, 72, 12, 119, 158, 67, 59, 42, 25, 96, 0, 3, 210]).unwrap();
+    fn test_file_dedup() {
+        assert_eq!(file_dedup_csv(IWFileDedup::Off), vec!["2022-04-03 13:00:00 0.500", "2022-04-03 14:00:00 1.000", "2022-04-03 14:00:00 2.000", "2022-04-03 15:00:00 3.000"]);
+        assert_eq!(file_dedup_csv(IWFileDedup::First), vec!["2022-04-03 13:00:00 0.500", "2022-04-03 14:00:00 1.000", "2022-04-03 15:00:00 3.000"]);
+        assert_eq!(file_dedup_csv(IWFileDedup::Last), vec!["2022-04-03 13:00:00 0.500", "2022-04-03 14:00:00 2.000", "2022-04-03 15:00:00 3.000"]);
+    }
 
-        let data1 = IWWeatherData {
-            timestamp: "2022-04-03 13:00:00".to_string(),
-            air_temperature: 16.57,
-            air_relative_humidity: 76.58,
-            solar_radiation: 820.0,
-            soil_water_content: 0.048,
-            soil_temperature: 20.6,
-            wind_speed: 6.046,
-            wind_max: 8.27,
-            wind_direction: 258.5,
-            precipitation: 0.0,
-            air_pressure: 978.0,
+    #[test]
+    fn test_startup_delay() {
+        let port = TcpListener::bind(("0.0.0.0", 0)).unwrap().local_addr().unwrap().port();
+
+        let config = IWConfiguration {
+            ports: vec![port],
+            startup_delay_secs: 1,
+            ..Default::default()
         };
 
-        let data2 = IWStationData::MultipleData(vec![data1]);
+        let start = std::time::Instant::now();
+        start_server(&config).unwrap();
 
-        assert_eq!(result, data2);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_ok());
     }
 
     #[test]
-    fn test_parse_binary_data_error1() {
-        let result = parse_binary_data(&[0]);
+    fn test_decode_hex1() {
+        let hex = "02001cd0fcaa3c00000000 46795dea033460304 80c779e433b2a196000 03d2";
+        let expected = parse_binary_data(&[2, 0, 28, 208, 252, 170, 60, 0, 0, 0, 0, 70, 121, 93, 234, 3, 52, 96, 48, 72, 12, 119, 158, 67, 59, 42, 25, 96, 0, 3, 210], &IWStationConfiguration::default(), default_epoch_base()).unwrap();
+
+        assert_eq!(decode_hex(hex).unwrap(), expected);
+
+        // Same packet with SBS header
+        let hex = format!("{}{}", "00".repeat(48), hex);
+        assert_eq!(decode_hex(&hex).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_trace_decode() {
+        let hex = format!("{}{}", "00".repeat(48), "02001cd0fcaa3c0000000046795dea03346030480c779e433b2a19600003d2");
+        let trace = trace_decode(&hex).unwrap();
+
+        assert!(trace.contains(&"Raw bytes (79): [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 28, 208, 252, 170, 60, 0, 0, 0, 0, 70, 121, 93, 234, 3, 52, 96, 48, 72, 12, 119, 158, 67, 59, 42, 25, 96, 0, 3, 210]".to_string()));
+        assert!(trace.contains(&"SBS header:".to_string()));
+        assert!(trace.contains(&"Frame header: '2', declared length: '28', actual length: '28'".to_string()));
+        assert!(trace.contains(&"  timestamp: raw: '1017838800', decoded: '2022-04-03 13:00:00'".to_string()));
+        assert!(trace.contains(&"  air_temperature: raw: '18041' (0x4679), decoded: '16.57'".to_string()));
+        assert!(trace.contains(&"  air_pressure: raw: '978' (0x03d2), decoded: '978'".to_string()));
+        assert!(trace.last().unwrap().starts_with("Result: MultipleData([IWWeatherData { timestamp: \"2022-04-03 13:00:00\""));
+    }
+
+    #[test]
+    fn test_decode_hex2() {
+        let result = decode_hex("02001");
 
         match result {
-            Err(IWError::DataTooShort(1)) => {
+            Err(IWError::InvalidHex(_)) => {
                 // OK
             }
             _ => {
                 panic!("Expected IWError, got: '{:?}'", result);
             }
         }
-    }
 
-    #[test]
-    fn test_parse_binary_data_error2() {
-        let result = parse_binary_data(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let result = decode_hex("02001x");
 
         match result {
-            Err(IWError::DataLengthMismatch(0)) => {
+            Err(IWError::InvalidHex(_)) => {
                 // OK
             }
             _ => {
@@ -740,8 +4980,26 @@ mod tests {
     }
 
     #[test]
-    fn test_parse_binary_data_error3() {
-        let result = parse_binary_data(&[0, 0, 14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    fn test_hex_dump() {
+        let result = hex_dump(&[2, 0, 14, 128, 151, 171, 60, 0, 0, 0, 0, 68, 209, 109, 116, 96, 0]);
+        let expected = "00000000  02 00 0e 80 97 ab 3c 00 00 00 00 44 d1 6d 74 60 |......<....D.mt`|\n\
+                        00000010  00                                              |.|\n";
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_failed_data_hex_dump() {
+        let dir = std::env::temp_dir().join(format!("iw_test_failed_data_{}", std::process::id()));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+
+        let config = IWConfiguration {
+            failed_dir: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let state = IWServerState::new(config).unwrap();
+
+        let result = send_test_connection(&listener, &state, &with_sbs_header(&[0, 0, 14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
 
         match result {
             Err(IWError::InvalidDataHeader) => {
@@ -751,6 +5009,16 @@ mod tests {
                 panic!("Expected IWError, got: '{:?}'", result);
             }
         }
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].file_name().unwrap().to_str().unwrap().starts_with("unknown_"));
+
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert_eq!(content.lines().count(), 5);
+        assert!(content.ends_with("00000040  00                                              |.|\n"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
     fn send_data_to_server(data: &[u8]) {
@@ -758,33 +5026,23 @@ mod tests {
         sleep(Duration::from_secs(3));
 
         let mut stream = TcpStream::connect("localhost:2100").unwrap();
-        stream.write(data).unwrap();
+        stream.write_all(data).unwrap();
     }
 
     #[test]
     fn test_start_sever1() {
-        let log_config = ConfigBuilder::new()
-            .set_time_to_local(true)
-            .set_time_format_str("%Y.%m.%d - %H:%M:%S")
-            .build();
-
-        let _ = WriteLogger::init(
-            LevelFilter::Debug,
-            log_config,
-            File::options().append(true).create(true).open("test_iridium_weatherstation.log").unwrap()
-        );
+        init_test_logger();
 
         let config = IWConfiguration {
             ports: vec![2100, 2101, 2103, 2104],
             alive_message_intervall: 0,
+            ..Default::default()
         };
 
-        start_server(&config);
+        start_server(&config).unwrap();
 
         send_data_to_server(&[0]);
 
-        const SBS_HEADER: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
-
         let data1 = &[0];
 
         send_data_to_server(&[SBS_HEADER, data1].concat());
@@ -0,0 +1,83 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+
+#[derive(Debug)]
+struct IWTokenBucket {
+    tokens: f64,
+    last_update: Instant,
+}
+
+// Token bucket per source IP: every connection takes one token, tokens refill with rate_per_sec up to burst
+#[derive(Debug)]
+pub struct IWRateLimiter {
+    rate_per_sec: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, IWTokenBucket>>,
+}
+
+impl IWRateLimiter {
+    pub fn new(rate_per_sec: f64, burst: u32) -> Self {
+        IWRateLimiter {
+            rate_per_sec,
+            burst: burst as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn allow(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(ip).or_insert(IWTokenBucket {
+            tokens: self.burst,
+            last_update: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.last_update).as_secs_f64();
+        bucket.tokens = (bucket.tokens + (elapsed * self.rate_per_sec)).min(self.burst);
+        bucket.last_update = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    use super::IWRateLimiter;
+
+    #[test]
+    fn test_allow1() {
+        let limiter = IWRateLimiter::new(1.0, 2);
+        let ip1: IpAddr = "10.0.0.1".parse().unwrap();
+        let ip2: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.allow(ip1, now));
+        assert!(limiter.allow(ip1, now));
+        assert!(!limiter.allow(ip1, now));
+
+        // Other peers have their own bucket
+        assert!(limiter.allow(ip2, now));
+
+        // One token is back after a second
+        assert!(limiter.allow(ip1, now + Duration::from_secs(1)));
+        assert!(!limiter.allow(ip1, now + Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,164 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::thread::{spawn, sleep};
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use log::{debug, error};
+
+use crate::error::IWError;
+use crate::metrics::IWMetrics;
+use crate::process_data::IWServerState;
+
+
+// StatsD metric names are dot separated, so the station name must not contain any dots
+fn statsd_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+// StatsD counters are increments, so only the difference to the last push is sent.
+// last_sent is keyed by the StatsD name of the counter.
+fn statsd_lines(metrics: &IWMetrics, last_sent: &mut BTreeMap<String, u64>, now: NaiveDateTime) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut counts = BTreeMap::new();
+
+    for ((station, kind), count) in metrics.parse_error_counts() {
+        counts.insert(format!("iw.parse_errors.{}.{}", statsd_name(&station), kind), count);
+    }
+
+    // iw_bytes_received_total becomes iw.bytes_received.<station>
+    for ((station, name), count) in metrics.ingest_counts() {
+        let name = name.trim_start_matches("iw_").trim_end_matches("_total");
+        counts.insert(format!("iw.{}.{}", name, statsd_name(&station)), count);
+    }
+
+    // Expired labels start from zero again when they come back
+    last_sent.retain(|key, _| counts.contains_key(key));
+
+    for (key, count) in counts {
+        let previous = last_sent.get(&key).copied().unwrap_or(0);
+
+        if count > previous {
+            result.push(format!("{}:{}|c", key, count - previous));
+        }
+
+        last_sent.insert(key, count);
+    }
+
+    // Gauges are sent every time, a leading minus would be read as a decrement
+    for (station, age) in metrics.last_record_ages(now) {
+        result.push(format!("iw.last_record_age_seconds.{}:{}|g", statsd_name(&station), age.max(0)));
+    }
+
+    result
+}
+
+fn send_statsd(socket: &UdpSocket, address: &str, metrics: &IWMetrics, last_sent: &mut BTreeMap<String, u64>) -> Result<usize, IWError> {
+    let lines = statsd_lines(metrics, last_sent, Utc::now().naive_utc());
+
+    if !lines.is_empty() {
+        socket.send_to(lines.join("\n").as_bytes(), address)?;
+    }
+
+    Ok(lines.len())
+}
+
+pub fn start_statsd_emitter(address: String, interval_secs: u64, state: Arc<IWServerState>) -> Result<(), IWError> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    debug!("Push metrics to StatsD: '{}'", address);
+
+    spawn(move || {
+        let mut last_sent = BTreeMap::new();
+
+        loop {
+            sleep(Duration::from_secs(interval_secs));
+
+            if let Err(e) = send_statsd(&socket, &address, &state.metrics, &mut last_sent) {
+                error!("Could not send metrics to StatsD: '{}'", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    use chrono::NaiveDateTime;
+
+    use super::{send_statsd, statsd_lines};
+
+    use crate::error::IWError;
+    use crate::metrics::IWMetrics;
+
+    #[test]
+    fn test_send_statsd() {
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let address = server.local_addr().unwrap().to_string();
+
+        let client = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let metrics = IWMetrics::new(None, false);
+        let mut last_sent = BTreeMap::new();
+
+        metrics.inc_parse_error("Los Patos", &IWError::InvalidDataHeader);
+        metrics.inc_parse_error("Los Patos", &IWError::InvalidDataHeader);
+        metrics.inc_parse_error("Nahuelbuta", &IWError::DataTooShort(3));
+
+        assert_eq!(send_statsd(&client, &address, &metrics, &mut last_sent).unwrap(), 2);
+
+        let mut buffer = [0; 1024];
+        let len = server.recv(&mut buffer).unwrap();
+        assert_eq!(std::str::from_utf8(&buffer[..len]).unwrap(),
+            "iw.parse_errors.Los_Patos.InvalidDataHeader:2|c\niw.parse_errors.Nahuelbuta.DataTooShort:1|c");
+
+        // Nothing new, nothing sent
+        assert_eq!(send_statsd(&client, &address, &metrics, &mut last_sent).unwrap(), 0);
+
+        metrics.inc_parse_error("Nahuelbuta", &IWError::DataTooShort(3));
+
+        assert_eq!(send_statsd(&client, &address, &metrics, &mut last_sent).unwrap(), 1);
+
+        let len = server.recv(&mut buffer).unwrap();
+        assert_eq!(std::str::from_utf8(&buffer[..len]).unwrap(), "iw.parse_errors.Nahuelbuta.DataTooShort:1|c");
+    }
+
+    #[test]
+    fn test_statsd_ingest() {
+        let metrics = IWMetrics::new(None, true);
+        let mut last_sent = BTreeMap::new();
+        let now = NaiveDateTime::parse_from_str("2022-04-03 14:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        metrics.add_bytes_received("Los Patos", 79);
+        metrics.inc_frames_parsed("Los Patos");
+        metrics.set_last_record("Los Patos", NaiveDateTime::parse_from_str("2022-04-03 13:00:00", "%Y-%m-%d %H:%M:%S").unwrap());
+
+        assert_eq!(statsd_lines(&metrics, &mut last_sent, now), vec![
+            "iw.bytes_received.Los_Patos:79|c",
+            "iw.frames_parsed.Los_Patos:1|c",
+            "iw.last_record_age_seconds.Los_Patos:3600|g",
+        ]);
+
+        metrics.add_bytes_received("Los Patos", 21);
+        metrics.inc_db_insert_failure("Los Patos");
+
+        assert_eq!(statsd_lines(&metrics, &mut last_sent, now), vec![
+            "iw.bytes_received.Los_Patos:21|c",
+            "iw.db_insert_failures.Los_Patos:1|c",
+            "iw.last_record_age_seconds.Los_Patos:3600|g",
+        ]);
+    }
+}
@@ -0,0 +1,154 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::debug;
+use chrono::Utc;
+use serde_derive::{Serialize, Deserialize};
+
+use crate::error::IWError;
+use crate::process_data::{IWStationData, IWSbsHeader};
+
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct IWWalEntry {
+    pub station_name: String,
+    pub folder: String,
+    pub data: IWStationData,
+    #[serde(default)]
+    pub schema_version: Option<String>,
+    // Date part of the file names, the JSON sink uses the receive time
+    #[serde(default)]
+    pub file_date: Option<String>,
+    #[serde(default)]
+    pub sbs_header: Option<IWSbsHeader>,
+    // Sinks that already have the data, a replay skips them
+    #[serde(default)]
+    pub done: Vec<String>,
+}
+
+// Names of the sinks in IWWalEntry::done, the JSON sink is followed by its directory
+pub const WAL_SINK_MQTT: &str = "mqtt";
+pub const WAL_SINK_JSON: &str = "json";
+pub const WAL_SINK_DATABASE: &str = "database";
+pub const WAL_SINK_CSV: &str = "csv";
+
+impl IWWalEntry {
+    pub fn is_done(&self, sink: &str) -> bool {
+        self.done.iter().any(|done| done == sink)
+    }
+
+    pub fn set_done(&mut self, sink: &str) {
+        if !self.is_done(sink) {
+            self.done.push(sink.to_string());
+        }
+    }
+}
+
+fn write_entry(file: &mut File, entry: &IWWalEntry, fsync: bool) -> Result<(), IWError> {
+    serde_json::to_writer(&mut *file, entry)?;
+    file.flush()?;
+
+    if fsync {
+        file.sync_all()?;
+    }
+
+    Ok(())
+}
+
+// Every entry is written to its own file, so confirming an entry is just removing that file.
+#[derive(Debug)]
+pub struct IWWal {
+    dir: PathBuf,
+    fsync: bool,
+    counter: AtomicU64,
+}
+
+impl IWWal {
+    pub fn new<P: AsRef<Path>>(dir: P, fsync: bool) -> Result<Self, IWError> {
+        fs::create_dir_all(&dir)?;
+
+        Ok(IWWal {
+            dir: dir.as_ref().to_path_buf(),
+            fsync,
+            counter: AtomicU64::new(0),
+        })
+    }
+
+    pub fn append(&self, entry: &IWWalEntry) -> Result<PathBuf, IWError> {
+        let counter = self.counter.fetch_add(1, Ordering::SeqCst);
+        // UTC, the local clock goes back at the end of daylight saving time and breaks the order
+        let file_name = format!("{}_{:06}.json", Utc::now().format("%Y%m%d_%H%M%S_%f"), counter);
+        let path = self.dir.join(file_name);
+
+        let mut file = File::options().create_new(true).write(true).open(&path)?;
+
+        // A partial entry could not be replayed, a retry writes a new file
+        if let Err(e) = write_entry(&mut file, entry, self.fsync) {
+            let _ = fs::remove_file(&path);
+            return Err(e)
+        }
+
+        debug!("WAL entry written: '{}'", path.display());
+
+        Ok(path)
+    }
+
+    // Replaces the entry, the rename keeps the old one if writing fails
+    pub fn update(&self, path: &Path, entry: &IWWalEntry) -> Result<(), IWError> {
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        write_entry(&mut file, entry, self.fsync)?;
+        fs::rename(&tmp_path, path)?;
+
+        debug!("WAL entry updated: '{}'", path.display());
+
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<(), IWError> {
+        fs::remove_file(path)?;
+        debug!("WAL entry removed: '{}'", path.display());
+
+        Ok(())
+    }
+
+    // Path and size of every entry, the oldest first
+    pub fn files(&self) -> Result<Vec<(PathBuf, u64)>, IWError> {
+        let mut result = Vec::new();
+
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let dir_entry = dir_entry?;
+            let path = dir_entry.path();
+
+            if path.extension().is_some_and(|extension| extension == "json") {
+                result.push((path, dir_entry.metadata()?.len()));
+            }
+        }
+
+        // File names start with the time stamp, so this replays in the original order
+        result.sort();
+
+        Ok(result)
+    }
+
+    pub fn pending(&self) -> Result<Vec<(PathBuf, IWWalEntry)>, IWError> {
+        let mut result = Vec::new();
+
+        for (path, _) in self.files()? {
+            let file = File::open(&path)?;
+            let entry = serde_json::from_reader(file)?;
+            result.push((path, entry));
+        }
+
+        Ok(result)
+    }
+}
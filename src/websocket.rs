@@ -0,0 +1,75 @@
+// iridium_weatherstation V0.3 (2022.04.05), written by Willi Kappler
+//
+// Licensed under the MIT License
+//
+// A simple data processing tool written in Rust for one of the campbell iridium weather stations
+//
+
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+use log::{debug, error};
+use serde_derive::Serialize;
+use tungstenite::{WebSocket, Message};
+use tungstenite::protocol::Role;
+
+use crate::process_data::IWWeatherData;
+
+
+#[derive(Serialize)]
+struct IWWebSocketMessage<'a> {
+    station: &'a str,
+    data: &'a IWWeatherData,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schema_version: Option<&'a str>,
+}
+
+#[derive(Default, Debug)]
+pub struct IWWebSocketClients {
+    senders: Mutex<Vec<Sender<String>>>,
+}
+
+impl IWWebSocketClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (sender, receiver) = channel();
+        self.senders.lock().unwrap().push(sender);
+        receiver
+    }
+
+    pub fn broadcast_weather(&self, station: &str, data: &[IWWeatherData], schema_version: Option<&str>) {
+        let mut senders = self.senders.lock().unwrap();
+
+        if senders.is_empty() {
+            return
+        }
+
+        for entry in data {
+            let message = match serde_json::to_string(&IWWebSocketMessage { station, data: entry, schema_version }) {
+                Ok(message) => message,
+                Err(e) => {
+                    error!("Could not serialize data for WebSocket clients: '{}'", e);
+                    return
+                }
+            };
+
+            // The receiver is gone when the client has disconnected
+            senders.retain(|sender| sender.send(message.clone()).is_ok());
+        }
+    }
+}
+
+pub fn serve_websocket(stream: TcpStream, receiver: Receiver<String>) {
+    let mut websocket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+    for message in receiver {
+        if let Err(e) = websocket.send(Message::Text(message)) {
+            debug!("WebSocket client disconnected: '{}'", e);
+            break
+        }
+    }
+}